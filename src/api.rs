@@ -0,0 +1,298 @@
+//! An optional HTTP API on localhost, so editor plugins and scripts can drive the stopwatch.
+//! Every request needs an `Authorization: Bearer <token>` header.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Local, NaiveDate};
+use tiny_http::{Header, Method, Request, Response};
+use tracing::{info, warn};
+
+use crate::database::{Block, Database, Tag};
+use crate::history::History;
+use crate::profiles::Profile;
+use crate::settings::ApiSettings;
+
+/// A block, as the API returns it
+#[derive(serde::Serialize)]
+struct BlockJson {
+    id: usize,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+    seconds: i64,
+    running: bool,
+    /// Path of the tag, like `ACME / Website`
+    tag: Option<String>,
+}
+
+impl BlockJson {
+    fn new(block: &Block, tags: &[Tag]) -> Self {
+        Self {
+            id: block.id(),
+            start: block.start,
+            end: block.end,
+            seconds: block.duration().num_seconds(),
+            running: block.running,
+            tag: block.tag.as_ref().map(|t| t.path(tags)),
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct TagJson {
+    id: usize,
+    name: String,
+    path: String,
+    archived: bool,
+}
+
+#[derive(serde::Serialize)]
+struct StatusJson {
+    running: Option<BlockJson>,
+    today_seconds: i64,
+}
+
+#[derive(serde::Deserialize, Default)]
+#[serde(default)]
+struct StartJson {
+    /// Name or path of the tag for the new block
+    tag: Option<String>,
+}
+
+/// A failed request, answered with `status` and a json error message
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl ToString) -> Self {
+        Self {
+            status,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::new(500, format!("{e:#}"))
+    }
+}
+
+/// A random token for the settings, so users don't have to make one up
+pub fn new_token() -> String {
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_i64(Local::now().timestamp_nanos_opt().unwrap_or_default());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+pub struct Server {
+    http: tiny_http::Server,
+    token: String,
+}
+
+impl Server {
+    /// Listens on localhost. Port 0 picks any free port.
+    pub fn bind(port: u16, token: &str) -> anyhow::Result<Self> {
+        if token.is_empty() {
+            bail!("The API needs a token");
+        }
+        let http = tiny_http::Server::http(("127.0.0.1", port))
+            .map_err(|e| anyhow!("Failed to listen on port {port}: {e}"))?;
+        Ok(Self {
+            http,
+            token: token.to_string(),
+        })
+    }
+
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Answers requests until the process exits or the server is unblocked. `changed` is called
+    /// after the stopwatch is started or stopped, e.g. to redraw the window.
+    pub fn run(&self, database: &Database, changed: impl Fn()) {
+        for mut request in self.http.incoming_requests() {
+            let (status, body) = match self.respond(database, &mut request) {
+                Ok(body) => (200, body),
+                Err(e) => (
+                    e.status,
+                    serde_json::json!({ "error": e.message }).to_string(),
+                ),
+            };
+            if *request.method() == Method::Post && status == 200 {
+                changed();
+            }
+
+            let content_type = Header::from_bytes("Content-Type", "application/json")
+                .expect("The header is valid");
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type);
+            if let Err(e) = request.respond(response) {
+                warn!("Failed to answer API request: {e}");
+            }
+        }
+    }
+
+    fn respond(&self, database: &Database, request: &mut Request) -> Result<String, ApiError> {
+        let expected = format!("Bearer {}", self.token);
+        let authorized = (request.headers().iter())
+            .any(|h| h.field.equiv("Authorization") && h.value.as_str() == expected);
+        if !authorized {
+            return Err(ApiError::new(401, "Missing or wrong token"));
+        }
+
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        match (request.method(), path) {
+            (Method::Get, "/status") => status(database),
+            (Method::Post, "/start") => {
+                let mut body = String::new();
+                (request.as_reader().read_to_string(&mut body))
+                    .map_err(|e| ApiError::new(400, e))?;
+                let start: StartJson = if body.trim().is_empty() {
+                    StartJson::default()
+                } else {
+                    serde_json::from_str(&body).map_err(|e| ApiError::new(400, e))?
+                };
+                self::start(database, start.tag.as_deref())
+            }
+            (Method::Post, "/stop") => {
+                database.stopwatch().stop()?;
+                status(database)
+            }
+            (Method::Get, "/blocks") => blocks(database, query),
+            (Method::Get, "/tags") => tags(database),
+            _ => Err(ApiError::new(404, format!("No endpoint {path}"))),
+        }
+    }
+}
+
+/// Serves the API on its own thread, until this is dropped
+pub struct Handle {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.server.http.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Serves the API for `profile`, or logs why it can't
+pub fn spawn(
+    profile: Profile,
+    settings: &ApiSettings,
+    changed: impl Fn() + Send + 'static,
+) -> Option<Handle> {
+    // a server that was just dropped lets go of its port shortly after
+    let mut bound = Server::bind(settings.port, &settings.token);
+    for _ in 0..5 {
+        if bound.is_ok() {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        bound = Server::bind(settings.port, &settings.token);
+    }
+    let server = match bound {
+        Ok(server) => Arc::new(server),
+        Err(e) => {
+            warn!("Failed to serve the API: {e:#}");
+            return None;
+        }
+    };
+    let thread = std::thread::spawn({
+        let server = server.clone();
+        move || {
+            // connections can't be shared between threads, so the server has its own
+            match Database::new(&profile) {
+                Ok(database) => {
+                    info!("Serving the API for profile {profile}");
+                    server.run(&database, changed);
+                }
+                Err(e) => warn!("Failed to serve the API: {e:#}"),
+            }
+        }
+    });
+    Some(Handle {
+        server,
+        thread: Some(thread),
+    })
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<String, ApiError> {
+    serde_json::to_string(value).map_err(|e| ApiError::new(500, e))
+}
+
+fn status(database: &Database) -> Result<String, ApiError> {
+    database.stopwatch().update()?;
+    let tags = database.tags().all()?;
+    let running = database.blocks().current()?;
+    to_json(&StatusJson {
+        running: running.map(|b| BlockJson::new(&b, &tags)),
+        today_seconds: History::new(database)
+            .total_time(Local::now())
+            .num_seconds(),
+    })
+}
+
+fn start(database: &Database, tag: Option<&str>) -> Result<String, ApiError> {
+    if database.blocks().current()?.is_some() {
+        return Err(ApiError::new(409, "A block is already running"));
+    }
+    let tag = tag.map(|name| database.tags().find(name));
+    let tag = tag.transpose().map_err(|e| ApiError::new(404, e))?;
+    database.stopwatch().start(tag)?;
+    status(database)
+}
+
+/// Blocks from the days between the `from` and `to` query parameters, as `YYYY-MM-DD`.
+/// Either defaults to today.
+fn blocks(database: &Database, query: &str) -> Result<String, ApiError> {
+    let today = Local::now().date_naive();
+    let (mut from, mut to) = (today, today);
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let date = || {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|e| ApiError::new(400, format!("{key} must be YYYY-MM-DD: {e}")))
+        };
+        match key {
+            "from" => from = date()?,
+            "to" => to = date()?,
+            _ => (),
+        }
+    }
+
+    let tags = database.tags().all()?;
+    let blocks = History::new(database).blocks_in_days(from, to)?;
+    let blocks: Vec<BlockJson> = blocks.iter().map(|b| BlockJson::new(b, &tags)).collect();
+    to_json(&blocks)
+}
+
+fn tags(database: &Database) -> Result<String, ApiError> {
+    let tags = database.tags().all()?;
+    let tags: Vec<TagJson> = tags
+        .iter()
+        .map(|tag| TagJson {
+            id: tag.id(),
+            name: tag.name.clone(),
+            path: tag.path(&tags),
+            archived: tag.is_archived(&tags),
+        })
+        .collect();
+    to_json(&tags)
+}
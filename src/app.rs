@@ -1,36 +1,110 @@
-use std::thread;
+use std::path::Path;
 
-use chrono::Duration;
+use anyhow::bail;
+use chrono::{DateTime, Duration, Local};
 use eframe::egui;
-use tracing::warn;
+use tracing::{info, warn};
 
-use crate::{Args, Commands};
-use crate::database::Database;
-use crate::gui::{draw_stopwatch, GuiMessage, GuiState};
+use crate::api;
+use crate::cli;
+use crate::database::{Block, BlockEdit, Database};
+use crate::export;
+use crate::git::{self, Commit};
+use crate::gui::{
+    bulk_edit_outcome_id, draw_block_history, draw_commits, draw_stopwatch, GuiMessage, GuiState,
+    StopwatchGuiData,
+};
 use crate::history::History;
+use crate::instance::{Listener, Request};
+use crate::pomodoro::{Phase, Pomodoro};
+use crate::profiles::{self, Profile};
+use crate::scheduler::Scheduler;
 use crate::settings::Settings;
+use crate::undo::{Edit, UndoStack};
+use crate::{Args, Commands};
 
+/// Directory in the data directory that exports are saved to
+const EXPORT_DIR: &str = "exports";
+/// Settings used to be kept in eframe storage, they are moved to the database on first run
 const SETTINGS_KEY: &str = "Settings";
 const STATE_KEY: &str = "State";
+/// How long a toast stays up, e.g. offering to undo a deleted block or tag
+const TOAST_SECONDS: i64 = 8;
 
 pub struct TimeKeeperApp {
     state: GuiState,
     settings: Settings,
     database: Database,
+    profile: Profile,
+    pomodoro: Option<Pomodoro>,
+    stopwatch: StopwatchGuiData,
+    undo: UndoStack,
+    toast: Option<Toast>,
+    /// Block whose history is shown in a popup
+    block_history: Option<usize>,
+    /// Commits made while that block ran
+    block_commits: Vec<Commit>,
+    /// Receives commands from later invocations of the app
+    listener: Option<Listener>,
+    /// Wakes the window every second and fires scheduled rules
+    scheduler: Scheduler,
+    /// Serves the API for the current profile, if it is enabled
+    api: Option<api::Handle>,
+    /// Lets other threads wake the window
+    ctx: egui::Context,
+}
+
+/// A short lived notice, which may offer to undo the last edit
+struct Toast {
+    text: String,
+    undo: bool,
+    shown: DateTime<Local>,
 }
 
 impl eframe::App for TimeKeeperApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.database.stopwatch().update().unwrap();
+        self.handle_forwarded(ctx);
+        self.record_scheduled();
+        self.update_pomodoro(ctx);
+        self.stop_at_planned(ctx);
         let current = self.database.blocks().current().unwrap();
+        let tags = self.database.tags().all().unwrap();
 
         egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
             self.state.draw_tabs(ui);
         });
 
+        // leave ctrl+z to text fields while they are being edited
+        if !ctx.wants_keyboard_input() {
+            let redo = egui::KeyboardShortcut::new(
+                egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+                egui::Key::Z,
+            );
+            let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+            // check redo first, as undo would also match with shift held
+            if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+                self.handle_message(GuiMessage::Redo);
+            } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+                self.handle_message(GuiMessage::Undo);
+            }
+        }
+
+        let message = self.draw_toast(ctx);
+        self.handle_message(message);
+        self.draw_block_history(ctx);
+
         let message = egui::TopBottomPanel::bottom("stopwatch")
             .show(ctx, |ui| {
-                draw_stopwatch(current, History::new(&self.database), &self.settings, ui)
+                draw_stopwatch(
+                    current,
+                    self.pomodoro.as_ref(),
+                    &mut self.stopwatch,
+                    History::new(&self.database),
+                    &tags,
+                    &mut self.settings,
+                    ui,
+                )
             })
             .inner;
         self.handle_message(message);
@@ -39,8 +113,12 @@ impl eframe::App for TimeKeeperApp {
             .show(ctx, |ui| {
                 egui::ScrollArea::vertical()
                     .show(ui, |ui| {
-                        self.state
-                            .draw_screen(&self.database, &mut self.settings, ui)
+                        self.state.draw_screen(
+                            &self.database,
+                            &mut self.settings,
+                            &self.profile,
+                            ui,
+                        )
                     })
                     .inner
             })
@@ -51,22 +129,24 @@ impl eframe::App for TimeKeeperApp {
 
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
         storage.set_string(STATE_KEY, serde_json::to_string(&self.state).unwrap());
-        storage.set_string(SETTINGS_KEY, self.settings.serialize());
+        self.settings.save(&self.database);
     }
 }
 
 impl TimeKeeperApp {
     pub fn new(cc: &eframe::CreationContext<'_>, args: Args) -> Self {
-        let Args { command } = args;
+        let profile = args.profile();
+        let Args { command, .. } = args;
 
         // initialize internal structures
-        let settings: Settings;
         let state: GuiState;
-        let database = Database::new().unwrap();
+        let database = Database::new(&profile).unwrap();
 
         // load previous state if any
         if let Some(storage) = cc.storage {
-            settings = Settings::deserailize(storage.get_string(SETTINGS_KEY));
+            if profile == Profile::default() {
+                import_settings(storage, &database);
+            }
             if let Some(value) = storage
                 .get_string(STATE_KEY)
                 .and_then(|s| serde_json::from_str(&s).ok())
@@ -77,41 +157,214 @@ impl TimeKeeperApp {
                 state = GuiState::default();
             }
         } else {
-            settings = Settings::default();
             state = GuiState::default();
         }
+        let settings = Settings::load(&database);
 
-        // handle startup commands
-        match command {
-            Some(Commands::Start) => {
-                database.stopwatch().start(None).unwrap();
-            },
-            None => (),
-        }
+        let scheduler = Scheduler::spawn(profile.clone(), cc.egui_ctx.clone());
+        let listener = listen(&profile, &cc.egui_ctx);
+        let api = spawn_api(&profile, &settings, &cc.egui_ctx);
 
-        //start update thread
-        let ctx = cc.egui_ctx.clone();
-        thread::spawn(|| bg_timer(ctx));
-
-        Self {
+        let mut app = Self {
             state,
             settings,
             database,
+            profile,
+            pomodoro: None,
+            stopwatch: StopwatchGuiData::default(),
+            undo: UndoStack::default(),
+            toast: None,
+            block_history: None,
+            block_commits: Vec::new(),
+            listener,
+            scheduler,
+            api,
+            ctx: cc.egui_ctx.clone(),
+        };
+
+        // handle startup commands
+        // other commands are handled without opening the gui
+        if let Some(Commands::Start { tag, here }) = command {
+            let here = here.then(|| std::env::current_dir().unwrap_or_default());
+            if let Err(e) = app.start_named(tag.as_deref(), here.as_deref()) {
+                warn!("Failed to start: {e:#}");
+            }
         }
+        app
     }
 
     fn handle_message(&mut self, message: GuiMessage) {
         let result: anyhow::Result<()> = (|| {
             match message {
                 GuiMessage::None => (),
-                GuiMessage::ChangedBlockTag(block) => self.database.blocks().update_tag(block)?,
-                GuiMessage::DeletedBlock(block) => History::new(&self.database).delete_block(block),
+                GuiMessage::ChangedBlockTag(block) => {
+                    self.change_block(block.id(), |db| db.blocks().update_tag(block))?
+                }
+                GuiMessage::DeletedBlock(block) => {
+                    self.change_block(block.id(), |db| db.blocks().delete(block))?;
+                    self.show_undo_toast();
+                }
+                GuiMessage::RestoredBlock(block) => {
+                    self.change_block(block.id(), |db| db.blocks().restore(block))?
+                }
+                GuiMessage::PurgedBlock(block) => {
+                    self.change_block(block.id(), |db| db.blocks().purge(block))?;
+                    self.show_undo_toast();
+                }
                 GuiMessage::SetState(state) => self.state = state,
-                GuiMessage::StartStopwatch(tag) => self.database.stopwatch().start(tag)?,
-                GuiMessage::StopStopwatch => self.database.stopwatch().stop()?,
-                GuiMessage::CreateTag(name) => self.database.tags().create(&name)?,
-                GuiMessage::DeleteTag(tag) => self.database.tags().delete(tag)?,
-                GuiMessage::RenameTag(tag) => self.database.tags().rename(tag)?,
+                GuiMessage::StartStopwatch(tag) => {
+                    self.database.stopwatch().start(tag)?;
+                    self.record_started()?;
+                }
+                GuiMessage::StartTimer(tag, planned) => {
+                    self.database
+                        .stopwatch()
+                        .start_planned(tag, Some(planned))?;
+                    self.record_started()?;
+                }
+                GuiMessage::StartPomodoro(tag) => {
+                    self.pomodoro = Some(Pomodoro::start(&self.database, tag, Local::now())?);
+                    self.record_started()?;
+                }
+                // the session itself can't be undone, so pausing and resuming aren't recorded
+                GuiMessage::PausePomodoro => {
+                    if let Some(pomodoro) = &mut self.pomodoro {
+                        pomodoro.pause(&self.database, Local::now())?;
+                    }
+                }
+                GuiMessage::ResumePomodoro => {
+                    if let Some(pomodoro) = &mut self.pomodoro {
+                        pomodoro.resume(&self.database, &self.settings, Local::now())?;
+                    }
+                }
+                GuiMessage::StopStopwatch => self.stop()?,
+                GuiMessage::CreateTag(name) => {
+                    let created = self.database.tags().create_path(&name)?;
+                    let edits = created
+                        .into_iter()
+                        .map(|tag| Edit::Tag {
+                            before: None,
+                            after: Some(tag),
+                        })
+                        .collect();
+                    self.undo.push(Edit::group(edits));
+                }
+                GuiMessage::DeleteTag(tag) => {
+                    let subtree = self.database.tags().subtree(&tag)?;
+                    let ids: Vec<usize> = subtree.iter().map(|t| t.id()).collect();
+                    self.change_tags(&ids, |db| db.tags().delete(tag))?;
+                    self.show_undo_toast();
+                }
+                GuiMessage::MergeTag(from, into) => {
+                    let blocks = self.database.blocks().tagged(&from)?;
+                    let blocks: Vec<usize> = blocks.iter().map(|b| b.id()).collect();
+                    let mut tags: Vec<usize> = (self.database.tags().all()?.iter())
+                        .filter(|t| t.parent == Some(from.id()))
+                        .map(|t| t.id())
+                        .collect();
+                    tags.push(from.id());
+                    self.change_records(&blocks, &tags, |db| db.tags().merge(&from, &into))?;
+                    self.show_undo_toast();
+                }
+                GuiMessage::UpdateTag(tag) => {
+                    self.change_tag(tag.id(), |db| db.tags().update(tag))?
+                }
+                GuiMessage::Undo => self.undo.undo(&self.database)?,
+                GuiMessage::Redo => self.undo.redo(&self.database)?,
+                GuiMessage::SwitchProfile(name) => self.switch_profile(name)?,
+                GuiMessage::CreateBackup => {
+                    self.database.backups().create(&self.settings.backups)?;
+                }
+                GuiMessage::RestoreBackup(path) => {
+                    self.database.restore(&path)?;
+                    self.settings = Settings::load(&self.database);
+                    self.pomodoro = None;
+                    self.undo.clear();
+                }
+                GuiMessage::ShowBlockHistory(block) => {
+                    self.block_commits = git::commits_during(&self.settings.repositories, &block);
+                    self.block_history = Some(block.id());
+                }
+                GuiMessage::SaveReport(day, format) => {
+                    let report = &self.settings.report;
+                    let template = report.template(format).map(export::read_template);
+                    let timesheet = History::new(&self.database).timesheet(day, &self.settings)?;
+                    let rendered = export::report(
+                        &timesheet,
+                        format,
+                        &self.settings,
+                        &report.notes,
+                        template.transpose()?.as_deref(),
+                    );
+                    let dir = profiles::data_dir()?.join(EXPORT_DIR);
+                    std::fs::create_dir_all(&dir)?;
+                    let name = format!("timesheet-{}.{}", timesheet.first, format.extension());
+                    let path = dir.join(name);
+                    std::fs::write(&path, rendered)?;
+                    info!("Saved report to {}", path.display());
+                    self.show_toast(format!("Saved {}", path.display()));
+                }
+                GuiMessage::EditBlocks(blocks, edit) => {
+                    let edited = self.edit_blocks(&blocks, &edit);
+                    // the block table keeps its selection until it hears how the edit went
+                    let error = edited.as_ref().err().map(|e| format!("{e:#}"));
+                    self.ctx
+                        .data_mut(|d| d.insert_temp(bulk_edit_outcome_id(), error));
+                    edited?;
+                    self.show_undo_toast();
+                }
+                GuiMessage::SplitBlock(block, at, tags) => {
+                    let before = self.database.blocks().get(block.id())?;
+                    let (first, second) = self.database.blocks().split(&block, at, tags)?;
+                    self.undo.push(Edit::group(vec![
+                        Edit::Block {
+                            before,
+                            after: Some(first),
+                        },
+                        Edit::Block {
+                            before: None,
+                            after: Some(second),
+                        },
+                    ]));
+                }
+                GuiMessage::MergeBlocks(first, second) => {
+                    // the later block is removed, so it is recorded first like in EditBlocks
+                    let ids = if first.start <= second.start {
+                        [second.id(), first.id()]
+                    } else {
+                        [first.id(), second.id()]
+                    };
+                    self.change_records(&ids, &[], |db| {
+                        db.blocks().merge(&first, &second).map(|_| ())
+                    })?;
+                    self.show_undo_toast();
+                }
+                GuiMessage::ExportBlocks(blocks, format) => {
+                    let tags = self.database.tags().all()?;
+                    let dir = profiles::data_dir()?.join(EXPORT_DIR);
+                    std::fs::create_dir_all(&dir)?;
+                    let name = format!("blocks-{}", Local::now().format("%Y-%m-%d-%H%M%S"));
+                    let path = dir.join(format!("{name}.{}", format.extension()));
+                    std::fs::write(
+                        &path,
+                        export::blocks(&blocks, &tags, format, &self.settings),
+                    )?;
+                    info!("Saved {} blocks to {}", blocks.len(), path.display());
+                    self.show_toast(format!("Saved {}", path.display()));
+                }
+                GuiMessage::CreateRule(rule) => self.database.rules().create(&rule)?,
+                GuiMessage::UpdateRule(rule) => self.database.rules().update(&rule)?,
+                GuiMessage::DeleteRule(rule) => self.database.rules().delete(&rule)?,
+                GuiMessage::ExportInvoice(first, last, format) => {
+                    let history = History::new(&self.database);
+                    let invoice = history.invoice(first, last, &self.settings)?;
+                    let dir = profiles::data_dir()?.join(EXPORT_DIR);
+                    std::fs::create_dir_all(&dir)?;
+                    let path = dir.join(format!("invoice-{first}-{last}.{}", format.extension()));
+                    std::fs::write(&path, export::invoice(&invoice, format, &self.settings))?;
+                    info!("Saved invoice to {}", path.display());
+                    self.show_toast(format!("Saved {}", path.display()));
+                }
             }
             Ok(())
         })();
@@ -120,16 +373,337 @@ impl TimeKeeperApp {
             warn!("Error updating database: {e:#}");
         }
     }
+
+    /// Runs `change` on a block, recording it so it can be undone
+    fn change_block(
+        &mut self,
+        id: usize,
+        change: impl FnOnce(&Database) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let before = self.database.blocks().get(id)?;
+        change(&self.database)?;
+        let after = self.database.blocks().get(id)?;
+        self.undo.push(Edit::Block { before, after });
+        Ok(())
+    }
+
+    /// Runs `change` on a tag, recording it so it can be undone
+    fn change_tag(
+        &mut self,
+        id: usize,
+        change: impl FnOnce(&Database) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.change_tags(&[id], change)
+    }
+
+    /// Runs `change` on several tags, recording it as one edit
+    fn change_tags(
+        &mut self,
+        ids: &[usize],
+        change: impl FnOnce(&Database) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.change_records(&[], ids, change)
+    }
+
+    /// Runs `change` on several blocks and tags, recording it as one edit
+    fn change_records(
+        &mut self,
+        block_ids: &[usize],
+        tag_ids: &[usize],
+        change: impl FnOnce(&Database) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let blocks = self.database.blocks();
+        let tags = self.database.tags();
+        let blocks_before: Vec<_> =
+            (block_ids.iter().map(|&id| blocks.get(id))).collect::<Result<_, _>>()?;
+        let tags_before: Vec<_> = tag_ids
+            .iter()
+            .map(|&id| tags.get(id))
+            .collect::<Result<_, _>>()?;
+        change(&self.database)?;
+
+        let mut edits = Vec::new();
+        for (&id, before) in block_ids.iter().zip(blocks_before) {
+            let after = blocks.get(id)?;
+            edits.push(Edit::Block { before, after });
+        }
+        for (&id, before) in tag_ids.iter().zip(tags_before) {
+            let after = tags.get(id)?;
+            edits.push(Edit::Tag { before, after });
+        }
+        self.undo.push(Edit::group(edits));
+        Ok(())
+    }
+
+    /// Makes the same edit to several blocks, recording it as one edit
+    fn edit_blocks(&mut self, blocks: &[Block], edit: &BlockEdit) -> anyhow::Result<()> {
+        let kept = edit.apply(blocks)?;
+        let mut ids: Vec<usize> = blocks.iter().map(|b| b.id()).collect();
+        // blocks the edit removes go first, so undoing a merge stops the merged block running
+        // before the one it took over from runs again
+        ids.sort_by_key(|&id| kept.iter().any(|b| b.id() == id));
+        self.change_records(&ids, &[], |db| db.blocks().edit_all(blocks, edit))
+    }
+
+    /// Stops the running block, and the pomodoro session if there is one
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.pomodoro = None;
+        if let Some(current) = self.database.blocks().current()? {
+            self.change_block(current.id(), |db| db.stopwatch().stop())?;
+        }
+        Ok(())
+    }
+
+    /// Starts a block with a tag given by name, or with the tag of the git repository in
+    /// `here`, as from the command line
+    fn start_named(&mut self, tag: Option<&str>, here: Option<&Path>) -> anyhow::Result<String> {
+        if self.database.blocks().current()?.is_some() {
+            bail!("A block is already running, use switch to start another");
+        }
+        let tags = self.database.tags().all()?;
+        let tag = match here {
+            Some(dir) => Some(git::tag_for(&self.settings.repositories, &tags, dir)?),
+            None => tag
+                .map(|name| self.database.tags().find(name))
+                .transpose()?,
+        };
+        let started = match &tag {
+            Some(tag) => format!("Started {}", tag.path(&tags)),
+            None => "Started".to_string(),
+        };
+        self.database.stopwatch().start(tag)?;
+        self.record_started()?;
+        Ok(started)
+    }
+
+    /// Answers commands sent by later invocations of the app
+    fn handle_forwarded(&mut self, ctx: &egui::Context) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+        let requests: Vec<_> = std::iter::from_fn(|| listener.try_recv()).collect();
+        for forwarded in requests {
+            let result = match &forwarded.request {
+                Request::Show => {
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                    Ok(String::new())
+                }
+                Request::Start { tag, here } => self.start_named(tag.as_deref(), here.as_deref()),
+                Request::Stop => self.stop().map(|_| "Stopped".to_string()),
+                Request::Switch { tag } => self
+                    .database
+                    .tags()
+                    .find(tag)
+                    .and_then(|_| self.stop())
+                    .and_then(|_| self.start_named(Some(tag), None)),
+                Request::Status => cli::status(&self.database),
+            };
+            forwarded.reply(result);
+        }
+    }
+
+    /// Records the block that was just started, so starting it can be undone
+    fn record_started(&mut self) -> anyhow::Result<()> {
+        let after = self.database.blocks().current()?;
+        self.undo.push(Edit::Block {
+            before: None,
+            after,
+        });
+        Ok(())
+    }
+
+    /// Records what scheduled rules changed, so it can be undone like any other edit
+    fn record_scheduled(&mut self) {
+        let edits: Vec<_> = self.scheduler.edits().collect();
+        for (profile, edit) in edits {
+            // a rule may have fired just before the scheduler heard of a profile switch
+            if profile == self.profile {
+                self.undo.push(edit);
+                self.show_undo_toast();
+            }
+        }
+    }
+
+    fn show_undo_toast(&mut self) {
+        self.toast = self.undo.last().map(|edit| Toast {
+            text: edit.description().to_string(),
+            undo: true,
+            shown: Local::now(),
+        });
+    }
+
+    fn show_toast(&mut self, text: String) {
+        self.toast = Some(Toast {
+            text,
+            undo: false,
+            shown: Local::now(),
+        });
+    }
+
+    /// Close the current database and open the profile's database in its place
+    fn switch_profile(&mut self, name: String) -> anyhow::Result<()> {
+        profiles::validate_name(&name)?;
+        let profile = Profile::Named(name);
+        let database = Database::new(&profile)?;
+
+        // the old socket has to go before a window for the new profile can be found
+        self.listener = None;
+        self.listener = listen(&profile, &self.ctx);
+        self.scheduler.switch_profile(profile.clone());
+        self.settings.save(&self.database);
+        self.settings = Settings::load(&database);
+        // the old server has to let go of the port before the new one can take it
+        self.api = None;
+        self.api = spawn_api(&profile, &self.settings, &self.ctx);
+        self.database = database;
+        self.pomodoro = None;
+        self.undo.clear();
+        profile.set_active();
+        info!("Switched to profile {}", profile);
+        self.profile = profile;
+        Ok(())
+    }
+
+    fn draw_block_history(&mut self, ctx: &egui::Context) {
+        let Some(id) = self.block_history else {
+            return;
+        };
+        let changes = match self.database.block_history().for_block(id) {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("Failed to read block history: {e:#}");
+                self.block_history = None;
+                return;
+            }
+        };
+
+        let mut open = true;
+        egui::Window::new(format!("History of block #{id}"))
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                draw_block_history(&changes, &self.settings, ui);
+                if !self.settings.repositories.is_empty() {
+                    ui.separator();
+                    draw_commits(&self.block_commits, &self.settings, ui);
+                }
+            });
+        if !open {
+            self.block_history = None;
+        }
+    }
+
+    fn draw_toast(&mut self, ctx: &egui::Context) -> GuiMessage {
+        let Some(toast) = &self.toast else {
+            return GuiMessage::None;
+        };
+        if Local::now() - toast.shown > Duration::seconds(TOAST_SECONDS) {
+            self.toast = None;
+            return GuiMessage::None;
+        }
+
+        let clicked = egui::Area::new(egui::Id::new("undo-toast"))
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style())
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(&toast.text);
+                            toast.undo && ui.button("Undo").clicked()
+                        })
+                        .inner
+                    })
+                    .inner
+            })
+            .inner;
+
+        if clicked {
+            self.toast = None;
+            GuiMessage::Undo
+        } else {
+            GuiMessage::None
+        }
+    }
+
+    /// Advance the pomodoro session, notifying the user when it changes phase
+    fn update_pomodoro(&mut self, ctx: &egui::Context) {
+        let Some(pomodoro) = &mut self.pomodoro else {
+            return;
+        };
+
+        // the block was stopped by something other than the pomodoro session
+        if !pomodoro.is_paused() && !matches!(self.database.blocks().current(), Ok(Some(_))) {
+            self.pomodoro = None;
+            return;
+        }
+
+        match pomodoro.tick(&self.database, &self.settings, Local::now()) {
+            Ok(Some(phase)) => {
+                let attention = match phase {
+                    Phase::Work => egui::UserAttentionType::Critical,
+                    Phase::ShortBreak | Phase::LongBreak => egui::UserAttentionType::Informational,
+                };
+                ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(attention));
+            }
+            Ok(None) => (),
+            Err(e) => warn!("Error updating pomodoro: {e:#}"),
+        }
+    }
+
+    /// Stop the running block once it reaches its planned duration, if enabled in settings
+    fn stop_at_planned(&mut self, ctx: &egui::Context) {
+        if !self.settings.stop_at_planned || self.pomodoro.is_some() {
+            return;
+        }
+        let Ok(Some(current)) = self.database.blocks().current() else {
+            return;
+        };
+        let Some(planned) = current.planned else {
+            return;
+        };
+
+        if current.duration() >= planned {
+            if let Err(e) = self.stop() {
+                warn!("Error stopping planned block: {e:#}");
+                return;
+            }
+            self.show_undo_toast();
+            ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(
+                egui::UserAttentionType::Informational,
+            ));
+        }
+    }
 }
 
-/// thread to update the gui regularly.
-/// This could be improved to only do it while the timer is active and the window is visible
-fn bg_timer(frame: egui::Context) {
-    let one_second = Duration::seconds(1)
-        .to_std()
-        .expect("1 second should be in range");
-    loop {
-        thread::sleep(one_second);
-        frame.request_repaint();
+/// Starts listening for commands from later invocations, logging rather than failing
+fn listen(profile: &Profile, ctx: &egui::Context) -> Option<Listener> {
+    Listener::bind(profile, ctx.clone())
+        .map_err(|e| warn!("Commands can't be sent to this window: {e:#}"))
+        .ok()
+}
+
+/// Serves the API for `profile` if its settings enable it
+fn spawn_api(profile: &Profile, settings: &Settings, ctx: &egui::Context) -> Option<api::Handle> {
+    if !settings.api.enabled {
+        return None;
+    }
+    let ctx = ctx.clone();
+    api::spawn(profile.clone(), &settings.api, move || {
+        ctx.request_repaint()
+    })
+}
+
+/// Moves settings from eframe storage into the database, if the database has none yet
+fn import_settings(storage: &dyn eframe::Storage, database: &Database) {
+    let Some(serialized) = storage.get_string(SETTINGS_KEY) else {
+        return;
+    };
+    match database.settings().is_empty() {
+        Ok(true) => {
+            info!("Moving settings into the database");
+            Settings::deserailize(Some(serialized)).save(database);
+        }
+        Ok(false) => (),
+        Err(e) => warn!("Failed to check for stored settings: {e:#}"),
     }
 }
@@ -0,0 +1,265 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use chrono::{Local, NaiveDate};
+
+use crate::api;
+use crate::database::Database;
+use crate::export::{self, Format};
+use crate::format::{fmt_duration, fmt_rounded};
+use crate::git;
+use crate::history::{GoalState, History};
+use crate::settings::Settings;
+use crate::Commands;
+
+/// Runs a command that doesn't need the gui
+pub(crate) fn run(command: &Commands, database: &mut Database) -> anyhow::Result<()> {
+    match command {
+        Commands::Status => {
+            println!("{}", status(database)?);
+            Ok(())
+        }
+        Commands::Stop => {
+            database.stopwatch().stop()?;
+            println!("Stopped");
+            Ok(())
+        }
+        Commands::Switch { tag } => switch(database, tag),
+        Commands::Restore { backup } => restore(database, backup.as_deref()),
+        Commands::Log { block, limit } => log(database, *block, *limit),
+        Commands::Invoice {
+            from,
+            to,
+            format,
+            output,
+        } => invoice(database, *from, *to, *format, output.as_deref()),
+        Commands::Report {
+            day,
+            format,
+            output,
+            template,
+            notes,
+        } => report(
+            database,
+            *day,
+            *format,
+            output.as_deref(),
+            template.as_deref(),
+            notes.as_deref(),
+        ),
+        Commands::Commits { from, to } => commits(database, *from, *to),
+        Commands::Serve { port, token } => serve(database, *port, token.as_deref()),
+        Commands::Start { .. } => unreachable!("start opens the gui"),
+    }
+}
+
+/// The running block and progress towards today's goal
+pub(crate) fn status(database: &Database) -> anyhow::Result<String> {
+    let settings = Settings::load(database);
+    database.stopwatch().update()?;
+    let mut out = String::new();
+
+    match database.blocks().current()? {
+        Some(block) => {
+            let tag = block.tag.as_ref().map_or("untagged", |t| &t.name);
+            writeln!(
+                out,
+                "Running since {} ({}) on {}",
+                block.start.format(&settings.time_format),
+                fmt_duration(block.duration()),
+                tag
+            )?;
+        }
+        None => writeln!(out, "Stopped")?,
+    }
+
+    let history = History::new(database);
+    let now = Local::now();
+    let (total, blocks) = history.blocks_in_day(now);
+    write!(
+        out,
+        "{}: {}",
+        now.format(&settings.date_format),
+        fmt_rounded(total, settings.rounding.day_total(&blocks))
+    )?;
+    match history.remaining_daily_goal(&settings) {
+        GoalState::ZeroGoal => (),
+        GoalState::StillNeeds(remaining) => {
+            write!(out, "\n{} left on daily goal", fmt_duration(remaining))?
+        }
+        GoalState::Reached => write!(out, "\nDaily goal reached")?,
+    }
+
+    Ok(out)
+}
+
+/// Stops the running block, if any, and starts one tagged `tag`
+fn switch(database: &Database, tag: &str) -> anyhow::Result<()> {
+    let tag = database.tags().find(tag)?;
+    let tags = database.tags().all()?;
+    let path = tag.path(&tags);
+    // one stopwatch, so the new block starts as the old one ends
+    let stopwatch = database.stopwatch();
+    stopwatch.stop()?;
+    stopwatch.start(Some(tag))?;
+    println!("Started {path}");
+    Ok(())
+}
+
+/// Lists the available backups, or restores `backup` if given
+fn restore(database: &mut Database, backup: Option<&str>) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    let backups = database.backups().list()?;
+
+    let Some(backup) = backup else {
+        if backups.is_empty() {
+            println!("No backups found");
+        }
+        for info in &backups {
+            let range = match (info.first, info.last) {
+                (Some(first), Some(last)) => format!(
+                    "{} to {}",
+                    first.format(&settings.date_format),
+                    last.format(&settings.date_format)
+                ),
+                _ => "empty".to_string(),
+            };
+            println!("{}\t{} blocks, {}", info.file_name(), info.blocks, range);
+        }
+        return Ok(());
+    };
+
+    let path = backups
+        .iter()
+        .find(|info| info.file_name() == backup)
+        .map(|info| info.path.clone())
+        .unwrap_or_else(|| PathBuf::from(backup));
+    database.restore(&path)?;
+    println!("Restored {}", path.display());
+    Ok(())
+}
+
+/// Prints changes to one block, or the most recent changes to any block
+fn log(database: &Database, block: Option<usize>, limit: usize) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    let changes = match block {
+        Some(block) => database.block_history().for_block(block)?,
+        None => database.block_history().recent(limit)?,
+    };
+
+    if changes.is_empty() {
+        println!("No changes recorded");
+    }
+    let format = format!("{} {}", settings.date_format, settings.time_format);
+    for change in &changes {
+        println!(
+            "{}\tblock #{} {}: {}",
+            change.changed.format(&format),
+            change.block,
+            change.action,
+            change.describe(&settings.time_format)
+        );
+    }
+    Ok(())
+}
+
+/// Prints an invoice summary, or writes it to `output`
+fn invoice(
+    database: &Database,
+    from: NaiveDate,
+    to: NaiveDate,
+    format: Format,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    let invoice = History::new(database).invoice(from, to, &settings)?;
+    let rendered = export::invoice(&invoice, format, &settings);
+    match output {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            print!("{rendered}");
+            Ok(())
+        }
+    }
+}
+
+/// Prints a weekly timesheet, or writes it to `output`
+fn report(
+    database: &Database,
+    day: Option<NaiveDate>,
+    format: Format,
+    output: Option<&Path>,
+    template: Option<&Path>,
+    notes: Option<&str>,
+) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    let day = day.unwrap_or_else(|| Local::now().date_naive());
+    let timesheet = History::new(database).timesheet(day, &settings)?;
+    let template = template.or(settings.report.template(format));
+    let template = template.map(export::read_template).transpose()?;
+    let notes = notes.unwrap_or(&settings.report.notes);
+    let rendered = export::report(&timesheet, format, &settings, notes, template.as_deref());
+    match output {
+        Some(path) => std::fs::write(path, rendered)
+            .with_context(|| format!("Failed to write {}", path.display())),
+        None => {
+            print!("{rendered}");
+            Ok(())
+        }
+    }
+}
+
+/// Prints each block from the days with the commits made while it ran
+fn commits(
+    database: &Database,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    if settings.repositories.is_empty() {
+        bail!("Add git repositories in the settings first");
+    }
+    let from = from.unwrap_or_else(|| Local::now().date_naive());
+    let to = to.unwrap_or(from);
+    let tags = database.tags().all()?;
+    let format = format!("{} {}", settings.date_format, settings.time_format);
+
+    for block in History::new(database).blocks_in_days(from, to)? {
+        let tag = block.tag.as_ref().map(|t| t.path(&tags));
+        println!(
+            "{} -> {}\t{}\t{}",
+            block.start.format(&format),
+            block.end.format(&settings.time_format),
+            fmt_duration(block.duration()),
+            tag.as_deref().unwrap_or("untagged")
+        );
+        for commit in git::commits_during(&settings.repositories, &block) {
+            println!(
+                "    {} {} {}: {}",
+                commit.time.format(&settings.time_format),
+                commit.short_hash(),
+                commit.repository_name(),
+                commit.summary
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Serves the HTTP API until the process is stopped
+fn serve(database: &Database, port: Option<u16>, token: Option<&str>) -> anyhow::Result<()> {
+    let settings = Settings::load(database);
+    let port = port.unwrap_or(settings.api.port);
+    let token = token.unwrap_or(&settings.api.token);
+    if token.is_empty() {
+        bail!("Set a token with --token, TIMEKEEPER_TOKEN or in the settings");
+    }
+    let server = api::Server::bind(port, token)?;
+    if let Some(addr) = server.addr() {
+        println!("Listening on http://{addr}");
+    }
+    server.run(database, || ());
+    Ok(())
+}
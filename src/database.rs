@@ -1,20 +1,60 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+#[cfg(not(target_arch = "wasm32"))]
 use anyhow::{anyhow, Context};
-use chrono::{DateTime, Duration, Local};
-use rusqlite::Connection;
-use tracing::info;
+#[cfg(not(target_arch = "wasm32"))]
+use chrono::TimeZone;
+use chrono::{DateTime, Duration, Local, NaiveDate};
+#[cfg(not(target_arch = "wasm32"))]
+use rusqlite::{Connection, OptionalExtension};
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::{info, warn};
 
-use crate::APP_NAME;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::history::start_of_day;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::hooks;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::profiles::Profile;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::settings::{BackupSettings, HookSettings, Settings};
 
+#[cfg(not(target_arch = "wasm32"))]
+mod audit;
+#[cfg(not(target_arch = "wasm32"))]
+mod backup;
+#[cfg(not(target_arch = "wasm32"))]
 mod migrations;
+#[cfg(not(target_arch = "wasm32"))]
+mod rules;
+mod storage;
+mod web;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use audit::BlockChange;
+#[cfg(not(target_arch = "wasm32"))]
+pub use backup::BackupInfo;
+#[cfg(not(target_arch = "wasm32"))]
+pub use rules::{Rule, RuleAction, Rules};
+pub use storage::Storage;
+pub use web::WebStorage;
 
 /// A block of time
-#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
 pub struct Block {
     id: usize,
     pub start: DateTime<Local>,
     pub end: DateTime<Local>,
     pub tag: Option<Tag>,
     pub running: bool,
+    /// Set when the block was a completed pomodoro work interval
+    pub pomodoro: bool,
+    /// How long the block was intended to run for
+    pub planned: Option<Duration>,
+    /// When the block was moved to the trash
+    pub deleted: Option<DateTime<Local>>,
 }
 
 impl Block {
@@ -27,10 +67,177 @@ impl Block {
     }
 }
 
+/// A change made to several blocks at once
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockEdit {
+    SetTag(Option<Tag>),
+    /// Joins the blocks into the earliest one, which then lasts until the latest ends. Time
+    /// between them is counted.
+    Merge,
+    /// Moves the blocks earlier or later
+    Shift(Duration),
+    Delete,
+}
+
+impl BlockEdit {
+    /// The blocks as they would be after the edit, leaving out those it removes
+    pub fn apply(&self, blocks: &[Block]) -> anyhow::Result<Vec<Block>> {
+        match self {
+            BlockEdit::SetTag(tag) => Ok(blocks
+                .iter()
+                .map(|block| Block {
+                    tag: tag.clone(),
+                    ..block.clone()
+                })
+                .collect()),
+            BlockEdit::Merge => {
+                if blocks.len() < 2 {
+                    bail!("Pick at least two blocks to merge");
+                }
+                let mut sorted = blocks.to_vec();
+                sorted.sort_by_key(|b| b.start);
+                if sorted.windows(2).any(|pair| pair[1].start < pair[0].end) {
+                    bail!("Blocks that overlap can't be merged");
+                }
+                let last = sorted.last().expect("There are blocks");
+                if sorted.iter().any(|b| b.running && b.id != last.id) {
+                    bail!("The running block can only be merged with blocks before it");
+                }
+                // the same as merging them in pairs with Blocks::merge
+                let merged = Block {
+                    end: last.end,
+                    running: last.running,
+                    pomodoro: false,
+                    planned: last.planned.filter(|_| last.running),
+                    ..sorted[0].clone()
+                };
+                Ok(vec![merged])
+            }
+            BlockEdit::Shift(offset) => {
+                if blocks.iter().any(|b| b.running) {
+                    bail!("The running block can't be moved");
+                }
+                let shifted: Vec<Block> = (blocks.iter())
+                    .map(|block| Block {
+                        start: block.start + *offset,
+                        end: block.end + *offset,
+                        ..block.clone()
+                    })
+                    .collect();
+                if shifted.iter().any(|b| b.end > Local::now()) {
+                    bail!("Blocks can't be moved into the future");
+                }
+                Ok(shifted)
+            }
+            BlockEdit::Delete => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Which blocks [`Blocks::query`] returns. Empty fields match every block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// Blocks with any of these tags, or tags nested under them
+    pub tags: Vec<Tag>,
+    /// First day blocks start on
+    pub from: Option<NaiveDate>,
+    /// Last day blocks start on
+    pub to: Option<NaiveDate>,
+    pub min_duration: Option<Duration>,
+    /// Blocks don't have notes of their own, so this is looked for in the path and
+    /// description of their tag, ignoring case
+    pub text: String,
+}
+
+impl BlockFilter {
+    /// Checks the parts of the filter that aren't checked in SQL
+    fn matches(&self, block: &Block, tags: &[Tag]) -> bool {
+        let tagged = self.tags.is_empty()
+            || (block.tag.as_ref()).is_some_and(|t| self.tags.iter().any(|f| t.is_within(f, tags)));
+        let text = self.text.trim().to_lowercase();
+        let described = text.is_empty()
+            || block.tag.as_ref().is_some_and(|tag| {
+                let description = tag.description.as_deref().unwrap_or_default();
+                tag.path(tags).to_lowercase().contains(&text)
+                    || description.to_lowercase().contains(&text)
+            });
+        tagged && described
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Eq)]
 pub struct Tag {
     id: usize,
     pub name: String,
+    /// Colour as red, green and blue
+    pub color: Option<[u8; 3]>,
+    /// An emoji shown before the name
+    pub icon: Option<String>,
+    pub description: Option<String>,
+    /// Id of the tag this one is nested under
+    pub parent: Option<usize>,
+    /// Hourly rate, inherited by nested tags that don't have their own
+    pub rate: Option<Rate>,
+    /// Whether time is billed, inherited like the rate. Billable if no tag says otherwise.
+    pub billable: Option<bool>,
+    /// Archived tags are kept for history, but can't be picked for new blocks
+    pub archived: bool,
+}
+impl Tag {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The name, with the icon in front if there is one
+    pub fn label(&self) -> String {
+        match &self.icon {
+            Some(icon) => format!("{} {}", icon, self.name),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The tags this one is nested under, nearest first. `tags` should hold every tag.
+    pub fn ancestors<'a>(&self, tags: &'a [Tag]) -> Vec<&'a Tag> {
+        let mut ancestors = Vec::new();
+        let mut parent = self.parent;
+        // the length check stops a loop if the database somehow contains a cycle
+        while let Some(tag) = parent.and_then(|id| tags.iter().find(|t| t.id == id)) {
+            if ancestors.len() >= tags.len() {
+                break;
+            }
+            ancestors.push(tag);
+            parent = tag.parent;
+        }
+        ancestors
+    }
+
+    /// Names from the root down to this tag, e.g. `ACME / Website / QA`
+    pub fn path(&self, tags: &[Tag]) -> String {
+        let mut names: Vec<&str> = self.ancestors(tags).iter().map(|t| &*t.name).collect();
+        names.reverse();
+        names.push(&self.name);
+        names.join(TAG_PATH_SEPARATOR)
+    }
+
+    /// The rate time on this tag is billed at, `None` if it isn't billable
+    pub fn billing_rate(&self, tags: &[Tag]) -> Option<Rate> {
+        let mut lineage = std::iter::once(self).chain(self.ancestors(tags));
+        let billable = lineage.clone().find_map(|t| t.billable).unwrap_or(true);
+        if !billable {
+            return None;
+        }
+        lineage.find_map(|t| t.rate.clone())
+    }
+
+    /// Whether this tag or one it is nested under is archived
+    pub fn is_archived(&self, tags: &[Tag]) -> bool {
+        self.archived || self.ancestors(tags).iter().any(|t| t.archived)
+    }
+
+    /// Whether this is `tag` or nested somewhere under it
+    pub fn is_within(&self, tag: &Tag, tags: &[Tag]) -> bool {
+        self == tag || self.ancestors(tags).contains(&tag)
+    }
 }
 impl PartialEq for Tag {
     fn eq(&self, other: &Self) -> bool {
@@ -38,26 +245,110 @@ impl PartialEq for Tag {
     }
 }
 
+/// An hourly rate, in the smallest unit of the currency such as cents
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Rate {
+    pub amount: i64,
+    pub currency: String,
+}
+
+impl Rate {
+    /// What `time` costs at this rate, rounded to the nearest unit
+    pub fn cost(&self, time: Duration) -> i64 {
+        let seconds = time.num_seconds() as i128 * self.amount as i128;
+        ((seconds + 1800) / 3600) as i64
+    }
+}
+
+/// Separates tag names in a path
+pub const TAG_PATH_SEPARATOR: &str = " / ";
+/// Most levels tags can be nested, guards against cycles
+const MAX_TAG_DEPTH: usize = 64;
+/// Key of [`Settings::hooks`] in the settings table
+#[cfg(not(target_arch = "wasm32"))]
+const HOOKS_KEY: &str = "hooks";
+
+/// Puts tags in tree order, with each tag followed by its children
+fn sort_tree(tags: Vec<Tag>) -> Vec<Tag> {
+    // sorting by the names along the path keeps each subtree together
+    let mut keyed: Vec<(Vec<String>, Tag)> = tags
+        .iter()
+        .map(|tag| {
+            let mut key: Vec<String> = tag
+                .ancestors(&tags)
+                .iter()
+                .rev()
+                .chain([&tag])
+                .map(|t| t.name.to_lowercase())
+                .collect();
+            key.push(tag.id.to_string());
+            (key, tag.clone())
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0));
+    keyed.into_iter().map(|(_, tag)| tag).collect()
+}
+
+/// Splits a path like `ACME / Website / QA` into tag names
+fn path_names(path: &str) -> anyhow::Result<Vec<&str>> {
+    let names: Vec<&str> = path
+        .split(TAG_PATH_SEPARATOR.trim())
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        bail!("Tag name is empty");
+    }
+    Ok(names)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 pub struct Database {
     conn: Connection,
+    /// Location of the database on disk, `None` if it is only kept in memory
+    path: Option<PathBuf>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Database {
-    pub fn new() -> Result<Self, anyhow::Error> {
-        let mut conn = new_disk_connection().or_else(|e| {
+    pub fn new(profile: &Profile) -> Result<Self, anyhow::Error> {
+        let (mut conn, path) = new_disk_connection(profile).or_else(|e| {
             tracing::warn!("Failed to open or create database on disk. Records will not be persisted. Error is {:#}", e);
-            new_in_memory_connection()
+            new_in_memory_connection().map(|conn| (conn, None))
         })?;
 
-        migrations::migrate(&mut conn)?;
+        migrations::migrate(&mut conn, path.as_deref())?;
 
         //perform maintainence on database
-        let database = Self { conn };
+        let database = Self { conn, path };
         database.tags().maintain()?;
 
+        let settings = Settings::load(&database);
+        database
+            .blocks()
+            .empty_trash(Local::now() - Duration::days(settings.trash_days.into()))?;
+        if let Err(e) = database.backups().daily(&settings.backups) {
+            warn!("Daily backup failed: {e:#}");
+        }
+
         Ok(database)
     }
 
+    /// Replaces the contents of the database with a backup.
+    /// The current contents are backed up first, so this can be undone.
+    pub fn restore(&mut self, backup: &Path) -> anyhow::Result<()> {
+        let path = self
+            .path
+            .as_deref()
+            .ok_or(anyhow!("Backups are not available for in memory databases"))?;
+        backup::create(&self.conn, path, "pre-restore")?;
+        backup::restore(&mut self.conn, backup)?;
+
+        // the backup might be from an older version of the app
+        migrations::migrate(&mut self.conn, None)?;
+        Ok(())
+    }
+
     pub fn stopwatch(&self) -> StopWatch<'_> {
         StopWatch {
             conn: &self.conn,
@@ -72,122 +363,521 @@ impl Database {
     pub fn tags(&self) -> Tags<'_> {
         Tags { conn: &self.conn }
     }
+
+    pub fn settings(&self) -> StoredSettings<'_> {
+        StoredSettings { conn: &self.conn }
+    }
+
+    pub fn block_history(&self) -> audit::BlockHistory<'_> {
+        audit::BlockHistory { conn: &self.conn }
+    }
+
+    pub fn rules(&self) -> Rules<'_> {
+        Rules { conn: &self.conn }
+    }
+
+    pub fn backups(&self) -> Backups<'_> {
+        Backups {
+            conn: &self.conn,
+            path: self.path.as_deref(),
+        }
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub struct StopWatch<'a> {
     conn: &'a Connection,
     now: DateTime<Local>,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl StopWatch<'_> {
     /// Start the stopwatch
     pub fn start(&self, tag: Option<Tag>) -> Result<(), anyhow::Error> {
+        self.start_planned(tag, None)
+    }
+
+    /// Start the stopwatch, with a target for how long the block should run
+    pub fn start_planned(
+        &self,
+        tag: Option<Tag>,
+        planned: Option<Duration>,
+    ) -> Result<(), anyhow::Error> {
         let block = Block {
             id: 0,
             start: self.now,
             end: self.now,
             tag,
             running: true,
+            pomodoro: false,
+            planned,
+            deleted: None,
         };
 
         let tag_id = block.tag.as_ref().map(|t| t.id);
         let running = if block.running { Some("Y") } else { None };
+        let planned = block.planned.map(|p| p.num_seconds());
+        let (start, start_offset) = to_epoch(&block.start);
+        let (end, end_offset) = to_epoch(&block.end);
 
-        self.conn
-            .execute(
-                "
-            INSERT INTO time_blocks (start, end, tag, running)
-            VALUES (?1, ?2, ?3, ?4)",
-                rusqlite::params![block.start, block.end, tag_id, running],
-            )
-            .map(|_| ())
-            .context("Trying to insert block into database")?;
+        let id = in_transaction(self.conn, || {
+            self.conn
+                .execute(
+                    "
+            INSERT INTO time_blocks (start, start_offset, end, end_offset, tag, running, planned)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    rusqlite::params![
+                        start,
+                        start_offset,
+                        end,
+                        end_offset,
+                        tag_id,
+                        running,
+                        planned
+                    ],
+                )
+                .context("Trying to insert block into database")?;
+            let id = self.conn.last_insert_rowid() as usize;
+            let after = get_block(self.conn, id)?;
+            audit::record(self.conn, id, "start", None, after.as_ref())?;
+            Ok(id)
+        })?;
 
         if let Some(tag) = &block.tag {
             info!("Started block tagged `{}`", tag.name);
         } else {
             info!("Started untagged block");
         }
+        self.run_hook(hooks::Event::Start, id);
         Ok(())
     }
 
+    /// Id of the running block, if any
+    fn running(&self) -> Result<Option<usize>, anyhow::Error> {
+        match self.conn.query_row(
+            "SELECT id FROM time_blocks WHERE running = 'Y'",
+            [],
+            |row| row.get(0),
+        ) {
+            Ok(id) => Ok(Some(id)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Trying to find running block"),
+        }
+    }
+
     /// Stops any running blocks
     pub fn stop(&self) -> Result<(), anyhow::Error> {
-        self.conn
-            .execute(
-                "UPDATE time_blocks SET end = ?1, running = ?2 WHERE running = ?3",
-                rusqlite::params![self.now, Option::<&str>::None, "Y"],
-            )
-            .map(|_| ())
-            .context("Trying to stop running blocks")?;
+        let Some(id) = self.running()? else {
+            return Ok(());
+        };
+        let (end, end_offset) = to_epoch(&self.now);
+        audit::logged(self.conn, id, "stop", || {
+            self.conn
+                .execute(
+                    "UPDATE time_blocks SET end = ?1, end_offset = ?2, running = NULL
+                    WHERE id = ?3",
+                    rusqlite::params![end, end_offset, id],
+                )
+                .map(|_| ())
+                .context("Trying to stop running blocks")
+        })?;
         info!("Stopped");
+        self.run_hook(hooks::Event::Stop, id);
         Ok(())
     }
 
-    /// Update end times on running blocks
+    /// Stops any running blocks, marking them as completed pomodoros
+    pub fn finish_pomodoro(&self) -> Result<(), anyhow::Error> {
+        let Some(id) = self.running()? else {
+            return Ok(());
+        };
+        let (end, end_offset) = to_epoch(&self.now);
+        audit::logged(self.conn, id, "pomodoro", || {
+            self.conn
+                .execute(
+                    "UPDATE time_blocks
+                    SET end = ?1, end_offset = ?2, running = NULL, pomodoro = 'Y'
+                    WHERE id = ?3",
+                    rusqlite::params![end, end_offset, id],
+                )
+                .map(|_| ())
+                .context("Trying to finish running pomodoro")
+        })?;
+        info!("Finished pomodoro");
+        self.run_hook(hooks::Event::Stop, id);
+        Ok(())
+    }
+
+    /// Runs the user's hook for the block. Problems are logged, the block has already changed.
+    fn run_hook(&self, event: hooks::Event, id: usize) {
+        let result = (|| {
+            let settings = StoredSettings { conn: self.conn }.hooks()?;
+            if let Some(block) = get_block(self.conn, id)? {
+                let tags = Tags { conn: self.conn }.all()?;
+                hooks::fire(&settings, event, &block, &tags);
+            }
+            anyhow::Ok(())
+        })();
+        if let Err(e) = result {
+            warn!("Failed to run the {event:?} hook: {e:#}");
+        }
+    }
+
+    /// Update end times on running blocks.
+    /// This runs every frame, so it is not recorded in the block history.
     pub fn update(&self) -> Result<(), anyhow::Error> {
+        let (end, end_offset) = to_epoch(&self.now);
         self.conn
             .execute(
-                "UPDATE time_blocks SET end = ?1 WHERE running = ?2",
-                rusqlite::params![self.now, "Y"],
+                "UPDATE time_blocks SET end = ?1, end_offset = ?2 WHERE running = ?3",
+                rusqlite::params![end, end_offset, "Y"],
             )
             .map(|_| ())
             .context("Trying to stop running blocks")
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub struct Blocks<'a> {
     conn: &'a Connection,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Blocks<'_> {
     /// Converts a rustqlite row into a block
     fn to_blocks(row: &rusqlite::Row<'_>) -> Result<Block, rusqlite::Error> {
         let running: Option<String> = row.get(3)?;
         let running = running.filter(|s| s == "Y").is_some();
-        let id: Option<usize> = row.get(4)?;
-        let name: Option<String> = row.get(5)?;
-        let tag = id.map(|id| Tag {
-            id,
-            name: name.expect("tags.name should not be null when tags.id is not null"),
-        });
+        let pomodoro: Option<String> = row.get(4)?;
+        let planned: Option<i64> = row.get(5)?;
+        let deleted: Option<i64> = row.get(6)?;
+        let deleted = match deleted {
+            Some(_) => Some(get_time(row, 6)?),
+            None => None,
+        };
+        let tag_id: Option<usize> = row.get(7)?;
+        let tag = match tag_id {
+            Some(_) => Some(Tags::to_tag(row, 7)?),
+            None => None,
+        };
         Ok(Block {
             id: row.get(0)?,
-            start: row.get(1)?,
-            end: row.get(2)?,
+            start: get_time(row, 1)?,
+            end: get_time(row, 2)?,
             tag,
             running,
+            pomodoro: pomodoro.is_some(),
+            planned: planned.map(Duration::seconds),
+            deleted,
         })
     }
 
     pub fn update_tag(&self, block: Block) -> Result<(), anyhow::Error> {
         let tag = block.tag.map(|t| t.id);
-        self.conn
-            .execute(
-                "UPDATE time_blocks
+        audit::logged(self.conn, block.id, "retag", || {
+            self.conn
+                .execute(
+                    "UPDATE time_blocks
             SET tag = ?2
             WHERE id = ?1",
-                rusqlite::params![block.id, tag],
-            )
-            .map(|_| ())
-            .context("Trying to update a block")
+                    rusqlite::params![block.id, tag],
+                )
+                .map(|_| ())
+                .context("Trying to update a block")
+        })
     }
 
+    /// Moves the block to the trash, stopping it if it is running
     pub fn delete(&self, block: Block) -> Result<(), anyhow::Error> {
+        audit::logged(self.conn, block.id, "trash", || {
+            self.conn
+                .execute(
+                    "UPDATE time_blocks SET deleted = ?2, running = NULL WHERE id = ?1",
+                    rusqlite::params![block.id, Local::now().timestamp()],
+                )
+                .map(|_| ())
+                .context("Trying to move block to trash")
+        })
+    }
+
+    /// Takes the block back out of the trash
+    pub fn restore(&self, block: Block) -> Result<(), anyhow::Error> {
+        audit::logged(self.conn, block.id, "restore", || {
+            self.conn
+                .execute(
+                    "UPDATE time_blocks SET deleted = NULL WHERE id = ?1",
+                    [block.id],
+                )
+                .map(|_| ())
+                .context("Trying to restore block from trash")
+        })
+    }
+
+    /// Permanently removes the block
+    pub fn purge(&self, block: Block) -> Result<(), anyhow::Error> {
+        audit::logged(self.conn, block.id, "purge", || {
+            self.conn
+                .execute("DELETE FROM time_blocks WHERE id = ?1", [block.id])
+                .map(|_| ())
+                .context("Trying to delete block from database")
+        })
+    }
+
+    /// Permanently removes blocks that were moved to the trash before `cutoff`
+    pub fn empty_trash(&self, cutoff: DateTime<Local>) -> Result<(), anyhow::Error> {
+        let expired: Vec<usize> = self
+            .conn
+            .prepare("SELECT id FROM time_blocks WHERE deleted IS NOT NULL AND deleted < ?1")
+            .context("Preparing to find expired blocks in trash")?
+            .query_map([cutoff.timestamp()], |row| row.get(0))
+            .context("Trying to find expired blocks in trash")?
+            .collect::<Result<_, _>>()
+            .context("Trying to find expired blocks in trash")?;
+        if expired.is_empty() {
+            return Ok(());
+        }
+
+        in_transaction(self.conn, || {
+            for &id in &expired {
+                audit::logged(self.conn, id, "purge", || {
+                    self.conn
+                        .execute("DELETE FROM time_blocks WHERE id = ?1", [id])
+                        .map(|_| ())
+                        .context("Trying to empty trash")
+                })?;
+            }
+            Ok(())
+        })?;
+        info!("Removed {} blocks from the trash", expired.len());
+        Ok(())
+    }
+
+    /// Blocks in the trash, most recently deleted first
+    pub fn trash(&self) -> Result<Vec<Block>, anyhow::Error> {
+        self.conn
+            .prepare(
+                "
+                SELECT
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
+                FROM time_blocks block
+                LEFT JOIN tags tag ON block.tag = tag.id
+                WHERE deleted IS NOT NULL
+                ORDER BY deleted DESC",
+            )
+            .context("Preparing to get trash")?
+            .query_map([], Self::to_blocks)
+            .context("Trying to get trash")?
+            .map(|r| r.context("Trying to map row to Block struct"))
+            .collect()
+    }
+
+    /// Writes every field of the block, inserting it if it doesn't exist
+    pub fn put(&self, block: &Block) -> Result<(), anyhow::Error> {
+        audit::logged(self.conn, block.id, "edit", || self.write(block))
+    }
+
+    /// Writes the block without recording the change
+    fn write(&self, block: &Block) -> Result<(), anyhow::Error> {
+        let tag_id = block.tag.as_ref().map(|t| t.id);
+        let running = if block.running { Some("Y") } else { None };
+        let pomodoro = if block.pomodoro { Some("Y") } else { None };
+        let planned = block.planned.map(|p| p.num_seconds());
+        let (start, start_offset) = to_epoch(&block.start);
+        let (end, end_offset) = to_epoch(&block.end);
+        let deleted = block.deleted.map(|d| d.timestamp());
+
         self.conn
-            .execute("DELETE FROM time_blocks WHERE id = ?1", [block.id])
+            .execute(
+                "
+                INSERT INTO time_blocks
+                    (id, start, start_offset, end, end_offset,
+                    tag, running, pomodoro, planned, deleted)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                ON CONFLICT(id) DO UPDATE SET
+                    start = ?2, start_offset = ?3, end = ?4, end_offset = ?5,
+                    tag = ?6, running = ?7, pomodoro = ?8, planned = ?9, deleted = ?10",
+                rusqlite::params![
+                    block.id,
+                    start,
+                    start_offset,
+                    end,
+                    end_offset,
+                    tag_id,
+                    running,
+                    pomodoro,
+                    planned,
+                    deleted
+                ],
+            )
             .map(|_| ())
-            .context("Trying to delete block from database")
+            .context("Trying to write block to database")
+    }
+
+    /// Divides the block at `at`, giving each part its own tag. The second part is a new
+    /// block, which keeps running if the block was.
+    pub fn split(
+        &self,
+        block: &Block,
+        at: DateTime<Local>,
+        tags: [Option<Tag>; 2],
+    ) -> Result<(Block, Block), anyhow::Error> {
+        let block = self.get_undeleted(block.id)?;
+        let end = if block.running {
+            Local::now()
+        } else {
+            block.end
+        };
+        if at <= block.start || at >= end {
+            bail!("Blocks can only be split between their start and end");
+        }
+
+        let [first_tag, second_tag] = tags;
+        let first = Block {
+            end: at,
+            tag: first_tag,
+            running: false,
+            planned: block.planned.filter(|_| !block.running),
+            ..block.clone()
+        };
+        // a running block's target is for the time since it started
+        let planned = (block.planned.filter(|_| block.running))
+            .map(|p| (p - first.duration()).max(Duration::zero()));
+        let second = Block {
+            id: 0,
+            start: at,
+            end,
+            tag: second_tag,
+            planned,
+            ..block
+        };
+
+        in_transaction(self.conn, || {
+            // the first part stops running before the second starts
+            audit::logged(self.conn, first.id, "split", || self.write(&first))?;
+            let id = self.insert(&second)?;
+            let second = get_block(self.conn, id)?;
+            audit::record(self.conn, id, "split", None, second.as_ref())?;
+            let second = second.ok_or_else(|| anyhow!("The new block wasn't saved"))?;
+            Ok((first, second))
+        })
+    }
+
+    /// Combines two blocks into the earlier one and moves the later one to the trash. There
+    /// can't be other blocks between them, and any time between them is counted.
+    pub fn merge(&self, a: &Block, b: &Block) -> Result<Block, anyhow::Error> {
+        if a.id == b.id {
+            bail!("A block can't be merged with itself");
+        }
+        let (a, b) = (self.get_undeleted(a.id)?, self.get_undeleted(b.id)?);
+        let (first, second) = if a.start <= b.start { (a, b) } else { (b, a) };
+        if first.running {
+            bail!("The running block can only be merged with blocks before it");
+        }
+        if second.start < first.end {
+            bail!("Blocks that overlap can't be merged");
+        }
+        let end = if second.running {
+            Local::now()
+        } else {
+            second.end
+        };
+        let between = self.overlapping(first.start, end)?;
+        if between
+            .iter()
+            .any(|b| b.id != first.id && b.id != second.id)
+        {
+            bail!("Only blocks next to each other can be merged");
+        }
+
+        let merged = Block {
+            end: second.end,
+            running: second.running,
+            pomodoro: false,
+            planned: second.planned.filter(|_| second.running),
+            ..first.clone()
+        };
+        in_transaction(self.conn, || {
+            self.delete(second)?;
+            audit::logged(self.conn, merged.id, "merge", || self.write(&merged))?;
+            Ok(merged)
+        })
+    }
+
+    /// Blocks with any time between `start` and `end`, leaving out the trash
+    fn overlapping(
+        &self,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+    ) -> Result<Vec<Block>, anyhow::Error> {
+        self.conn
+            .prepare(
+                "
+                SELECT
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
+                FROM time_blocks block
+                LEFT JOIN tags tag ON block.tag = tag.id
+                WHERE start < ?2
+                AND (end > ?1 OR running IS 'Y')
+                AND deleted IS NULL",
+            )
+            .context("Preparing to get overlapping blocks")?
+            .query_map([start.timestamp(), end.timestamp()], Self::to_blocks)
+            .context("Trying to get overlapping blocks")?
+            .map(|r| r.context("Trying to map row to Block struct"))
+            .collect()
+    }
+
+    /// The block, failing if it doesn't exist or is in the trash
+    fn get_undeleted(&self, id: usize) -> Result<Block, anyhow::Error> {
+        match self.get(id)? {
+            Some(block) if block.deleted.is_none() => Ok(block),
+            _ => bail!("The block was deleted"),
+        }
+    }
+
+    /// Adds the block with a new id, returning the id
+    fn insert(&self, block: &Block) -> Result<usize, anyhow::Error> {
+        let (start, start_offset) = to_epoch(&block.start);
+        let (end, end_offset) = to_epoch(&block.end);
+        self.conn
+            .execute(
+                "
+                INSERT INTO time_blocks
+                    (start, start_offset, end, end_offset, tag, running, pomodoro, planned)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                rusqlite::params![
+                    start,
+                    start_offset,
+                    end,
+                    end_offset,
+                    block.tag.as_ref().map(|t| t.id),
+                    block.running.then_some("Y"),
+                    block.pomodoro.then_some("Y"),
+                    block.planned.map(|p| p.num_seconds()),
+                ],
+            )
+            .context("Trying to insert block into database")?;
+        Ok(self.conn.last_insert_rowid() as usize)
+    }
+
+    pub fn get(&self, id: usize) -> Result<Option<Block>, anyhow::Error> {
+        get_block(self.conn, id)
     }
 
     pub fn current(&self) -> Result<Option<Block>, anyhow::Error> {
         let current = self.conn.query_row(
             "
                 SELECT 
-                    block.id, start, end, running, tag.id, tag.name 
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
                 FROM time_blocks block 
                 LEFT JOIN tags tag ON block.tag = tag.id
-                WHERE running is 'Y'",
+                WHERE running is 'Y' AND deleted IS NULL",
             [],
             Self::to_blocks,
         );
@@ -208,134 +898,1015 @@ impl Blocks<'_> {
             .prepare(
                 "
                 SELECT
-                    block.id, start, end, running, tag.id, tag.name
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
                 FROM time_blocks block
                 LEFT JOIN tags tag ON block.tag = tag.id
-                WHERE JulianDay(start) > JulianDay(?1) 
-                AND JulianDay(start) < JulianDay(?2)",
+                WHERE start > ?1
+                AND start < ?2
+                AND deleted IS NULL",
             )
             .context("Preparing to get all blocks")?
-            .query_map([before, after], Self::to_blocks)
+            .query_map([before.timestamp(), after.timestamp()], Self::to_blocks)
             .context("Trying to get all blocks")?
             .map(|r| r.context("Trying to map row to Block struct"))
             .collect()
     }
+
+    /// Blocks matching the filter, newest first, leaving out the trash
+    pub fn query(&self, filter: &BlockFilter) -> Result<Vec<Block>, anyhow::Error> {
+        let from = filter.from.map(|day| start_of_day(day).timestamp());
+        // up to the end of the last day
+        let to =
+            (filter.to.and_then(|day| day.succ_opt())).map(|day| start_of_day(day).timestamp());
+        let min_duration = filter.min_duration.map(|d| d.num_seconds());
+        let blocks: Vec<Block> = self
+            .conn
+            .prepare(
+                "
+                SELECT
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
+                FROM time_blocks block
+                LEFT JOIN tags tag ON block.tag = tag.id
+                WHERE deleted IS NULL
+                AND (?1 IS NULL OR start >= ?1)
+                AND (?2 IS NULL OR start < ?2)
+                AND (?3 IS NULL OR end - start >= ?3)
+                ORDER BY start DESC",
+            )
+            .context("Preparing to search blocks")?
+            .query_map(rusqlite::params![from, to, min_duration], Self::to_blocks)
+            .context("Trying to search blocks")?
+            .collect::<Result<_, _>>()
+            .context("Trying to map row to Block struct")?;
+
+        let tags = Tags { conn: self.conn }.all()?;
+        Ok(blocks
+            .into_iter()
+            .filter(|block| filter.matches(block, &tags))
+            .collect())
+    }
+
+    /// Makes the edit to every block, all or none of them
+    pub fn edit_all(&self, blocks: &[Block], edit: &BlockEdit) -> Result<(), anyhow::Error> {
+        let edited = edit.apply(blocks)?;
+        match edit {
+            BlockEdit::SetTag(tag) => self.retag_all(blocks, tag.as_ref()),
+            BlockEdit::Delete => self.delete_all(blocks),
+            BlockEdit::Merge => in_transaction(self.conn, || {
+                let mut sorted = blocks.to_vec();
+                sorted.sort_by_key(|b| b.start);
+                let mut merged = sorted[0].clone();
+                for block in &sorted[1..] {
+                    merged = self.merge(&merged, block)?;
+                }
+                Ok(())
+            }),
+            BlockEdit::Shift(_) => in_transaction(self.conn, || {
+                for block in &edited {
+                    let overlapping = self.overlapping(block.start, block.end)?;
+                    if overlapping
+                        .iter()
+                        .any(|o| blocks.iter().all(|b| b.id != o.id))
+                    {
+                        bail!("Blocks can't be moved over other blocks");
+                    }
+                }
+                for block in &edited {
+                    self.put(block)?;
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    /// Gives every block the tag, all or none of them
+    pub fn retag_all(&self, blocks: &[Block], tag: Option<&Tag>) -> Result<(), anyhow::Error> {
+        in_transaction(self.conn, || {
+            for block in blocks {
+                self.update_tag(Block {
+                    tag: tag.cloned(),
+                    ..block.clone()
+                })?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Moves every block to the trash, all or none of them
+    pub fn delete_all(&self, blocks: &[Block]) -> Result<(), anyhow::Error> {
+        in_transaction(self.conn, || {
+            for block in blocks {
+                self.delete(block.clone())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Every block with the tag, including those in the trash
+    pub fn tagged(&self, tag: &Tag) -> Result<Vec<Block>, anyhow::Error> {
+        self.conn
+            .prepare(
+                "
+                SELECT
+                    block.id, start, end, running, pomodoro, planned, deleted,
+                    tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                    tag.rate, tag.currency, tag.billable, tag.archived
+                FROM time_blocks block
+                JOIN tags tag ON block.tag = tag.id
+                WHERE tag.id = ?1",
+            )
+            .context("Preparing to get tagged blocks")?
+            .query_map([tag.id], Self::to_blocks)
+            .context("Trying to get tagged blocks")?
+            .map(|r| r.context("Trying to map row to Block struct"))
+            .collect()
+    }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 pub struct Tags<'a> {
     conn: &'a Connection,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Tags<'_> {
+    /// Reads a tag from `id, name, color, icon, description, parent, rate, currency, billable,
+    /// archived` columns, starting at `idx`
+    fn to_tag(row: &rusqlite::Row<'_>, idx: usize) -> Result<Tag, rusqlite::Error> {
+        let color: Option<u32> = row.get(idx + 2)?;
+        let rate: Option<i64> = row.get(idx + 6)?;
+        let currency: Option<String> = row.get(idx + 7)?;
+        let billable: Option<String> = row.get(idx + 8)?;
+        let archived: Option<String> = row.get(idx + 9)?;
+        Ok(Tag {
+            id: row.get(idx)?,
+            name: row.get(idx + 1)?,
+            color: color.map(to_rgb),
+            icon: row.get(idx + 3)?,
+            description: row.get(idx + 4)?,
+            parent: row.get(idx + 5)?,
+            rate: rate.map(|amount| Rate {
+                amount,
+                currency: currency.unwrap_or_default(),
+            }),
+            billable: billable.map(|b| b == "Y"),
+            archived: archived.is_some(),
+        })
+    }
+
+    /// All tags that haven't been deleted, in tree order with each tag followed by its children
     pub fn all(&self) -> Result<Vec<Tag>, anyhow::Error> {
-        self.conn
+        let tags: Vec<Tag> = self
+            .conn
             .prepare(
                 "
             SELECT
-            id, name
+            id, name, color, icon, description, parent, rate, currency, billable, archived
             FROM tags
             WHERE to_delete IS NULL",
             )
             .context("Preparing to get all tags")?
-            .query_map([], |row| {
-                Ok(Tag {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                })
-            })
+            .query_map([], |row| Self::to_tag(row, 0))
             .context("Trying to get all tags")?
             .map(|r| r.context("Trying to map row to Tag struct"))
-            .collect()
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(sort_tree(tags))
+    }
+
+    /// Gets a tag, unless it has been deleted
+    pub fn get(&self, id: usize) -> anyhow::Result<Option<Tag>> {
+        let tag = self.conn.query_row(
+            "SELECT id, name, color, icon, description, parent, rate, currency, billable, archived
+            FROM tags
+            WHERE id = ?1 AND to_delete IS NULL",
+            [id],
+            |row| Self::to_tag(row, 0),
+        );
+
+        match tag {
+            Ok(tag) => Ok(Some(tag)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e).context("Trying to get tag"),
+        }
+    }
+
+    /// Finds a tag by its path, like `ACME / Website`, or by its name if no other tag has it
+    pub fn find(&self, name: &str) -> anyhow::Result<Tag> {
+        let tags = self.all()?;
+        let wanted = path_names(name)?.join(TAG_PATH_SEPARATOR).to_lowercase();
+        if let Some(tag) = tags.iter().find(|t| t.path(&tags).to_lowercase() == wanted) {
+            return Ok(tag.clone());
+        }
+        let mut named = tags.iter().filter(|t| t.name.to_lowercase() == wanted);
+        match (named.next(), named.next()) {
+            (Some(tag), None) => Ok(tag.clone()),
+            (Some(_), Some(_)) => bail!("There is more than one {name} tag, give its path"),
+            (None, _) => bail!("There is no {name} tag"),
+        }
+    }
+
+    /// The tag and every tag nested under it
+    pub fn subtree(&self, tag: &Tag) -> anyhow::Result<Vec<Tag>> {
+        let tags = self.all()?;
+        Ok(tags
+            .iter()
+            .filter(|t| t.is_within(tag, &tags))
+            .cloned()
+            .collect())
     }
 
-    pub fn create(&self, name: &str) -> anyhow::Result<()> {
+    pub fn create(&self, name: &str, parent: Option<usize>) -> anyhow::Result<Tag> {
         info!("Creating tag {name}");
         self.conn
             .execute(
                 "
-                INSERT INTO tags (name)
-                VALUES (?)",
-                [name],
+                INSERT INTO tags (name, parent)
+                VALUES (?1, ?2)",
+                rusqlite::params![name, parent],
             )
-            .map(|_| ())
-            .context("Failed to insert tag into database")
+            .context("Failed to insert tag into database")?;
+        Ok(Tag {
+            id: self.conn.last_insert_rowid() as usize,
+            name: name.to_string(),
+            color: None,
+            icon: None,
+            description: None,
+            parent,
+            rate: None,
+            billable: None,
+            archived: false,
+        })
     }
 
-    pub fn rename(&self, tag: Tag) -> anyhow::Result<()> {
-        info!("Renaming a tag to {}", tag.name);
+    /// Creates the tags in a path like `ACME / Website / QA` that don't exist yet.
+    /// Returns the tags that were created, parents first.
+    pub fn create_path(&self, path: &str) -> anyhow::Result<Vec<Tag>> {
+        let names = path_names(path)?;
+
+        in_transaction(self.conn, || {
+            let mut created = Vec::new();
+            let mut parent = None;
+            for name in names {
+                let existing: Option<usize> = self
+                    .conn
+                    .query_row(
+                        "SELECT id FROM tags
+                        WHERE name = ?1 AND parent IS ?2 AND to_delete IS NULL",
+                        rusqlite::params![name, parent],
+                        |row| row.get(0),
+                    )
+                    .optional()
+                    .context("Trying to find tag")?;
+                parent = match existing {
+                    Some(id) => Some(id),
+                    None => {
+                        let tag = self.create(name, parent)?;
+                        parent = Some(tag.id);
+                        created.push(tag);
+                        parent
+                    }
+                };
+            }
+            Ok(created)
+        })
+    }
+
+    /// Makes sure the tag's parent exists and isn't the tag itself or nested under it
+    fn check_parent(&self, tag: &Tag) -> anyhow::Result<()> {
+        let mut parent = tag.parent;
+        let mut depth = 0;
+        while let Some(id) = parent {
+            if id == tag.id {
+                bail!("{} can't be nested inside itself", tag.name);
+            }
+            depth += 1;
+            if depth > MAX_TAG_DEPTH {
+                bail!("Tags are nested too deeply");
+            }
+            parent = self
+                .conn
+                .query_row("SELECT parent FROM tags WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .context("Trying to find parent tag")?
+                .ok_or_else(|| anyhow!("Parent of {} doesn't exist", tag.name))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the tag, and brings it back if it was deleted
+    pub fn put(&self, tag: &Tag) -> anyhow::Result<()> {
+        in_transaction(self.conn, || {
+            self.update(tag.clone())?;
+            self.conn
+                .execute("UPDATE tags SET to_delete = NULL WHERE id = ?1", [tag.id])
+                .map(|_| ())
+                .with_context(|| format!("Failed to restore tag {}", tag.name))
+        })
+    }
+
+    /// Writes the tag's details, parent, billing and whether it is archived
+    pub fn update(&self, tag: Tag) -> anyhow::Result<()> {
+        info!("Updating tag {}", tag.name);
+        self.check_parent(&tag)?;
         self.conn
             .execute(
-                "UPDATE tags SET name=?2 WHERE id=?1",
-                rusqlite::params![tag.id, tag.name],
+                "UPDATE tags
+                SET name = ?2, color = ?3, icon = ?4, description = ?5, parent = ?6,
+                    rate = ?7, currency = ?8, billable = ?9, archived = ?10
+                WHERE id = ?1",
+                rusqlite::params![
+                    tag.id,
+                    tag.name,
+                    tag.color.map(from_rgb),
+                    tag.icon,
+                    tag.description,
+                    tag.parent,
+                    tag.rate.as_ref().map(|r| r.amount),
+                    tag.rate.as_ref().map(|r| &r.currency),
+                    tag.billable.map(|b| if b { "Y" } else { "N" }),
+                    tag.archived.then_some("Y"),
+                ],
             )
             .map(|_| ())
-            .context("Failed to rename a tag")
+            .context("Failed to update a tag")
     }
 
+    /// Moves all of `from`'s blocks and nested tags into `into`, then deletes `from`
+    pub fn merge(&self, from: &Tag, into: &Tag) -> anyhow::Result<()> {
+        info!("Merging tag {} into {}", from.name, into.name);
+        let tags = self.all()?;
+        if into.is_within(from, &tags) {
+            bail!(
+                "Can't merge {} into itself or a tag nested under it",
+                from.name
+            );
+        }
+
+        in_transaction(self.conn, || {
+            let blocks = Blocks { conn: self.conn };
+            for mut block in blocks.tagged(from)? {
+                block.tag = Some(into.clone());
+                blocks.update_tag(block)?;
+            }
+            self.conn
+                .execute(
+                    "UPDATE tags SET parent = ?2 WHERE parent = ?1",
+                    [from.id, into.id],
+                )
+                .context("Failed to move nested tags")?;
+            self.conn
+                .execute(
+                    "UPDATE rules SET tag = ?2 WHERE tag = ?1",
+                    [from.id, into.id],
+                )
+                .context("Failed to move rules")?;
+            self.conn
+                .execute("UPDATE tags SET to_delete = 'Y' WHERE id = ?1", [from.id])
+                .with_context(|| format!("Failed to delete tag {}", from.name))?;
+            Ok(())
+        })
+    }
+
+    /// Deletes the tag along with every tag nested under it
     pub fn delete(&self, tag: Tag) -> anyhow::Result<()> {
         info!("Deleting tag {}", tag.name);
+        let subtree = self.subtree(&tag)?;
+        in_transaction(self.conn, || {
+            for tag in &subtree {
+                self.conn
+                    .execute("UPDATE tags SET to_delete = 'Y' WHERE id = ?1", [tag.id])
+                    .with_context(|| format!("Failed to delete tag {}", tag.name))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove tags that have been marked for deletion and are no longer found in tags.
+    /// A deleted tag is kept while it still has children, so that their paths stay intact,
+    /// or while a rule uses it.
+    pub fn maintain(&self) -> anyhow::Result<()> {
+        loop {
+            let deleted_tags: Vec<Tag> = self
+                .conn
+                .prepare(
+                    "SELECT
+                        t.id, t.name, t.color, t.icon, t.description, t.parent,
+                        t.rate, t.currency, t.billable, t.archived
+                    FROM tags t
+                    WHERE to_delete = 'Y'
+                        AND NOT EXISTS (SELECT 1 FROM time_blocks b WHERE b.tag = t.id)
+                        AND NOT EXISTS (SELECT 1 FROM tags c WHERE c.parent = t.id)
+                        AND NOT EXISTS (SELECT 1 FROM rules r WHERE r.tag = t.id)",
+                )
+                .context("Preparing to get deleted tags")?
+                .query_map([], |row| Self::to_tag(row, 0))
+                .context("Trying to get deleted tags")?
+                .map(|r| r.context("Failed to map row to Tag struct"))
+                .collect::<anyhow::Result<_>>()?;
+            // removing children can leave their parents removable, so repeat until done
+            if deleted_tags.is_empty() {
+                return Ok(());
+            }
+
+            for tag in deleted_tags {
+                self.conn
+                    .execute(
+                        "DELETE FROM tags WHERE id = ?1 AND to_delete IS NOT NULL",
+                        [tag.id],
+                    )
+                    .with_context(|| format!("Failed to delete tag {}", tag.name))?;
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Key-value store for user settings, values are stored as json
+pub struct StoredSettings<'a> {
+    conn: &'a Connection,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StoredSettings<'_> {
+    fn set<T: serde::Serialize>(&self, key: &str, value: &T) -> anyhow::Result<()> {
+        let value = serde_json::to_string(value)
+            .with_context(|| format!("Failed to serialize setting {key}"))?;
         self.conn
-            .execute("UPDATE tags SET to_delete = 'Y' WHERE id = ?1", [tag.id])
-            .with_context(|| format!("Failed to delete tag {}", tag.name))
+            .execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+                [key, &value],
+            )
             .map(|_| ())
+            .with_context(|| format!("Trying to store setting {key}"))
     }
 
-    /// Remove tags that have been marked for deletion and are no longer found in tags
-    pub fn maintain(&self) -> anyhow::Result<()> {
-        let mut stmt = self
+    /// Stores every setting in `values`, or none of them if one fails
+    pub fn set_all(
+        &self,
+        values: &serde_json::Map<String, serde_json::Value>,
+    ) -> anyhow::Result<()> {
+        in_transaction(self.conn, || {
+            (values.iter()).try_for_each(|(key, value)| self.set(key, value))
+        })
+    }
+
+    /// The hook commands, read whenever a block starts or stops
+    pub fn hooks(&self) -> anyhow::Result<HookSettings> {
+        Ok(self.get(HOOKS_KEY)?.unwrap_or_default())
+    }
+
+    pub fn set_hooks(&self, hooks: &HookSettings) -> anyhow::Result<()> {
+        self.set(HOOKS_KEY, hooks)
+    }
+
+    /// One setting, `None` if it hasn't been stored
+    fn get<T: serde::de::DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        let value: Option<String> = self
             .conn
-            .prepare(
-                "SELECT
-                    t.id, t.name
-                FROM tags t
-                LEFT JOIN time_blocks b ON t.id = b.tag
-                WHERE to_delete = 'Y'
-                    AND b.id IS NULL",
-            )
-            .context("Preparing to get deleted tags")?;
-        let deleted_tags = stmt
+            .query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+                row.get(0)
+            })
+            .optional()
+            .with_context(|| format!("Trying to get setting {key}"))?;
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .with_context(|| format!("Failed to parse setting {key}"))
+    }
+
+    /// All stored settings as a json object
+    pub fn all(&self) -> anyhow::Result<serde_json::Map<String, serde_json::Value>> {
+        self.conn
+            .prepare("SELECT key, value FROM settings")
+            .context("Preparing to get all settings")?
             .query_map([], |row| {
-                Ok(Tag {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                })
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .context("Trying to get all settings")?
+            .map(|r| {
+                let (key, value) = r.context("Trying to read a setting")?;
+                let value = serde_json::from_str(&value)
+                    .with_context(|| format!("Failed to parse setting {key}"))?;
+                Ok((key, value))
             })
-            .context("Trying to get deleted tags")?
-            .map(|r| r.context("Failed to map row to Tag struct"));
+            .collect()
+    }
 
-        for tag in deleted_tags {
-            let tag = tag?;
-            self.conn
-                .execute(
-                    "DELETE FROM tags WHERE id = ?1 AND to_delete IS NOT NULL",
-                    [tag.id],
-                )
-                .with_context(|| format!("Failed to delete tag {}", tag.name))?;
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        self.conn
+            .query_row("SELECT count(*) FROM settings", [], |row| {
+                row.get::<_, usize>(0)
+            })
+            .map(|count| count == 0)
+            .context("Trying to count settings")
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Backups<'a> {
+    conn: &'a Connection,
+    path: Option<&'a Path>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Backups<'_> {
+    fn path(&self) -> anyhow::Result<&Path> {
+        self.path
+            .ok_or(anyhow!("Backups are not available for in memory databases"))
+    }
+
+    /// Back up the database now
+    pub fn create(&self, settings: &BackupSettings) -> anyhow::Result<PathBuf> {
+        let backup = backup::create(self.conn, self.path()?, "manual")?;
+        backup::prune(self.path()?, settings.keep)?;
+        Ok(backup)
+    }
+
+    /// Back up the database if it has not been backed up in the last day
+    pub fn daily(&self, settings: &BackupSettings) -> anyhow::Result<()> {
+        if !settings.daily {
+            return Ok(());
         }
+        backup::daily(self.conn, self.path()?)?;
+        backup::prune(self.path()?, settings.keep)
+    }
 
-        Ok(())
+    /// All backups, newest first
+    pub fn list(&self) -> anyhow::Result<Vec<BackupInfo>> {
+        backup::list(self.path()?)
     }
 }
 
-fn new_disk_connection() -> Result<Connection, anyhow::Error> {
-    //get database dierectory
-    let proj_dirs = directories_next::ProjectDirs::from("", "", APP_NAME)
-        .ok_or(anyhow!("Saving disabled: Failed to find path to data_dir"))?;
-    let data_dir = proj_dirs.data_dir().to_path_buf();
+#[cfg(not(target_arch = "wasm32"))]
+/// Gets a block by id, including blocks in the trash
+fn get_block(conn: &Connection, id: usize) -> Result<Option<Block>, anyhow::Error> {
+    let block = conn.query_row(
+        "
+            SELECT
+                block.id, start, end, running, pomodoro, planned, deleted,
+                tag.id, tag.name, tag.color, tag.icon, tag.description, tag.parent,
+                tag.rate, tag.currency, tag.billable, tag.archived
+            FROM time_blocks block
+            LEFT JOIN tags tag ON block.tag = tag.id
+            WHERE block.id = ?1",
+        [id],
+        Blocks::to_blocks,
+    );
 
-    std::fs::create_dir_all(&data_dir).with_context(|| {
-        format!(
-            "Saving disabled: Failed to create app path at {}",
-            data_dir.display()
-        )
-    })?;
-    let path = data_dir.join("database.sqlite");
+    match block {
+        Ok(block) => Ok(Some(block)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        err => err.map(|_| None).context("Trying to get block"),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Runs `f` in a new transaction, or as part of the caller's transaction if one is open
+fn in_transaction<T>(
+    conn: &Connection,
+    f: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    if !conn.is_autocommit() {
+        return f();
+    }
+    let tx = conn.unchecked_transaction()?;
+    let value = f()?;
+    tx.commit()?;
+    Ok(value)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Colours are stored as `0xRRGGBB`
+fn from_rgb([r, g, b]: [u8; 3]) -> u32 {
+    u32::from_be_bytes([0, r, g, b])
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn to_rgb(color: u32) -> [u8; 3] {
+    let [_, r, g, b] = color.to_be_bytes();
+    [r, g, b]
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Timestamps are stored as seconds since the unix epoch, along with the utc offset
+/// (in seconds) that was in effect when they were recorded.
+fn to_epoch(time: &DateTime<Local>) -> (i64, i32) {
+    (time.timestamp(), time.offset().local_minus_utc())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Reads a timestamp stored by [`to_epoch`]
+fn get_time(row: &rusqlite::Row<'_>, idx: usize) -> Result<DateTime<Local>, rusqlite::Error> {
+    let epoch: i64 = row.get(idx)?;
+    Local
+        .timestamp_opt(epoch, 0)
+        .single()
+        .ok_or(rusqlite::Error::IntegralValueOutOfRange(idx, epoch))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn new_disk_connection(profile: &Profile) -> Result<(Connection, Option<PathBuf>), anyhow::Error> {
+    //get database path
+    let path = profile
+        .database_path()
+        .context("Saving disabled: Failed to find path to database")?;
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).with_context(|| {
+            format!(
+                "Saving disabled: Failed to create app path at {}",
+                dir.display()
+            )
+        })?;
+    }
 
     //open the database
-    Connection::open(&path)
-        .with_context(|| format!("Saving disabled: Failed to open {}", path.display()))
+    let conn = Connection::open(&path)
+        .with_context(|| format!("Saving disabled: Failed to open {}", path.display()))?;
+    Ok((conn, Some(path)))
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 fn new_in_memory_connection() -> Result<Connection, anyhow::Error> {
     Err(anyhow!("TODO - implement in memory fallback"))
 }
+
+/// Helpers for tests in other modules, which can't make blocks of their own
+#[cfg(all(test, not(target_arch = "wasm32")))]
+impl Database {
+    pub(crate) fn in_memory() -> Self {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn, None).unwrap();
+        Self { conn, path: None }
+    }
+
+    /// Adds a stopped block
+    pub(crate) fn add_block(
+        &self,
+        start: DateTime<Local>,
+        end: DateTime<Local>,
+        tag: Option<&Tag>,
+    ) -> Block {
+        let block = Block {
+            id: 0,
+            start,
+            end,
+            tag: tag.cloned(),
+            running: false,
+            pomodoro: false,
+            planned: None,
+            deleted: None,
+        };
+        let blocks = self.blocks();
+        let id = blocks.insert(&block).unwrap();
+        blocks.get(id).unwrap().unwrap()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn, None).unwrap();
+        conn
+    }
+
+    #[test]
+    fn creates_tags_along_a_path() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let created = tags.create_path("ACME / Website / QA").unwrap();
+        assert_eq!(created.len(), 3);
+        let created = tags.create_path("ACME/Website/Design").unwrap();
+        assert_eq!(created.len(), 1);
+
+        let all = tags.all().unwrap();
+        let paths: Vec<String> = all.iter().map(|t| t.path(&all)).collect();
+        assert_eq!(
+            paths,
+            [
+                "ACME",
+                "ACME / Website",
+                "ACME / Website / Design",
+                "ACME / Website / QA"
+            ]
+        );
+    }
+
+    #[test]
+    fn the_same_name_can_be_under_different_parents() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        tags.create_path("ACME / Website").unwrap();
+        let created = tags.create_path("Globex / Website").unwrap();
+        assert_eq!(created.len(), 2);
+        assert!(tags.create("ACME", None).is_err());
+
+        let all = tags.all().unwrap();
+        let paths: Vec<String> = all.iter().map(|t| t.path(&all)).collect();
+        assert_eq!(
+            paths,
+            ["ACME", "ACME / Website", "Globex", "Globex / Website"]
+        );
+    }
+
+    #[test]
+    fn tags_cant_be_nested_in_themselves() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let created = tags.create_path("ACME / Website").unwrap();
+        let mut acme = created[0].clone();
+
+        acme.parent = Some(created[1].id());
+        assert!(tags.update(acme.clone()).is_err());
+        acme.parent = Some(acme.id());
+        assert!(tags.update(acme).is_err());
+    }
+
+    #[test]
+    fn deleting_a_tag_deletes_its_subtree() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let created = tags.create_path("ACME / Website / QA").unwrap();
+        tags.create("Other", None).unwrap();
+        conn.execute(
+            "INSERT INTO time_blocks (start, start_offset, end, end_offset, tag)
+            VALUES (0, 0, 60, 0, ?1)",
+            [created[2].id()],
+        )
+        .unwrap();
+
+        tags.delete(created[1].clone()).unwrap();
+        let names: Vec<String> = tags.all().unwrap().into_iter().map(|t| t.name).collect();
+        assert_eq!(names, ["ACME", "Other"]);
+
+        // QA is still used by a block, so it and its parent are kept
+        tags.maintain().unwrap();
+        let remaining: usize = conn
+            .query_row("SELECT count(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 4);
+
+        conn.execute("DELETE FROM time_blocks", []).unwrap();
+        tags.maintain().unwrap();
+        let remaining: usize = conn
+            .query_row("SELECT count(*) FROM tags", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 2);
+    }
+
+    #[test]
+    fn nested_tags_inherit_billing() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let created = tags.create_path("ACME / Website / QA").unwrap();
+        let mut acme = created[0].clone();
+        acme.rate = Some(Rate {
+            amount: 10000,
+            currency: "USD".to_string(),
+        });
+        tags.update(acme).unwrap();
+        let mut website = created[1].clone();
+        website.billable = Some(false);
+        tags.update(website).unwrap();
+
+        let all = tags.all().unwrap();
+        assert_eq!(all[0].billing_rate(&all).unwrap().amount, 10000);
+        assert!(all[1].billing_rate(&all).is_none());
+        assert!(all[2].billing_rate(&all).is_none());
+        assert_eq!(
+            all[0]
+                .billing_rate(&all)
+                .unwrap()
+                .cost(Duration::minutes(90)),
+            15000
+        );
+    }
+
+    #[test]
+    fn merging_a_tag_moves_its_blocks_and_children() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let from = tags.create_path("Old / Nested").unwrap();
+        let into = tags.create("New", None).unwrap();
+        conn.execute(
+            "INSERT INTO time_blocks (start, start_offset, end, end_offset, tag)
+            VALUES (0, 0, 60, 0, ?1)",
+            [from[0].id()],
+        )
+        .unwrap();
+
+        assert!(tags.merge(&from[0], &from[1]).is_err());
+        tags.merge(&from[0], &into).unwrap();
+        let blocks = Blocks { conn: &conn };
+        assert_eq!(blocks.tagged(&into).unwrap().len(), 1);
+        let all = tags.all().unwrap();
+        let paths: Vec<String> = all.iter().map(|t| t.path(&all)).collect();
+        assert_eq!(paths, ["New", "New / Nested"]);
+    }
+
+    #[test]
+    fn archiving_is_inherited() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let created = tags.create_path("ACME / Website").unwrap();
+        let mut acme = created[0].clone();
+        acme.archived = true;
+        tags.update(acme).unwrap();
+
+        let all = tags.all().unwrap();
+        assert!(all.iter().all(|t| t.is_archived(&all)));
+    }
+
+    #[test]
+    fn queries_blocks_by_tag_time_and_text() {
+        let conn = database();
+        let tags = Tags { conn: &conn };
+        let website = tags.create_path("ACME / Website").unwrap();
+        let other = tags.create("Other", None).unwrap();
+        let day = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let noon = start_of_day(day).timestamp() + 12 * 3600;
+        for (start, minutes, tag) in [(0, 60, website[1].id()), (7200, 5, other.id())] {
+            conn.execute(
+                "INSERT INTO time_blocks (start, start_offset, end, end_offset, tag)
+                VALUES (?1, 0, ?2, 0, ?3)",
+                [noon + start, noon + start + minutes * 60, tag as i64],
+            )
+            .unwrap();
+        }
+        let blocks = Blocks { conn: &conn };
+        let query = |filter: BlockFilter| blocks.query(&filter).unwrap().len();
+
+        assert_eq!(query(BlockFilter::default()), 2);
+        let in_acme = BlockFilter {
+            tags: vec![website[0].clone()],
+            ..Default::default()
+        };
+        assert_eq!(query(in_acme), 1);
+        let long = BlockFilter {
+            min_duration: Some(Duration::minutes(30)),
+            ..Default::default()
+        };
+        assert_eq!(query(long), 1);
+        let text = BlockFilter {
+            text: "acme / web".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(query(text), 1);
+        let later = BlockFilter {
+            from: day.succ_opt(),
+            ..Default::default()
+        };
+        assert_eq!(query(later), 0);
+
+        let all = blocks.query(&BlockFilter::default()).unwrap();
+        blocks.retag_all(&all, Some(&other)).unwrap();
+        assert_eq!(blocks.tagged(&other).unwrap().len(), 2);
+        blocks.delete_all(&all).unwrap();
+        assert_eq!(query(BlockFilter::default()), 0);
+    }
+
+    #[test]
+    fn edits_blocks_together() {
+        let conn = database();
+        let blocks = Blocks { conn: &conn };
+        let now = Local::now().timestamp();
+        for (start, end, running) in [(-7200, -3600, None), (-3000, 0, Some("Y"))] {
+            conn.execute(
+                "INSERT INTO time_blocks (start, start_offset, end, end_offset, running)
+                VALUES (?1, 0, ?2, 0, ?3)",
+                rusqlite::params![now + start, now + end, running],
+            )
+            .unwrap();
+        }
+        let all = blocks.query(&BlockFilter::default()).unwrap();
+
+        let shift = BlockEdit::Shift(Duration::minutes(-5));
+        assert!(shift.apply(&all).is_err());
+        assert!(BlockEdit::Merge.apply(&all[..1]).is_err());
+
+        blocks.edit_all(&all, &BlockEdit::Merge).unwrap();
+        let merged = blocks.query(&BlockFilter::default()).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].duration(), Duration::hours(2));
+        assert_eq!(blocks.current().unwrap().unwrap().id(), merged[0].id());
+    }
+
+    #[test]
+    fn splits_and_merges_blocks() {
+        let conn = database();
+        let blocks = Blocks { conn: &conn };
+        let tag = Tags { conn: &conn }.create("Tagged", None).unwrap();
+        let now = Local::now().timestamp();
+        for (start, end, running) in [(-7200, -5400, None), (-3600, 0, Some("Y"))] {
+            conn.execute(
+                "INSERT INTO time_blocks (start, start_offset, end, end_offset, running)
+                VALUES (?1, 0, ?2, 0, ?3)",
+                rusqlite::params![now + start, now + end, running],
+            )
+            .unwrap();
+        }
+        let all = blocks.query(&BlockFilter::default()).unwrap();
+        let (running, earlier) = (&all[0], &all[1]);
+
+        let at = running.start + Duration::minutes(30);
+        assert!(blocks.split(running, running.start, [None, None]).is_err());
+        let (first, second) = blocks
+            .split(running, at, [Some(tag.clone()), None])
+            .unwrap();
+        assert_eq!(first.duration(), Duration::minutes(30));
+        assert_eq!(first.tag, Some(tag));
+        assert_eq!(blocks.current().unwrap().unwrap().id(), second.id());
+
+        // the running block comes after the first part, so they can't merge
+        assert!(blocks.merge(earlier, &second).is_err());
+        assert!(blocks.merge(&second, &first).is_ok());
+        let merged = blocks.merge(earlier, &first).unwrap();
+        assert_eq!(merged.start, earlier.start);
+        assert!(merged.running);
+        assert_eq!(blocks.query(&BlockFilter::default()).unwrap().len(), 1);
+    }
+
+    /// Inserts blocks from `start` to `end`, in seconds from an hour ago
+    fn insert_blocks(conn: &Connection, times: &[(i64, i64)]) -> Vec<Block> {
+        let hour_ago = Local::now().timestamp() - 3600;
+        for (start, end) in times {
+            conn.execute(
+                "INSERT INTO time_blocks (start, start_offset, end, end_offset)
+                VALUES (?1, 0, ?2, 0)",
+                [hour_ago + start, hour_ago + end],
+            )
+            .unwrap();
+        }
+        let mut blocks = Blocks { conn }.query(&BlockFilter::default()).unwrap();
+        blocks.reverse();
+        blocks
+    }
+
+    #[test]
+    fn blocks_that_overlap_arent_merged() {
+        let conn = database();
+        let blocks = Blocks { conn: &conn };
+
+        let overlapping = insert_blocks(&conn, &[(0, 1200), (600, 1800)]);
+        assert!(blocks.merge(&overlapping[0], &overlapping[1]).is_err());
+
+        conn.execute("DELETE FROM time_blocks", []).unwrap();
+        let nested = insert_blocks(&conn, &[(0, 2400), (600, 1200)]);
+        assert!(blocks.merge(&nested[0], &nested[1]).is_err());
+        let outer = blocks.get(nested[0].id()).unwrap().unwrap();
+        assert_eq!(outer.duration(), Duration::minutes(40));
+        assert!(blocks
+            .get(nested[1].id())
+            .unwrap()
+            .unwrap()
+            .deleted
+            .is_none());
+    }
+
+    #[test]
+    fn blocks_starting_together_are_between() {
+        let conn = database();
+        let blocks = Blocks { conn: &conn };
+        // the second block starts with the first, so it lies between the first and third
+        let inserted = insert_blocks(&conn, &[(0, 600), (0, 300), (1200, 1800)]);
+        let first = inserted
+            .iter()
+            .find(|b| b.duration() == Duration::minutes(10))
+            .unwrap();
+        let third = inserted.iter().find(|b| b.start > first.start).unwrap();
+        assert!(blocks.merge(first, third).is_err());
+    }
+
+    #[test]
+    fn bulk_edits_leave_other_blocks_alone() {
+        let conn = database();
+        let blocks = Blocks { conn: &conn };
+        let inserted = insert_blocks(&conn, &[(0, 600), (900, 1200), (1500, 1800)]);
+        let (first, second, third) = (&inserted[0], &inserted[1], &inserted[2]);
+
+        let merge = [first.clone(), third.clone()];
+        assert!(blocks.edit_all(&merge, &BlockEdit::Merge).is_err());
+        let shift = BlockEdit::Shift(Duration::minutes(10));
+        assert!(blocks.edit_all(&inserted[..1], &shift).is_err());
+        assert!(blocks.edit_all(&inserted[..2], &shift).is_err());
+        assert_eq!(blocks.query(&BlockFilter::default()).unwrap().len(), 3);
+
+        let shift = BlockEdit::Shift(Duration::minutes(5));
+        blocks.edit_all(&inserted[..2], &shift).unwrap();
+        let merge = [second.clone(), third.clone()];
+        blocks.edit_all(&merge, &BlockEdit::Merge).unwrap();
+        let merged = blocks.get(second.id()).unwrap().unwrap();
+        assert_eq!(merged.start, second.start + Duration::minutes(5));
+        assert_eq!(merged.end, third.end);
+    }
+}
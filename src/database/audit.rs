@@ -0,0 +1,240 @@
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use rusqlite::Connection;
+
+use super::{get_block, get_time, in_transaction, Block};
+
+/// A recorded change to a block, with its state before and after
+#[derive(Clone, Debug)]
+pub struct BlockChange {
+    pub block: usize,
+    pub changed: DateTime<Local>,
+    /// What caused the change, e.g. `start` or `retag`
+    pub action: String,
+    /// `None` if the block didn't exist before the change
+    pub before: Option<Block>,
+    /// `None` if the block was removed by the change
+    pub after: Option<Block>,
+}
+
+impl BlockChange {
+    /// Lists what the change did, e.g. `tag Work -> Chores, end 10:00 -> 10:30`
+    pub fn describe(&self, time_format: &str) -> String {
+        let (before, after) = match (&self.before, &self.after) {
+            (None, Some(after)) => {
+                return format!(
+                    "created {} -> {}, {}",
+                    after.start.format(time_format),
+                    after.end.format(time_format),
+                    tag_name(after)
+                )
+            }
+            (Some(_), None) => return "removed".to_string(),
+            (None, None) => return String::new(),
+            (Some(before), Some(after)) => (before, after),
+        };
+
+        let mut changes = Vec::new();
+        if before.start != after.start {
+            changes.push(format!(
+                "start {} -> {}",
+                before.start.format(time_format),
+                after.start.format(time_format)
+            ));
+        }
+        if before.end != after.end {
+            changes.push(format!(
+                "end {} -> {}",
+                before.end.format(time_format),
+                after.end.format(time_format)
+            ));
+        }
+        if before.tag != after.tag || tag_name(before) != tag_name(after) {
+            changes.push(format!("tag {} -> {}", tag_name(before), tag_name(after)));
+        }
+        if before.running && !after.running {
+            changes.push("stopped".to_string());
+        } else if !before.running && after.running {
+            changes.push("running again".to_string());
+        }
+        if !before.pomodoro && after.pomodoro {
+            changes.push("completed pomodoro".to_string());
+        }
+        match (before.deleted, after.deleted) {
+            (None, Some(_)) => changes.push("moved to trash".to_string()),
+            (Some(_), None) => changes.push("restored from trash".to_string()),
+            _ => (),
+        }
+
+        if changes.is_empty() {
+            "no change".to_string()
+        } else {
+            changes.join(", ")
+        }
+    }
+}
+
+fn tag_name(block: &Block) -> &str {
+    block.tag.as_ref().map_or("untagged", |t| &t.name)
+}
+
+/// Read access to the `block_history` table, which is only ever appended to
+pub struct BlockHistory<'a> {
+    pub(super) conn: &'a Connection,
+}
+
+impl BlockHistory<'_> {
+    /// Changes to one block, oldest first
+    pub fn for_block(&self, block: usize) -> anyhow::Result<Vec<BlockChange>> {
+        self.conn
+            .prepare(
+                "SELECT block, changed, action, before, after
+                FROM block_history
+                WHERE block = ?1
+                ORDER BY id",
+            )
+            .context("Preparing to get block history")?
+            .query_map([block], to_change)
+            .context("Trying to get block history")?
+            .map(|r| r.context("Trying to map row to BlockChange struct"))
+            .collect()
+    }
+
+    /// The most recent changes to any block, oldest first
+    pub fn recent(&self, limit: usize) -> anyhow::Result<Vec<BlockChange>> {
+        let mut changes = self
+            .conn
+            .prepare(
+                "SELECT block, changed, action, before, after
+                FROM block_history
+                ORDER BY id DESC
+                LIMIT ?1",
+            )
+            .context("Preparing to get block history")?
+            .query_map([limit], to_change)
+            .context("Trying to get block history")?
+            .map(|r| r.context("Trying to map row to BlockChange struct"))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        changes.reverse();
+        Ok(changes)
+    }
+}
+
+fn to_change(row: &rusqlite::Row<'_>) -> Result<BlockChange, rusqlite::Error> {
+    let parse = |idx: usize| -> Result<Option<Block>, rusqlite::Error> {
+        let json: Option<String> = row.get(idx)?;
+        json.map(|json| serde_json::from_str(&json))
+            .transpose()
+            .map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    idx,
+                    rusqlite::types::Type::Text,
+                    e.into(),
+                )
+            })
+    };
+    Ok(BlockChange {
+        block: row.get(0)?,
+        changed: get_time(row, 1)?,
+        action: row.get(2)?,
+        before: parse(3)?,
+        after: parse(4)?,
+    })
+}
+
+/// Runs `change`, then records how it changed the block.
+/// Both happen in one transaction, so the history can't miss a change.
+pub(super) fn logged(
+    conn: &Connection,
+    block: usize,
+    action: &str,
+    change: impl FnOnce() -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    in_transaction(conn, || {
+        let before = get_block(conn, block)?;
+        change()?;
+        let after = get_block(conn, block)?;
+        record(conn, block, action, before.as_ref(), after.as_ref())
+    })
+}
+
+/// Appends a change to the history. Should be called in the same transaction as the change.
+pub(super) fn record(
+    conn: &Connection,
+    block: usize,
+    action: &str,
+    before: Option<&Block>,
+    after: Option<&Block>,
+) -> anyhow::Result<()> {
+    let to_json = |block: Option<&Block>| block.map(serde_json::to_string).transpose();
+    conn.execute(
+        "INSERT INTO block_history (block, changed, action, before, after)
+        VALUES (?1, ?2, ?3, ?4, ?5)",
+        rusqlite::params![
+            block,
+            Local::now().timestamp(),
+            action,
+            to_json(before).context("Failed to serialize block")?,
+            to_json(after).context("Failed to serialize block")?,
+        ],
+    )
+    .map(|_| ())
+    .context("Trying to record block history")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{migrations, Blocks, StopWatch};
+    use super::*;
+
+    fn database() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn, None).unwrap();
+        conn
+    }
+
+    #[test]
+    fn records_every_change_to_a_block() {
+        let conn = database();
+        let stopwatch = StopWatch {
+            conn: &conn,
+            now: Local::now(),
+        };
+        stopwatch.start(None).unwrap();
+        stopwatch.stop().unwrap();
+        let history = BlockHistory { conn: &conn };
+        let changes = history.recent(10).unwrap();
+        let actions: Vec<_> = changes.iter().map(|c| c.action.as_str()).collect();
+        assert_eq!(actions, ["start", "stop"]);
+        assert!(changes[0].before.is_none());
+        assert!(changes[1].before.as_ref().unwrap().running);
+        assert!(!changes[1].after.as_ref().unwrap().running);
+
+        let block = changes[1].after.clone().unwrap();
+        let blocks = Blocks { conn: &conn };
+        blocks.delete(block.clone()).unwrap();
+        blocks.purge(block.clone()).unwrap();
+        let changes = history.for_block(block.id()).unwrap();
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[2].describe("%H:%M"), "moved to trash");
+        assert!(changes[3].after.is_none());
+    }
+
+    #[test]
+    fn history_is_append_only() {
+        let conn = database();
+        record(&conn, 1, "start", None, None).unwrap();
+        assert!(conn.execute("DELETE FROM block_history", []).is_err());
+        assert!(conn
+            .execute("UPDATE block_history SET action = 'edit'", [])
+            .is_err());
+    }
+
+    #[test]
+    fn failed_change_is_not_recorded() {
+        let conn = database();
+        let result = logged(&conn, 1, "edit", || anyhow::bail!("failed"));
+        assert!(result.is_err());
+        assert!(BlockHistory { conn: &conn }.recent(10).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, TimeZone};
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, DatabaseName, OpenFlags};
+use tracing::{info, warn};
+
+const BACKUP_DIR: &str = "backups";
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+/// Backups that are pruned, the ones made before migrations and restores are always kept
+const PRUNED_REASONS: &[&str] = &["daily", "manual"];
+
+/// A copy of the database in the backups directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub path: PathBuf,
+    pub created: DateTime<Local>,
+    /// Why the backup was made, e.g. `daily` or `pre-v3`
+    pub reason: String,
+    pub blocks: usize,
+    pub first: Option<DateTime<Local>>,
+    pub last: Option<DateTime<Local>>,
+}
+
+impl BackupInfo {
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Copies the database into the backup directory, using sqlite's online backup api
+pub(super) fn create(conn: &Connection, db_path: &Path, reason: &str) -> anyhow::Result<PathBuf> {
+    let dir = backup_dir(db_path)?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create backup directory {}", dir.display()))?;
+
+    let path = dir.join(format!(
+        "{}-{}-{}.sqlite",
+        file_stem(db_path)?,
+        Local::now().format(TIMESTAMP_FORMAT),
+        reason
+    ));
+    conn.backup(DatabaseName::Main, &path, None)
+        .with_context(|| format!("Failed to back up database to {}", path.display()))?;
+
+    info!("Backed up database to {}", path.display());
+    Ok(path)
+}
+
+/// All backups of the database, newest first
+pub(super) fn list(db_path: &Path) -> anyhow::Result<Vec<BackupInfo>> {
+    let dir = backup_dir(db_path)?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let stem = file_stem(db_path)?;
+
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(&dir).context("Reading backup directory")? {
+        let path = entry.context("Reading backup directory")?.path();
+        let Some((created, reason)) = parse_name(&path, &stem) else {
+            continue;
+        };
+        match read_info(&path, created, reason) {
+            Ok(info) => backups.push(info),
+            Err(e) => warn!("Skipping unreadable backup {}: {e:#}", path.display()),
+        }
+    }
+
+    backups.sort_by_key(|backup| Reverse(backup.created));
+    Ok(backups)
+}
+
+/// Makes a daily backup, unless one was made in the last day
+pub(super) fn daily(conn: &Connection, db_path: &Path) -> anyhow::Result<()> {
+    let dir = backup_dir(db_path)?;
+    let stem = file_stem(db_path)?;
+    let latest = std::fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| parse_name(&entry.ok()?.path(), &stem))
+        .filter(|(_, reason)| reason == "daily")
+        .map(|(created, _)| created)
+        .max();
+
+    match latest {
+        Some(latest) if Local::now() - latest < Duration::days(1) => Ok(()),
+        _ => create(conn, db_path, "daily").map(|_| ()),
+    }
+}
+
+/// Deletes all but the newest `keep` daily and manual backups
+pub(super) fn prune(db_path: &Path, keep: usize) -> anyhow::Result<()> {
+    let dir = backup_dir(db_path)?;
+    let stem = file_stem(db_path)?;
+    let mut backups: Vec<(DateTime<Local>, PathBuf)> = std::fs::read_dir(&dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let (created, reason) = parse_name(&path, &stem)?;
+            PRUNED_REASONS
+                .contains(&reason.as_str())
+                .then_some((created, path))
+        })
+        .collect();
+    backups.sort_by_key(|(created, _)| Reverse(*created));
+
+    for (_, path) in backups.into_iter().skip(keep) {
+        info!("Removing old backup {}", path.display());
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove backup {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Replaces the contents of the database with a backup
+pub(super) fn restore(conn: &mut Connection, backup: &Path) -> anyhow::Result<()> {
+    if !backup.is_file() {
+        bail!("Backup {} does not exist", backup.display());
+    }
+    conn.restore(
+        DatabaseName::Main,
+        backup,
+        None::<fn(rusqlite::backup::Progress)>,
+    )
+    .with_context(|| format!("Failed to restore backup {}", backup.display()))?;
+    info!("Restored backup {}", backup.display());
+    Ok(())
+}
+
+fn backup_dir(db_path: &Path) -> anyhow::Result<PathBuf> {
+    let parent = db_path
+        .parent()
+        .ok_or_else(|| anyhow!("Database path {} has no parent", db_path.display()))?;
+    Ok(parent.join(BACKUP_DIR))
+}
+
+fn file_stem(db_path: &Path) -> anyhow::Result<String> {
+    db_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow!("Database path {} has no file name", db_path.display()))
+}
+
+/// Reads the creation time and reason out of a backup's file name
+fn parse_name(path: &Path, stem: &str) -> Option<(DateTime<Local>, String)> {
+    let name = path.file_stem()?.to_str()?;
+    let rest = name.strip_prefix(stem)?.strip_prefix('-')?;
+    // timestamps have the fixed width of `YYYYmmdd-HHMMSS`
+    let (timestamp, reason) = (rest.get(..15)?, rest.get(16..)?);
+    let created = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+    let created = Local.from_local_datetime(&created).earliest()?;
+    Some((created, reason.to_string()))
+}
+
+/// Counts the blocks in a backup, and finds when the first and last ones started
+fn read_info(path: &Path, created: DateTime<Local>, reason: String) -> anyhow::Result<BackupInfo> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .context("Failed to open backup")?;
+    conn.query_row(
+        "SELECT count(*), min(start), max(start) FROM time_blocks",
+        [],
+        |row| {
+            Ok(BackupInfo {
+                path: path.to_path_buf(),
+                created,
+                reason,
+                blocks: row.get(0)?,
+                first: get_any_time(row, 1)?,
+                last: get_any_time(row, 2)?,
+            })
+        },
+    )
+    .context("Failed to read blocks from backup")
+}
+
+/// Reads a timestamp from a backup, which may predate storing timestamps as epoch seconds
+fn get_any_time(
+    row: &rusqlite::Row<'_>,
+    idx: usize,
+) -> Result<Option<DateTime<Local>>, rusqlite::Error> {
+    match row.get_ref(idx)? {
+        ValueRef::Integer(epoch) => Ok(Local.timestamp_opt(epoch, 0).single()),
+        ValueRef::Null => Ok(None),
+        _ => row.get(idx),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database(path: &Path) -> Connection {
+        let mut conn = Connection::open(path).unwrap();
+        super::super::migrations::migrate(&mut conn, None).unwrap();
+        conn
+    }
+
+    fn add_block(conn: &Connection) {
+        conn.execute(
+            "INSERT INTO time_blocks (start, start_offset, end, end_offset) \
+             VALUES (1704099600, 3600, 1704103200, 3600)",
+            [],
+        )
+        .unwrap();
+    }
+
+    fn count_blocks(conn: &Connection) -> usize {
+        conn.query_row("SELECT count(*) FROM time_blocks", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    /// Backs up to a file named as if it was made at `timestamp`
+    fn backup_at(conn: &Connection, db_path: &Path, timestamp: &str, reason: &str) -> PathBuf {
+        let dir = backup_dir(db_path).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("timekeeper-{timestamp}-{reason}.sqlite"));
+        conn.backup(DatabaseName::Main, &path, None).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_backup_names() {
+        let (created, reason) = parse_name(
+            Path::new("timekeeper-20240101-093000-pre-v3.sqlite"),
+            "timekeeper",
+        )
+        .unwrap();
+        assert_eq!(created.format("%F %T").to_string(), "2024-01-01 09:30:00");
+        assert_eq!(reason, "pre-v3");
+
+        for name in [
+            "other-20240101-093000-daily.sqlite",
+            "timekeeper-20240101-daily.sqlite",
+            "timekeeper-20241301-093000-daily.sqlite",
+            "timekeeper.sqlite",
+        ] {
+            assert!(
+                parse_name(Path::new(name), "timekeeper").is_none(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn lists_and_prunes_backups() {
+        let dir = std::env::temp_dir().join(format!("timekeeper-backup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("timekeeper.sqlite");
+        let conn = database(&db_path);
+        add_block(&conn);
+
+        backup_at(&conn, &db_path, "20240101-090000", "pre-v3");
+        backup_at(&conn, &db_path, "20240102-090000", "daily");
+        backup_at(&conn, &db_path, "20240103-090000", "pre-restore");
+        backup_at(&conn, &db_path, "20240104-090000", "manual");
+        backup_at(&conn, &db_path, "20240105-090000", "daily");
+        std::fs::write(backup_dir(&db_path).unwrap().join("notes.txt"), "").unwrap();
+
+        let backups = list(&db_path).unwrap();
+        let reasons: Vec<&str> = backups.iter().map(|b| &*b.reason).collect();
+        assert_eq!(
+            reasons,
+            ["daily", "manual", "pre-restore", "daily", "pre-v3"]
+        );
+        assert_eq!(backups[0].blocks, 1);
+        assert_eq!(backups[0].first, backups[0].last);
+        assert!(backups[0].first.is_some());
+
+        prune(&db_path, 1).unwrap();
+        let reasons: Vec<String> = list(&db_path)
+            .unwrap()
+            .into_iter()
+            .map(|b| b.reason)
+            .collect();
+        assert_eq!(reasons, ["daily", "pre-restore", "pre-v3"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn restores_a_backup() {
+        let dir = std::env::temp_dir().join(format!("timekeeper-restore-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("timekeeper.sqlite");
+        let mut conn = database(&db_path);
+        add_block(&conn);
+
+        let backup = create(&conn, &db_path, "manual").unwrap();
+        add_block(&conn);
+        assert_eq!(count_blocks(&conn), 2);
+
+        restore(&mut conn, &backup).unwrap();
+        assert_eq!(count_blocks(&conn), 1);
+        assert!(restore(&mut conn, &dir.join("missing.sqlite")).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
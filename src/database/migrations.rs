@@ -1,17 +1,281 @@
-use anyhow::Context;
-use rusqlite::Connection;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, FixedOffset};
+use rusqlite::{Connection, Transaction};
 use tracing::info;
 
-pub fn migrate(connection: &mut Connection) -> anyhow::Result<()> {
+use super::backup;
+
+/// A step that moves the database from the previous version to `version`
+struct Migration {
+    version: usize,
+    description: &'static str,
+    up: fn(&Transaction<'_>) -> anyhow::Result<()>,
+}
+
+/// Every migration in order. Only ever append to this list, and update `SCHEMA` to match.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Creating initial database",
+        up: v0_to_v1,
+    },
+    Migration {
+        version: 2,
+        description: "Adding app_info table and tag deletion",
+        up: v1_to_v2,
+    },
+    Migration {
+        version: 3,
+        description: "Adding pomodoros to blocks",
+        up: v2_to_v3,
+    },
+    Migration {
+        version: 4,
+        description: "Adding planned durations to blocks",
+        up: v3_to_v4,
+    },
+    Migration {
+        version: 5,
+        description: "Adding settings table",
+        up: v4_to_v5,
+    },
+    Migration {
+        version: 6,
+        description: "Storing block times as utc epoch seconds",
+        up: v5_to_v6,
+    },
+    Migration {
+        version: 7,
+        description: "Adding trash for blocks",
+        up: v6_to_v7,
+    },
+    Migration {
+        version: 8,
+        description: "Adding block history",
+        up: v7_to_v8,
+    },
+    Migration {
+        version: 9,
+        description: "Adding tag colors, icons and descriptions",
+        up: v8_to_v9,
+    },
+    Migration {
+        version: 10,
+        description: "Adding tag hierarchy",
+        up: v9_to_v10,
+    },
+    Migration {
+        version: 11,
+        description: "Adding billing rates to tags",
+        up: v10_to_v11,
+    },
+    Migration {
+        version: 12,
+        description: "Adding archived tags",
+        up: v11_to_v12,
+    },
+    Migration {
+        version: 13,
+        description: "Adding scheduled rules",
+        up: v12_to_v13,
+    },
+    Migration {
+        version: 14,
+        description: "Allowing the same tag name under different parents",
+        up: v13_to_v14,
+    },
+];
+
+/// Tables and their columns, as they should be after all migrations have run
+const SCHEMA: &[(&str, &[&str])] = &[
+    (
+        "tags",
+        &[
+            "id",
+            "name",
+            "to_delete",
+            "color",
+            "icon",
+            "description",
+            "parent",
+            "rate",
+            "currency",
+            "billable",
+            "archived",
+        ],
+    ),
+    (
+        "time_blocks",
+        &[
+            "id",
+            "start",
+            "start_offset",
+            "end",
+            "end_offset",
+            "running",
+            "tag",
+            "pomodoro",
+            "planned",
+            "deleted",
+        ],
+    ),
+    ("app_info", &["id", "key", "value"]),
+    ("settings", &["key", "value"]),
+    (
+        "block_history",
+        &["id", "block", "changed", "action", "before", "after"],
+    ),
+    (
+        "rules",
+        &["id", "action", "time", "days", "tag", "planned", "enabled"],
+    ),
+    (
+        "rule_firings",
+        &["id", "rule", "fired", "description", "outcome"],
+    ),
+];
+
+/// Indexes by table, as they should be after all migrations have run
+const INDEXES: &[(&str, &[&str])] = &[
+    ("tags", &["tags_parent_name"]),
+    ("time_blocks", &["time_blocks_start"]),
+    ("block_history", &["block_history_block"]),
+];
+
+fn latest_version() -> usize {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+/// Brings the database up to the latest version, then checks it has the expected schema.
+/// If `path` is given, the database is backed up before any changes are made.
+pub fn migrate(connection: &mut Connection, path: Option<&Path>) -> anyhow::Result<()> {
     let version = database_version(connection)?;
+    let latest = latest_version();
 
-    if version < 1 {
-        v0_to_v1(connection).context("Creating initial database")?;
+    if version > latest {
+        bail!(
+            "Database is version {version}, but {} only supports up to version {latest}. \
+            Please update the app.",
+            crate::APP_NAME
+        );
+    }
+
+    if version > 0 && version < latest {
+        if let Some(path) = path {
+            backup::create(connection, path, &format!("pre-v{}", version + 1))
+                .context("Backing up database before migrating")?;
+        }
     }
+
+    migrate_to(connection, version, latest)?;
+    verify_schema(connection).context("Database schema is not what was expected")
+}
+
+/// Runs the migrations after `version`, up to and including `target`
+fn migrate_to(connection: &mut Connection, version: usize, target: usize) -> anyhow::Result<()> {
+    // tables other tables refer to can only be rebuilt with foreign keys off, which can't be
+    // changed inside a transaction. They are checked before each migration commits instead.
+    let foreign_keys: bool =
+        connection.pragma_query_value(None, "foreign_keys", |row| row.get(0))?;
+    connection.pragma_update(None, "foreign_keys", false)?;
+    let migrated = run_migrations(connection, version, target);
+    connection.pragma_update(None, "foreign_keys", foreign_keys)?;
+    migrated
+}
+
+/// Rows that refer to rows that don't exist
+fn foreign_key_violations(conn: &Connection) -> anyhow::Result<usize> {
+    conn.query_row("SELECT count(*) FROM pragma_foreign_key_check", [], |row| {
+        row.get(0)
+    })
+    .context("Checking foreign keys")
+}
+
+fn run_migrations(
+    connection: &mut Connection,
+    version: usize,
+    target: usize,
+) -> anyhow::Result<()> {
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|m| m.version > version && m.version <= target)
+    {
+        info!(
+            "Migrating to database version {}: {}",
+            migration.version, migration.description
+        );
+        let tx = connection.transaction()?;
+        let violations = foreign_key_violations(&tx)?;
+        (migration.up)(&tx).context(migration.description)?;
+        set_version(&tx, migration.version)
+            .with_context(|| format!("Failed to set database version to {}", migration.version))?;
+        // databases from before foreign keys were enforced may already have some
+        if foreign_key_violations(&tx)? > violations {
+            bail!(
+                "{} left rows referring to missing rows",
+                migration.description
+            );
+        }
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Records the version in `app_info`.
+/// Version 1 predates `app_info`, and is detected from its tables instead.
+fn set_version(tx: &Transaction<'_>, version: usize) -> anyhow::Result<()> {
     if version < 2 {
-        v1_to_v2(connection).context("Migrate to database version 2")?;
+        return Ok(());
+    }
+    let updated = tx.execute(
+        r#"UPDATE app_info SET value = ?2 WHERE key = ?1"#,
+        rusqlite::params!["version", version],
+    )?;
+    if updated == 0 {
+        tx.execute(
+            r#"INSERT INTO app_info (key, value) VALUES (?1, ?2)"#,
+            rusqlite::params!["version", version],
+        )?;
+    }
+    Ok(())
+}
+
+/// Checks that every table in `SCHEMA` exists with exactly the expected columns, and has the
+/// indexes in `INDEXES`
+fn verify_schema(conn: &Connection) -> anyhow::Result<()> {
+    for (table, expected) in SCHEMA {
+        let mut columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info(?1)")?
+            .query_map([table], |row| row.get(0))?
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Reading columns of `{table}`"))?;
+        if columns.is_empty() {
+            bail!("Table `{table}` is missing");
+        }
+
+        let mut expected: Vec<&str> = expected.to_vec();
+        columns.sort();
+        expected.sort();
+        if columns != expected {
+            bail!("Table `{table}` has columns {columns:?}, expected {expected:?}");
+        }
     }
 
+    for (table, expected) in INDEXES {
+        let indexes: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = ?1")?
+            .query_map([table], |row| row.get(0))?
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("Reading indexes of `{table}`"))?;
+        if let Some(missing) = expected
+            .iter()
+            .find(|i| !indexes.iter().any(|name| name == *i))
+        {
+            bail!("Table `{table}` is missing index `{missing}`");
+        }
+    }
     Ok(())
 }
 
@@ -53,9 +317,7 @@ fn database_version(conn: &Connection) -> anyhow::Result<usize> {
     }
 }
 
-fn v0_to_v1(conn: &mut Connection) -> anyhow::Result<()> {
-    info!("Initializing database");
-    let tx = conn.transaction()?;
+fn v0_to_v1(tx: &Transaction<'_>) -> anyhow::Result<()> {
     tx.execute(
         r#"CREATE TABLE "tags" (
             "id"	INTEGER NOT NULL,
@@ -81,14 +343,10 @@ fn v0_to_v1(conn: &mut Connection) -> anyhow::Result<()> {
     )
     .context("Failed to initailize time_blocks table")?;
 
-    tx.commit()?;
-
     Ok(())
 }
 
-fn v1_to_v2(conn: &mut Connection) -> anyhow::Result<()> {
-    info!("Migrating to database version 2");
-    let tx = conn.transaction()?;
+fn v1_to_v2(tx: &Transaction<'_>) -> anyhow::Result<()> {
     tx.execute(
         r#"CREATE TABLE "app_info" (
         "id" INTEGER NOT NULL,
@@ -108,13 +366,381 @@ fn v1_to_v2(conn: &mut Connection) -> anyhow::Result<()> {
     )
     .context("Failed to add `to_delete` column to tags table")?;
 
+    Ok(())
+}
+
+fn v2_to_v3(tx: &Transaction<'_>) -> anyhow::Result<()> {
     tx.execute(
-        r#"INSERT INTO app_info (key, value) VALUES (?1, ?2)"#,
-        rusqlite::params!["version", 2],
+        r#"ALTER TABLE time_blocks ADD pomodoro CHECK("pomodoro" = 'Y')"#,
+        [],
     )
-    .context("failed to set database version")?;
+    .context("Failed to add `pomodoro` column to time_blocks table")?;
 
-    tx.commit()?;
+    Ok(())
+}
+
+fn v3_to_v4(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(r#"ALTER TABLE time_blocks ADD planned INTEGER"#, [])
+        .context("Failed to add `planned` column to time_blocks table")?;
 
     Ok(())
 }
+
+fn v4_to_v5(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "settings" (
+        "key" TEXT NOT NULL,
+        "value" TEXT NOT NULL,
+        PRIMARY KEY("key")
+    );"#,
+        [],
+    )
+    .context("failed to create settings table")?;
+
+    Ok(())
+}
+
+fn v5_to_v6(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r#"
+        CREATE TABLE "time_blocks_v6" (
+            "id"	INTEGER,
+            "start"	INTEGER NOT NULL,
+            "start_offset"	INTEGER NOT NULL,
+            "end"	INTEGER NOT NULL,
+            "end_offset"	INTEGER NOT NULL,
+            "running"	TEXT CHECK("running" = 'Y') UNIQUE,
+            "tag"	INTEGER,
+            "pomodoro"	TEXT CHECK("pomodoro" = 'Y'),
+            "planned"	INTEGER,
+            FOREIGN KEY("tag") REFERENCES "tags"("id"),
+            PRIMARY KEY("id")
+    );"#,
+        [],
+    )
+    .context("Failed to create new time_blocks table")?;
+
+    // sqlite can't reliably parse chrono's timestamps, so convert them here
+    let mut select =
+        tx.prepare("SELECT id, start, end, running, tag, pomodoro, planned FROM time_blocks")?;
+    let mut insert = tx.prepare(
+        "INSERT INTO time_blocks_v6
+        (id, start, start_offset, end, end_offset, running, tag, pomodoro, planned)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let start: DateTime<FixedOffset> = row
+            .get(1)
+            .with_context(|| format!("Failed to read start of block {id}"))?;
+        let end: DateTime<FixedOffset> = row
+            .get(2)
+            .with_context(|| format!("Failed to read end of block {id}"))?;
+        insert
+            .execute(rusqlite::params![
+                id,
+                start.timestamp(),
+                start.offset().local_minus_utc(),
+                end.timestamp(),
+                end.offset().local_minus_utc(),
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<i64>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, Option<i64>>(6)?,
+            ])
+            .with_context(|| format!("Failed to convert block {id}"))?;
+    }
+
+    tx.execute("DROP TABLE time_blocks", [])
+        .context("Failed to drop old time_blocks table")?;
+    tx.execute("ALTER TABLE time_blocks_v6 RENAME TO time_blocks", [])
+        .context("Failed to rename new time_blocks table")?;
+    tx.execute(
+        r#"CREATE INDEX "time_blocks_start" ON "time_blocks" ("start")"#,
+        [],
+    )
+    .context("Failed to index block start times")?;
+
+    Ok(())
+}
+
+fn v6_to_v7(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(r#"ALTER TABLE time_blocks ADD deleted INTEGER"#, [])
+        .context("Failed to add `deleted` column to time_blocks table")?;
+
+    Ok(())
+}
+
+fn v7_to_v8(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "block_history" (
+        "id" INTEGER NOT NULL,
+        "block" INTEGER NOT NULL,
+        "changed" INTEGER NOT NULL,
+        "action" TEXT NOT NULL,
+        "before" TEXT,
+        "after" TEXT,
+        PRIMARY KEY("id")
+    );"#,
+        [],
+    )
+    .context("failed to create block_history table")?;
+    tx.execute(
+        r#"CREATE INDEX "block_history_block" ON "block_history" ("block")"#,
+        [],
+    )
+    .context("failed to index block_history table")?;
+
+    // history is append only
+    tx.execute(
+        r#"CREATE TRIGGER "block_history_no_update" BEFORE UPDATE ON "block_history"
+        BEGIN SELECT RAISE(ABORT, 'block_history is append only'); END"#,
+        [],
+    )
+    .context("failed to protect block_history from updates")?;
+    tx.execute(
+        r#"CREATE TRIGGER "block_history_no_delete" BEFORE DELETE ON "block_history"
+        BEGIN SELECT RAISE(ABORT, 'block_history is append only'); END"#,
+        [],
+    )
+    .context("failed to protect block_history from deletes")?;
+
+    Ok(())
+}
+
+fn v8_to_v9(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    for column in ["color INTEGER", "icon TEXT", "description TEXT"] {
+        tx.execute(&format!("ALTER TABLE tags ADD COLUMN {column}"), [])
+            .with_context(|| format!("failed to add {column} column to tags"))?;
+    }
+    Ok(())
+}
+
+fn v9_to_v10(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r#"ALTER TABLE tags ADD COLUMN "parent" INTEGER REFERENCES "tags"("id")"#,
+        [],
+    )
+    .context("failed to add parent column to tags")?;
+    Ok(())
+}
+
+fn v10_to_v11(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    for column in ["rate INTEGER", "currency TEXT", "billable TEXT"] {
+        tx.execute(&format!("ALTER TABLE tags ADD COLUMN {column}"), [])
+            .with_context(|| format!("failed to add {column} column to tags"))?;
+    }
+    Ok(())
+}
+
+fn v11_to_v12(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(r#"ALTER TABLE tags ADD COLUMN "archived" TEXT"#, [])
+        .context("failed to add archived column to tags")?;
+    Ok(())
+}
+
+fn v12_to_v13(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    tx.execute(
+        r#"CREATE TABLE "rules" (
+        "id" INTEGER NOT NULL,
+        "action" TEXT NOT NULL,
+        "time" INTEGER NOT NULL,
+        "days" INTEGER NOT NULL,
+        "tag" INTEGER,
+        "planned" INTEGER,
+        "enabled" TEXT,
+        PRIMARY KEY("id"),
+        FOREIGN KEY("tag") REFERENCES "tags"("id")
+    );"#,
+        [],
+    )
+    .context("failed to create rules table")?;
+    tx.execute(
+        r#"CREATE TABLE "rule_firings" (
+        "id" INTEGER NOT NULL,
+        "rule" INTEGER NOT NULL,
+        "fired" INTEGER NOT NULL,
+        "description" TEXT NOT NULL,
+        "outcome" TEXT NOT NULL,
+        PRIMARY KEY("id")
+    );"#,
+        [],
+    )
+    .context("failed to create rule_firings table")?;
+    Ok(())
+}
+
+fn v13_to_v14(tx: &Transaction<'_>) -> anyhow::Result<()> {
+    // names were unique across every tag, which sqlite can only drop by rebuilding the table
+    tx.execute(
+        r#"CREATE TABLE "tags_v14" (
+        "id" INTEGER NOT NULL,
+        "name" TEXT NOT NULL,
+        "to_delete" CHECK("to_delete" = 'Y'),
+        "color" INTEGER,
+        "icon" TEXT,
+        "description" TEXT,
+        "parent" INTEGER REFERENCES "tags"("id"),
+        "rate" INTEGER,
+        "currency" TEXT,
+        "billable" TEXT,
+        "archived" TEXT,
+        PRIMARY KEY("id")
+    );"#,
+        [],
+    )
+    .context("failed to create new tags table")?;
+    tx.execute(
+        "INSERT INTO tags_v14
+        SELECT id, name, to_delete, color, icon, description, parent,
+            rate, currency, billable, archived
+        FROM tags",
+        [],
+    )
+    .context("failed to copy tags")?;
+    tx.execute("DROP TABLE tags", [])
+        .context("failed to drop old tags table")?;
+    tx.execute("ALTER TABLE tags_v14 RENAME TO tags", [])
+        .context("failed to rename new tags table")?;
+    // top level tags have no parent, and NULLs are never equal in a unique index
+    tx.execute(
+        r#"CREATE UNIQUE INDEX "tags_parent_name" ON "tags" (COALESCE("parent", 0), "name")"#,
+        [],
+    )
+    .context("failed to index tag names")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds the schema as it was at `version`
+    fn database_at(version: usize) -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_to(&mut conn, 0, version).unwrap();
+        assert_eq!(database_version(&conn).unwrap(), version);
+        conn
+    }
+
+    /// Inserts a block from 09:00 to 10:30 at UTC+1 on 2024-01-01, in the format used at `version`
+    fn insert_block(conn: &Connection, version: usize) {
+        if version < 6 {
+            conn.execute(
+                "INSERT INTO time_blocks (start, end) VALUES (?1, ?2)",
+                [
+                    "2024-01-01 09:00:00+01:00",
+                    "2024-01-01 10:30:00.123456789+01:00",
+                ],
+            )
+            .unwrap();
+        } else {
+            conn.execute(
+                "INSERT INTO time_blocks (start, start_offset, end, end_offset)
+                VALUES (?1, ?2, ?3, ?4)",
+                [1704096000, 3600, 1704101400, 3600],
+            )
+            .unwrap();
+        }
+    }
+
+    fn count_blocks(conn: &Connection) -> usize {
+        conn.query_row("SELECT count(*) FROM time_blocks", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn migrations_are_consecutive() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i + 1, "{}", migration.description);
+        }
+    }
+
+    #[test]
+    fn every_version_migrates_forward() {
+        for version in 0..=latest_version() {
+            let mut conn = database_at(version);
+            if version >= 1 {
+                insert_block(&conn, version);
+            }
+
+            migrate(&mut conn, None).unwrap();
+
+            assert_eq!(database_version(&conn).unwrap(), latest_version());
+            assert_eq!(count_blocks(&conn), usize::from(version >= 1));
+            verify_schema(&conn).unwrap();
+        }
+    }
+
+    #[test]
+    fn block_times_become_epoch_seconds() {
+        let mut conn = database_at(5);
+        insert_block(&conn, 5);
+
+        migrate(&mut conn, None).unwrap();
+
+        let times: (i64, i32, i64, i32) = conn
+            .query_row(
+                "SELECT start, start_offset, end, end_offset FROM time_blocks",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(times, (1704096000, 3600, 1704101400, 3600));
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let mut conn = database_at(0);
+        migrate(&mut conn, None).unwrap();
+        migrate(&mut conn, None).unwrap();
+        assert_eq!(database_version(&conn).unwrap(), latest_version());
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut conn = database_at(latest_version());
+        conn.execute(
+            "UPDATE app_info SET value = ?1 WHERE key = 'version'",
+            [latest_version() + 1],
+        )
+        .unwrap();
+
+        assert!(migrate(&mut conn, None).is_err());
+    }
+
+    #[test]
+    fn detects_unexpected_schema() {
+        let conn = database_at(latest_version());
+        verify_schema(&conn).unwrap();
+
+        conn.execute("ALTER TABLE time_blocks ADD surprise TEXT", [])
+            .unwrap();
+        assert!(verify_schema(&conn).is_err());
+
+        conn.execute("DROP TABLE settings", []).unwrap();
+        assert!(verify_schema(&conn).is_err());
+    }
+
+    #[test]
+    fn tags_keep_their_ids_and_parents() {
+        let mut conn = database_at(13);
+        conn.execute("INSERT INTO tags (id, name) VALUES (4, 'ACME')", [])
+            .unwrap();
+        conn.execute(
+            "INSERT INTO tags (id, name, parent) VALUES (7, 'Website', 4)",
+            [],
+        )
+        .unwrap();
+
+        migrate(&mut conn, None).unwrap();
+
+        let parent: usize = conn
+            .query_row("SELECT parent FROM tags WHERE id = 7", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(parent, 4);
+        let duplicate = conn.execute("INSERT INTO tags (name, parent) VALUES ('Website', 4)", []);
+        assert!(duplicate.is_err());
+    }
+}
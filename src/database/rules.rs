@@ -0,0 +1,349 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, TimeZone, Timelike, Weekday};
+use rusqlite::Connection;
+
+use super::{get_time, Tag, Tags};
+
+/// Starts or stops blocks at a time of day, on some days of the week
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    id: usize,
+    pub action: RuleAction,
+    /// Local time the rule fires at
+    pub time: NaiveTime,
+    /// Days the rule fires on, Monday first
+    pub days: [bool; 7],
+    /// Tag for the blocks it starts
+    pub tag: Option<Tag>,
+    /// Blocks it starts are stopped after this long
+    pub planned: Option<Duration>,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleAction {
+    /// Start a block, stopping any that is running
+    Start,
+    /// Stop the running block
+    Stop,
+}
+
+impl RuleAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            RuleAction::Start => "start",
+            RuleAction::Stop => "stop",
+        }
+    }
+}
+
+/// A time a rule fired, and what came of it
+#[derive(Clone, Debug)]
+pub struct RuleFiring {
+    pub fired: DateTime<Local>,
+    /// The rule as it was when it fired
+    pub description: String,
+    /// What the rule did, or why it failed
+    pub outcome: String,
+}
+
+impl Rule {
+    /// A rule stopping blocks at 18:00 on weekdays, to be edited before it is created
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            action: RuleAction::Stop,
+            time: NaiveTime::from_hms_opt(18, 0, 0).expect("18:00 is a valid time"),
+            days: [true, true, true, true, true, false, false],
+            tag: None,
+            planned: None,
+            enabled: true,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn fires_on(&self, day: Weekday) -> bool {
+        self.days[day.num_days_from_monday() as usize]
+    }
+
+    /// When the rule fires after `after`, up to and including `until`, if it does
+    pub fn fires_between(
+        &self,
+        after: DateTime<Local>,
+        until: DateTime<Local>,
+    ) -> Option<DateTime<Local>> {
+        if !self.enabled {
+            return None;
+        }
+        let mut day = after.date_naive();
+        while day <= until.date_naive() {
+            // a time skipped by a daylight saving change doesn't fire that day
+            let fires = Local
+                .from_local_datetime(&day.and_time(self.time))
+                .earliest();
+            if let Some(fires) = fires.filter(|_| self.fires_on(day.weekday())) {
+                if fires > after && fires <= until {
+                    return Some(fires);
+                }
+            }
+            day = day.succ_opt()?;
+        }
+        None
+    }
+
+    /// Says what the rule does, e.g. `Start Standup for 15m at 09:30 on weekdays`
+    pub fn describe(&self, tags: &[Tag], time_format: &str) -> String {
+        let mut description = match self.action {
+            RuleAction::Start => {
+                let tag = self.tag.as_ref().map(|t| t.path(tags));
+                format!("Start {}", tag.as_deref().unwrap_or("untagged"))
+            }
+            RuleAction::Stop => "Stop".to_string(),
+        };
+        if let (RuleAction::Start, Some(planned)) = (self.action, self.planned) {
+            description += &format!(" for {}m", planned.num_minutes());
+        }
+        description += &format!(
+            " at {} on {}",
+            self.time.format(time_format),
+            self.day_names()
+        );
+        description
+    }
+
+    fn day_names(&self) -> String {
+        match self.days {
+            [true, true, true, true, true, true, true] => "every day".to_string(),
+            [true, true, true, true, true, false, false] => "weekdays".to_string(),
+            [false, false, false, false, false, true, true] => "weekends".to_string(),
+            [false, false, false, false, false, false, false] => "no days".to_string(),
+            days => {
+                let names: Vec<String> = (0..7)
+                    .filter(|&i| days[i])
+                    .filter_map(|i| Weekday::try_from(i as u8).ok())
+                    .map(|day| day.to_string())
+                    .collect();
+                names.join(", ")
+            }
+        }
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rules stored in the database, and a log of when they fired
+pub struct Rules<'a> {
+    pub(super) conn: &'a Connection,
+}
+
+impl Rules<'_> {
+    /// Every rule, in the order they fire through the day
+    pub fn all(&self) -> anyhow::Result<Vec<Rule>> {
+        self.conn
+            .prepare(
+                "SELECT
+                    r.id, r.action, r.time, r.days, r.planned, r.enabled,
+                    t.id, t.name, t.color, t.icon, t.description, t.parent,
+                    t.rate, t.currency, t.billable, t.archived
+                FROM rules r
+                LEFT JOIN tags t ON r.tag = t.id
+                ORDER BY r.time, r.id",
+            )
+            .context("Preparing to get rules")?
+            .query_map([], to_rule)
+            .context("Trying to get rules")?
+            .map(|r| r.context("Failed to map row to Rule struct"))
+            .collect()
+    }
+
+    pub fn create(&self, rule: &Rule) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO rules (action, time, days, tag, planned, enabled)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    rule.action.as_str(),
+                    rule.time.num_seconds_from_midnight(),
+                    from_days(rule.days),
+                    rule.tag.as_ref().map(|t| t.id),
+                    rule.planned.map(|p| p.num_seconds()),
+                    rule.enabled.then_some("Y"),
+                ],
+            )
+            .map(|_| ())
+            .context("Trying to create rule")
+    }
+
+    pub fn update(&self, rule: &Rule) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE rules
+                SET action = ?1, time = ?2, days = ?3, tag = ?4, planned = ?5, enabled = ?6
+                WHERE id = ?7",
+                rusqlite::params![
+                    rule.action.as_str(),
+                    rule.time.num_seconds_from_midnight(),
+                    from_days(rule.days),
+                    rule.tag.as_ref().map(|t| t.id),
+                    rule.planned.map(|p| p.num_seconds()),
+                    rule.enabled.then_some("Y"),
+                    rule.id,
+                ],
+            )
+            .map(|_| ())
+            .context("Trying to update rule")
+    }
+
+    /// Deletes the rule, its firings stay in the log
+    pub fn delete(&self, rule: &Rule) -> anyhow::Result<()> {
+        self.conn
+            .execute("DELETE FROM rules WHERE id = ?1", [rule.id])
+            .map(|_| ())
+            .context("Trying to delete rule")
+    }
+
+    /// Adds to the log of rule firings
+    pub fn record_firing(
+        &self,
+        rule: &Rule,
+        fired: DateTime<Local>,
+        description: &str,
+        outcome: &str,
+    ) -> anyhow::Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO rule_firings (rule, fired, description, outcome)
+                VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![rule.id, fired.timestamp(), description, outcome],
+            )
+            .map(|_| ())
+            .context("Trying to record rule firing")
+    }
+
+    /// The most recent firings, newest first
+    pub fn firings(&self, limit: usize) -> anyhow::Result<Vec<RuleFiring>> {
+        self.conn
+            .prepare(
+                "SELECT fired, description, outcome
+                FROM rule_firings
+                ORDER BY id DESC
+                LIMIT ?1",
+            )
+            .context("Preparing to get rule firings")?
+            .query_map([limit], |row| {
+                Ok(RuleFiring {
+                    fired: get_time(row, 0)?,
+                    description: row.get(1)?,
+                    outcome: row.get(2)?,
+                })
+            })
+            .context("Trying to get rule firings")?
+            .map(|r| r.context("Failed to map row to RuleFiring struct"))
+            .collect()
+    }
+}
+
+/// Days as bits, Monday in the lowest
+fn from_days(days: [bool; 7]) -> u8 {
+    (0..7).filter(|&i| days[i]).fold(0, |bits, i| bits | 1 << i)
+}
+
+fn to_days(bits: u8) -> [bool; 7] {
+    std::array::from_fn(|i| bits & 1 << i != 0)
+}
+
+fn to_rule(row: &rusqlite::Row<'_>) -> Result<Rule, rusqlite::Error> {
+    let action = match row.get::<_, String>(1)?.as_str() {
+        "start" => RuleAction::Start,
+        "stop" => RuleAction::Stop,
+        other => {
+            return Err(rusqlite::Error::FromSqlConversionFailure(
+                1,
+                rusqlite::types::Type::Text,
+                anyhow!("Unknown rule action {other}").into(),
+            ))
+        }
+    };
+    let time = NaiveTime::from_num_seconds_from_midnight_opt(row.get(2)?, 0).ok_or(
+        rusqlite::Error::IntegralValueOutOfRange(2, row.get::<_, i64>(2)?),
+    )?;
+    let tag: Option<usize> = row.get(6)?;
+    let enabled: Option<String> = row.get(5)?;
+    Ok(Rule {
+        id: row.get(0)?,
+        action,
+        time,
+        days: to_days(row.get(3)?),
+        tag: tag.map(|_| Tags::to_tag(row, 6)).transpose()?,
+        planned: row.get::<_, Option<i64>>(4)?.map(Duration::seconds),
+        enabled: enabled.is_some_and(|e| e == "Y"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::migrations;
+    use super::*;
+
+    #[test]
+    fn fires_at_its_time_on_its_days() {
+        let mut rule = Rule::new();
+        rule.time = NaiveTime::from_hms_opt(9, 30, 0).unwrap();
+        // 2024-01-05 is a Friday
+        let at = |day, h, m| Local.with_ymd_and_hms(2024, 1, day, h, m, 0).unwrap();
+        assert_eq!(
+            rule.fires_between(at(5, 9, 29), at(5, 9, 30)),
+            Some(at(5, 9, 30))
+        );
+        assert_eq!(rule.fires_between(at(5, 9, 30), at(5, 9, 31)), None);
+        // not on the weekend, but on the next monday
+        assert_eq!(rule.fires_between(at(5, 10, 0), at(7, 23, 0)), None);
+        assert_eq!(
+            rule.fires_between(at(5, 10, 0), at(8, 10, 0)),
+            Some(at(8, 9, 30))
+        );
+        rule.enabled = false;
+        assert_eq!(rule.fires_between(at(5, 9, 29), at(5, 9, 30)), None);
+    }
+
+    #[test]
+    fn stores_rules_and_firings() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrations::migrate(&mut conn, None).unwrap();
+        let tag = Tags { conn: &conn }
+            .create_path("Standup")
+            .unwrap()
+            .remove(0);
+        let rules = Rules { conn: &conn };
+
+        let mut rule = Rule::new();
+        rule.action = RuleAction::Start;
+        rule.tag = Some(tag.clone());
+        rule.planned = Some(Duration::minutes(15));
+        rules.create(&rule).unwrap();
+        let stored = rules.all().unwrap().remove(0);
+        assert_eq!(
+            stored.describe(&[tag], "%H:%M"),
+            "Start Standup for 15m at 18:00 on weekdays"
+        );
+
+        rule = stored;
+        rule.days = [false, true, false, false, false, false, true];
+        rules.update(&rule).unwrap();
+        assert_eq!(rules.all().unwrap(), [rule.clone()]);
+
+        rules
+            .record_firing(&rule, Local::now(), "Start", "Started")
+            .unwrap();
+        rules.delete(&rule).unwrap();
+        assert!(rules.all().unwrap().is_empty());
+        assert_eq!(rules.firings(10).unwrap()[0].outcome, "Started");
+    }
+}
@@ -0,0 +1,98 @@
+use chrono::{DateTime, Duration, Local};
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::Database;
+use super::{Block, Tag};
+
+/// Where blocks and tags are kept. The native app keeps them in SQLite,
+/// the web app in the browser's local storage.
+pub trait Storage {
+    /// The running block, if there is one
+    fn current(&self) -> anyhow::Result<Option<Block>>;
+    /// Starts a block, with an optional target for how long it should run
+    fn start(&self, tag: Option<Tag>, planned: Option<Duration>) -> anyhow::Result<()>;
+    /// Stops the running block
+    fn stop(&self) -> anyhow::Result<()>;
+    /// Stops the running block, marking it as a completed pomodoro
+    fn finish_pomodoro(&self) -> anyhow::Result<()>;
+    /// Moves the end of the running block to now
+    fn update(&self) -> anyhow::Result<()>;
+
+    fn block(&self, id: usize) -> anyhow::Result<Option<Block>>;
+    /// Blocks starting between `before` and `after`, leaving out the trash
+    fn blocks_in_range(
+        &self,
+        before: DateTime<Local>,
+        after: DateTime<Local>,
+    ) -> anyhow::Result<Vec<Block>>;
+    fn retag_block(&self, block: Block) -> anyhow::Result<()>;
+    /// Moves the block to the trash
+    fn delete_block(&self, block: Block) -> anyhow::Result<()>;
+
+    /// Tags that haven't been deleted, in tree order
+    fn tags(&self) -> anyhow::Result<Vec<Tag>>;
+    /// Creates the tags in a path like `ACME / Website` that don't exist yet, returning them
+    fn create_tags(&self, path: &str) -> anyhow::Result<Vec<Tag>>;
+    fn update_tag(&self, tag: Tag) -> anyhow::Result<()>;
+    /// Deletes the tag along with every tag nested under it
+    fn delete_tag(&self, tag: Tag) -> anyhow::Result<()>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Storage for Database {
+    fn current(&self) -> anyhow::Result<Option<Block>> {
+        self.blocks().current()
+    }
+
+    fn start(&self, tag: Option<Tag>, planned: Option<Duration>) -> anyhow::Result<()> {
+        self.stopwatch().start_planned(tag, planned)
+    }
+
+    fn stop(&self) -> anyhow::Result<()> {
+        self.stopwatch().stop()
+    }
+
+    fn finish_pomodoro(&self) -> anyhow::Result<()> {
+        self.stopwatch().finish_pomodoro()
+    }
+
+    fn update(&self) -> anyhow::Result<()> {
+        self.stopwatch().update()
+    }
+
+    fn block(&self, id: usize) -> anyhow::Result<Option<Block>> {
+        self.blocks().get(id)
+    }
+
+    fn blocks_in_range(
+        &self,
+        before: DateTime<Local>,
+        after: DateTime<Local>,
+    ) -> anyhow::Result<Vec<Block>> {
+        self.blocks().in_range(before, after)
+    }
+
+    fn retag_block(&self, block: Block) -> anyhow::Result<()> {
+        self.blocks().update_tag(block)
+    }
+
+    fn delete_block(&self, block: Block) -> anyhow::Result<()> {
+        self.blocks().delete(block)
+    }
+
+    fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        self.tags().all()
+    }
+
+    fn create_tags(&self, path: &str) -> anyhow::Result<Vec<Tag>> {
+        self.tags().create_path(path)
+    }
+
+    fn update_tag(&self, tag: Tag) -> anyhow::Result<()> {
+        self.tags().update(tag)
+    }
+
+    fn delete_tag(&self, tag: Tag) -> anyhow::Result<()> {
+        self.tags().delete(tag)
+    }
+}
@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Duration, Local};
+use tracing::{info, warn};
+
+use super::{path_names, sort_tree, Block, Storage, Tag, MAX_TAG_DEPTH};
+
+/// Key blocks and tags are kept under in eframe storage
+const STORAGE_KEY: &str = "Records";
+
+/// Keeps blocks and tags in memory, saved to eframe storage.
+/// On the web that is the browser's local storage.
+#[derive(Default)]
+pub struct WebStorage {
+    records: RefCell<Records>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+struct Records {
+    blocks: Vec<Block>,
+    /// Every tag, including deleted ones that blocks still use
+    tags: Vec<Tag>,
+    deleted_tags: Vec<usize>,
+    last_id: usize,
+}
+
+impl Records {
+    fn next_id(&mut self) -> usize {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    /// The block, with its tag as it is now rather than when the block was tagged
+    fn refreshed(&self, block: &Block) -> Block {
+        let mut block = block.clone();
+        if let Some(tag) = &block.tag {
+            block.tag = self.tags.iter().find(|t| t.id == tag.id).cloned();
+        }
+        block
+    }
+
+    fn block_mut(&mut self, id: usize) -> anyhow::Result<&mut Block> {
+        self.blocks
+            .iter_mut()
+            .find(|b| b.id == id)
+            .ok_or_else(|| anyhow!("Block {id} doesn't exist"))
+    }
+
+    fn running_mut(&mut self) -> Option<&mut Block> {
+        self.blocks.iter_mut().find(|b| b.running)
+    }
+
+    fn live_tags(&self) -> Vec<Tag> {
+        let tags = (self.tags.iter())
+            .filter(|t| !self.deleted_tags.contains(&t.id))
+            .cloned()
+            .collect();
+        sort_tree(tags)
+    }
+
+    /// Forgets deleted tags that no block or other tag uses
+    fn maintain(&mut self) {
+        loop {
+            let unused = self.deleted_tags.iter().copied().find(|&id| {
+                let tagged =
+                    (self.blocks.iter()).any(|b| b.tag.as_ref().is_some_and(|t| t.id == id));
+                let parent = self.tags.iter().any(|t| t.parent == Some(id));
+                !tagged && !parent
+            });
+            let Some(id) = unused else {
+                break;
+            };
+            self.tags.retain(|t| t.id != id);
+            self.deleted_tags.retain(|&d| d != id);
+        }
+    }
+}
+
+impl WebStorage {
+    /// Reads records saved by [`Self::save`], starting empty if there are none
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        let Some(serialized) = storage.and_then(|s| s.get_string(STORAGE_KEY)) else {
+            return Self::default();
+        };
+        match serde_json::from_str(&serialized) {
+            Ok(records) => Self {
+                records: RefCell::new(records),
+            },
+            Err(e) => {
+                warn!("Failed to read stored blocks and tags: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        let mut records = self.records.borrow_mut();
+        records.maintain();
+        match serde_json::to_string(&*records) {
+            Ok(serialized) => storage.set_string(STORAGE_KEY, serialized),
+            Err(e) => warn!("Failed to save blocks and tags: {}", e),
+        }
+    }
+}
+
+impl Storage for WebStorage {
+    fn current(&self) -> anyhow::Result<Option<Block>> {
+        let records = self.records.borrow();
+        let current = records.blocks.iter().find(|b| b.running);
+        Ok(current.map(|b| records.refreshed(b)))
+    }
+
+    fn start(&self, tag: Option<Tag>, planned: Option<Duration>) -> anyhow::Result<()> {
+        self.stop()?;
+        let mut records = self.records.borrow_mut();
+        let now = Local::now();
+        let id = records.next_id();
+        records.blocks.push(Block {
+            id,
+            start: now,
+            end: now,
+            tag,
+            running: true,
+            pomodoro: false,
+            planned,
+            deleted: None,
+        });
+        info!("Started block");
+        Ok(())
+    }
+
+    fn stop(&self) -> anyhow::Result<()> {
+        if let Some(block) = self.records.borrow_mut().running_mut() {
+            block.end = Local::now();
+            block.running = false;
+            info!("Stopped");
+        }
+        Ok(())
+    }
+
+    fn finish_pomodoro(&self) -> anyhow::Result<()> {
+        if let Some(block) = self.records.borrow_mut().running_mut() {
+            block.end = Local::now();
+            block.running = false;
+            block.pomodoro = true;
+            info!("Finished pomodoro");
+        }
+        Ok(())
+    }
+
+    fn update(&self) -> anyhow::Result<()> {
+        if let Some(block) = self.records.borrow_mut().running_mut() {
+            block.end = Local::now();
+        }
+        Ok(())
+    }
+
+    fn block(&self, id: usize) -> anyhow::Result<Option<Block>> {
+        let records = self.records.borrow();
+        let block = records.blocks.iter().find(|b| b.id == id);
+        Ok(block.map(|b| records.refreshed(b)))
+    }
+
+    fn blocks_in_range(
+        &self,
+        before: DateTime<Local>,
+        after: DateTime<Local>,
+    ) -> anyhow::Result<Vec<Block>> {
+        let records = self.records.borrow();
+        Ok((records.blocks.iter())
+            .filter(|b| b.start > before && b.start < after && b.deleted.is_none())
+            .map(|b| records.refreshed(b))
+            .collect())
+    }
+
+    fn retag_block(&self, block: Block) -> anyhow::Result<()> {
+        self.records.borrow_mut().block_mut(block.id)?.tag = block.tag;
+        Ok(())
+    }
+
+    fn delete_block(&self, block: Block) -> anyhow::Result<()> {
+        let mut records = self.records.borrow_mut();
+        let block = records.block_mut(block.id)?;
+        block.deleted = Some(Local::now());
+        block.running = false;
+        Ok(())
+    }
+
+    fn tags(&self) -> anyhow::Result<Vec<Tag>> {
+        Ok(self.records.borrow().live_tags())
+    }
+
+    fn create_tags(&self, path: &str) -> anyhow::Result<Vec<Tag>> {
+        let mut records = self.records.borrow_mut();
+        let mut created = Vec::new();
+        let mut parent = None;
+        for name in path_names(path)? {
+            let live = records.live_tags();
+            let existing = live.iter().find(|t| t.name == name && t.parent == parent);
+            parent = match existing {
+                Some(tag) => Some(tag.id),
+                None => {
+                    info!("Creating tag {name}");
+                    let tag = Tag {
+                        id: records.next_id(),
+                        name: name.to_string(),
+                        color: None,
+                        icon: None,
+                        description: None,
+                        parent,
+                        rate: None,
+                        billable: None,
+                        archived: false,
+                    };
+                    records.tags.push(tag.clone());
+                    created.push(tag);
+                    created.last().map(|t| t.id)
+                }
+            };
+        }
+        Ok(created)
+    }
+
+    fn update_tag(&self, tag: Tag) -> anyhow::Result<()> {
+        info!("Updating tag {}", tag.name);
+        let mut records = self.records.borrow_mut();
+        let mut parent = tag.parent;
+        for _ in 0..MAX_TAG_DEPTH {
+            let Some(id) = parent else {
+                break;
+            };
+            if id == tag.id {
+                bail!("{} can't be nested inside itself", tag.name);
+            }
+            parent = (records.tags.iter().find(|t| t.id == id))
+                .ok_or_else(|| anyhow!("Parent of {} doesn't exist", tag.name))?
+                .parent;
+        }
+        if parent.is_some() {
+            bail!("Tags are nested too deeply");
+        }
+
+        let stored = (records.tags.iter_mut().find(|t| t.id == tag.id))
+            .ok_or_else(|| anyhow!("Tag {} doesn't exist", tag.name))?;
+        *stored = tag;
+        Ok(())
+    }
+
+    fn delete_tag(&self, tag: Tag) -> anyhow::Result<()> {
+        info!("Deleting tag {}", tag.name);
+        let mut records = self.records.borrow_mut();
+        let tags = records.live_tags();
+        let subtree = tags
+            .iter()
+            .filter(|t| t.is_within(&tag, &tags))
+            .map(|t| t.id);
+        records.deleted_tags.extend(subtree);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_deleted_tags_while_blocks_use_them() {
+        let storage = WebStorage::default();
+        let created = storage.create_tags("ACME / Website").unwrap();
+        assert_eq!(storage.create_tags("ACME / Design").unwrap().len(), 1);
+        storage.start(Some(created[1].clone()), None).unwrap();
+        storage.stop().unwrap();
+
+        storage.delete_tag(created[0].clone()).unwrap();
+        let names: Vec<String> = storage
+            .tags()
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert!(names.is_empty());
+        let records = &mut storage.records.borrow_mut();
+        records.maintain();
+        assert_eq!(records.tags.len(), 2);
+        let block = records.refreshed(&records.blocks[0]);
+        assert_eq!(block.tag.unwrap().name, "Website");
+    }
+}
@@ -0,0 +1,602 @@
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use anyhow::Context;
+use chrono::Duration;
+
+use crate::database::{Block, Tag};
+use crate::format::{fmt_duration, fmt_hours, fmt_money};
+use crate::history::{Invoice, InvoiceLine, Timesheet};
+use crate::settings::Settings;
+
+/// Names that report templates can use as `{{name}}`, each replaced by a rendered section
+pub const REPORT_PLACEHOLDERS: &[&str] = &[
+    "title", "period", "days", "tags", "total", "goal", "balance", "notes",
+];
+
+const MARKDOWN_REPORT: &str = "# {{title}}
+
+{{period}}
+
+{{days}}
+## Summary
+
+{{tags}}
+| Total | Goal | Balance |
+| ---: | ---: | ---: |
+| {{total}} | {{goal}} | {{balance}} |
+
+{{notes}}
+";
+
+const HTML_REPORT: &str = "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{{title}} {{period}}</title>
+</head>
+<body>
+<h1>{{title}}</h1>
+<p>{{period}}</p>
+{{days}}
+<h2>Summary</h2>
+{{tags}}
+<table>
+<tr><th>Total</th><th>Goal</th><th>Balance</th></tr>
+<tr><td>{{total}}</td><td>{{goal}}</td><td>{{balance}}</td></tr>
+</table>
+{{notes}}
+</body>
+</html>
+";
+
+/// File formats that summaries can be exported as
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// Reads a report template supplied by the user
+pub fn read_template(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read template {}", path.display()))
+}
+
+/// Renders a weekly timesheet into `template`, or into the built in template for the format
+pub fn report(
+    timesheet: &Timesheet,
+    format: Format,
+    settings: &Settings,
+    notes: &str,
+    template: Option<&str>,
+) -> String {
+    let sections = match format {
+        Format::Markdown => report_markdown(timesheet, settings, notes),
+        Format::Html => report_html(timesheet, settings, notes),
+    };
+    let sections = sections.expect("Writing to a string can't fail");
+    let template = template.unwrap_or(match format {
+        Format::Markdown => MARKDOWN_REPORT,
+        Format::Html => HTML_REPORT,
+    });
+    fill(template, &sections)
+}
+
+/// Replaces each `{{name}}` in the template with its value, leaving unknown names as they are
+fn fill(template: &str, values: &[(&str, String)]) -> String {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let value = after.find("}}").and_then(|end| {
+            let name = after[..end].trim();
+            let value = values.iter().find(|(n, _)| *n == name);
+            value.map(|(_, value)| (end, value))
+        });
+        match value {
+            Some((end, value)) => {
+                out.push_str(value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                out.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// A balance with its sign, e.g. `-1h 30m`
+fn fmt_balance(balance: Duration) -> String {
+    if balance < Duration::zero() {
+        format!("-{}", fmt_duration(-balance))
+    } else {
+        format!("+{}", fmt_duration(balance))
+    }
+}
+
+fn report_markdown(
+    timesheet: &Timesheet,
+    settings: &Settings,
+    notes: &str,
+) -> Result<Vec<(&'static str, String)>, fmt::Error> {
+    let date = &settings.date_format;
+    let time = &settings.time_format;
+
+    let mut days = String::new();
+    for day in &timesheet.days {
+        writeln!(
+            days,
+            "## {} - {}\n",
+            day.day.format(date),
+            fmt_duration(day.rounded)
+        )?;
+        writeln!(days, "| Start | End | Time | Tag |")?;
+        writeln!(days, "| --- | --- | ---: | --- |")?;
+        for block in &day.blocks {
+            let tag = block.tag.as_ref().map(|t| t.path(&timesheet.tags));
+            let tag = tag.unwrap_or_default();
+            writeln!(
+                days,
+                "| {} | {} | {} | {} |",
+                block.start.format(time),
+                block.end.format(time),
+                fmt_duration(settings.rounding.block(block.duration())),
+                tag.replace('|', "\\|")
+            )?;
+        }
+        writeln!(days)?;
+    }
+
+    let mut tags = String::new();
+    writeln!(tags, "| Tag | Time |")?;
+    writeln!(tags, "| --- | ---: |")?;
+    for (tag, time) in &timesheet.by_tag {
+        let tag = tag.as_deref().unwrap_or("Untagged");
+        writeln!(
+            tags,
+            "| {} | {} |",
+            tag.replace('|', "\\|"),
+            fmt_duration(*time)
+        )?;
+    }
+
+    Ok(vec![
+        ("title", "Timesheet".to_string()),
+        (
+            "period",
+            format!(
+                "{} to {}",
+                timesheet.first.format(date),
+                timesheet.last.format(date)
+            ),
+        ),
+        ("days", days),
+        ("tags", tags),
+        ("total", fmt_total(timesheet)),
+        ("goal", fmt_duration(timesheet.goal)),
+        ("balance", fmt_balance(timesheet.balance())),
+        ("notes", notes.to_string()),
+    ])
+}
+
+/// The rounded total, with the tracked time when rounding changed it
+fn fmt_total(timesheet: &Timesheet) -> String {
+    let rounded = fmt_duration(timesheet.rounded);
+    if timesheet.rounded == timesheet.total {
+        rounded
+    } else {
+        format!("{rounded} ({} tracked)", fmt_duration(timesheet.total))
+    }
+}
+
+fn report_html(
+    timesheet: &Timesheet,
+    settings: &Settings,
+    notes: &str,
+) -> Result<Vec<(&'static str, String)>, fmt::Error> {
+    let date = &settings.date_format;
+    let time = &settings.time_format;
+
+    let mut days = String::new();
+    for day in &timesheet.days {
+        writeln!(
+            days,
+            "<h2>{} - {}</h2>\n<table>",
+            escape(&day.day.format(date).to_string()),
+            fmt_duration(day.rounded)
+        )?;
+        writeln!(
+            days,
+            "<tr><th>Start</th><th>End</th><th>Time</th><th>Tag</th></tr>"
+        )?;
+        for block in &day.blocks {
+            let tag = block.tag.as_ref().map(|t| t.path(&timesheet.tags));
+            let tag = tag.unwrap_or_default();
+            writeln!(
+                days,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&block.start.format(time).to_string()),
+                escape(&block.end.format(time).to_string()),
+                fmt_duration(settings.rounding.block(block.duration())),
+                escape(&tag)
+            )?;
+        }
+        writeln!(days, "</table>")?;
+    }
+
+    let mut tags = String::new();
+    writeln!(tags, "<table>\n<tr><th>Tag</th><th>Time</th></tr>")?;
+    for (tag, time) in &timesheet.by_tag {
+        let tag = tag.as_deref().unwrap_or("Untagged");
+        writeln!(
+            tags,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            escape(tag),
+            fmt_duration(*time)
+        )?;
+    }
+    writeln!(tags, "</table>")?;
+
+    let mut paragraphs = String::new();
+    for paragraph in notes.split("\n\n").filter(|p| !p.trim().is_empty()) {
+        writeln!(paragraphs, "<p>{}</p>", escape(paragraph.trim()))?;
+    }
+
+    let period = format!(
+        "{} to {}",
+        timesheet.first.format(date),
+        timesheet.last.format(date)
+    );
+    Ok(vec![
+        ("title", "Timesheet".to_string()),
+        ("period", escape(&period)),
+        ("days", days),
+        ("tags", tags),
+        ("total", fmt_total(timesheet)),
+        ("goal", fmt_duration(timesheet.goal)),
+        ("balance", fmt_balance(timesheet.balance())),
+        ("notes", paragraphs),
+    ])
+}
+
+/// Renders an invoice summary with line items per tag and per day
+pub fn invoice(invoice: &Invoice, format: Format, settings: &Settings) -> String {
+    let rendered = match format {
+        Format::Markdown => invoice_markdown(invoice, settings),
+        Format::Html => invoice_html(invoice, settings),
+    };
+    rendered.expect("Writing to a string can't fail")
+}
+
+fn invoice_markdown(invoice: &Invoice, settings: &Settings) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    let date = &settings.date_format;
+    writeln!(out, "# Invoice summary\n")?;
+    writeln!(
+        out,
+        "{} to {}\n",
+        invoice.first.format(date),
+        invoice.last.format(date)
+    )?;
+
+    writeln!(out, "## By tag\n")?;
+    writeln!(out, "| Tag | Hours | Rate | Amount |")?;
+    writeln!(out, "| --- | ---: | ---: | ---: |")?;
+    for line in &invoice.by_tag {
+        writeln!(
+            out,
+            "| {} | {} | {}/h | {} |",
+            line.tag.replace('|', "\\|"),
+            fmt_hours(line.time),
+            fmt_money(line.rate.amount, &line.rate.currency),
+            amount(line)
+        )?;
+    }
+    for (currency, total) in &invoice.totals {
+        writeln!(out, "\n**Total: {}**", fmt_money(*total, currency))?;
+    }
+
+    writeln!(out, "\n## By day")?;
+    for day in &invoice.by_day {
+        writeln!(out, "\n### {}\n", day.day.format(date))?;
+        writeln!(out, "| Tag | Hours | Amount |")?;
+        writeln!(out, "| --- | ---: | ---: |")?;
+        for line in &day.lines {
+            writeln!(
+                out,
+                "| {} | {} | {} |",
+                line.tag.replace('|', "\\|"),
+                fmt_hours(line.time),
+                amount(line)
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+fn invoice_html(invoice: &Invoice, settings: &Settings) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    let date = &settings.date_format;
+    let range = format!(
+        "{} to {}",
+        invoice.first.format(date),
+        invoice.last.format(date)
+    );
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>Invoice summary {}</title>", escape(&range))?;
+    writeln!(out, "</head>\n<body>")?;
+    writeln!(out, "<h1>Invoice summary</h1>")?;
+    writeln!(out, "<p>{}</p>", escape(&range))?;
+
+    writeln!(out, "<h2>By tag</h2>\n<table>")?;
+    writeln!(
+        out,
+        "<tr><th>Tag</th><th>Hours</th><th>Rate</th><th>Amount</th></tr>"
+    )?;
+    for line in &invoice.by_tag {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}/h</td><td>{}</td></tr>",
+            escape(&line.tag),
+            fmt_hours(line.time),
+            escape(&fmt_money(line.rate.amount, &line.rate.currency)),
+            escape(&amount(line))
+        )?;
+    }
+    writeln!(out, "</table>")?;
+    for (currency, total) in &invoice.totals {
+        writeln!(
+            out,
+            "<p><strong>Total: {}</strong></p>",
+            escape(&fmt_money(*total, currency))
+        )?;
+    }
+
+    writeln!(out, "<h2>By day</h2>")?;
+    for day in &invoice.by_day {
+        writeln!(
+            out,
+            "<h3>{}</h3>\n<table>",
+            escape(&day.day.format(date).to_string())
+        )?;
+        writeln!(out, "<tr><th>Tag</th><th>Hours</th><th>Amount</th></tr>")?;
+        for line in &day.lines {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&line.tag),
+                fmt_hours(line.time),
+                escape(&amount(line))
+            )?;
+        }
+        writeln!(out, "</table>")?;
+    }
+    writeln!(out, "</body>\n</html>")?;
+    Ok(out)
+}
+
+/// Renders a list of blocks, such as the results of a search, with their total
+pub fn blocks(blocks: &[Block], tags: &[Tag], format: Format, settings: &Settings) -> String {
+    let rendered = match format {
+        Format::Markdown => blocks_markdown(blocks, tags, settings),
+        Format::Html => blocks_html(blocks, tags, settings),
+    };
+    rendered.expect("Writing to a string can't fail")
+}
+
+fn blocks_markdown(
+    blocks: &[Block],
+    tags: &[Tag],
+    settings: &Settings,
+) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    let (date, time) = (&settings.date_format, &settings.time_format);
+    writeln!(out, "# Blocks\n")?;
+    writeln!(out, "| Day | Start | End | Time | Tag |")?;
+    writeln!(out, "| --- | --- | --- | ---: | --- |")?;
+    for block in blocks {
+        let tag = block.tag.as_ref().map(|t| t.path(tags)).unwrap_or_default();
+        writeln!(
+            out,
+            "| {} | {} | {} | {} | {} |",
+            block.start.format(date),
+            block.start.format(time),
+            block.end.format(time),
+            fmt_duration(block.duration()),
+            tag.replace('|', "\\|")
+        )?;
+    }
+    let total = blocks.iter().map(Block::duration).sum();
+    writeln!(out, "\n**Total: {}**", fmt_duration(total))?;
+    Ok(out)
+}
+
+fn blocks_html(blocks: &[Block], tags: &[Tag], settings: &Settings) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    let (date, time) = (&settings.date_format, &settings.time_format);
+    writeln!(out, "<!DOCTYPE html>\n<html>\n<head>")?;
+    writeln!(out, "<meta charset=\"utf-8\">")?;
+    writeln!(out, "<title>Blocks</title>")?;
+    writeln!(out, "</head>\n<body>")?;
+    writeln!(out, "<h1>Blocks</h1>\n<table>")?;
+    writeln!(
+        out,
+        "<tr><th>Day</th><th>Start</th><th>End</th><th>Time</th><th>Tag</th></tr>"
+    )?;
+    for block in blocks {
+        let tag = block.tag.as_ref().map(|t| t.path(tags)).unwrap_or_default();
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&block.start.format(date).to_string()),
+            escape(&block.start.format(time).to_string()),
+            escape(&block.end.format(time).to_string()),
+            fmt_duration(block.duration()),
+            escape(&tag)
+        )?;
+    }
+    writeln!(out, "</table>")?;
+    let total = blocks.iter().map(Block::duration).sum();
+    writeln!(
+        out,
+        "<p><strong>Total: {}</strong></p>",
+        fmt_duration(total)
+    )?;
+    writeln!(out, "</body>\n</html>")?;
+    Ok(out)
+}
+
+fn amount(line: &InvoiceLine) -> String {
+    fmt_money(line.amount, &line.rate.currency)
+}
+
+/// Escapes text for use in html
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, NaiveDate, TimeZone};
+
+    use super::*;
+    use crate::database::Database;
+    use crate::history::History;
+
+    /// A timesheet with a tagged and an untagged block on the first day of 2024
+    fn timesheet(database: &Database) -> Timesheet {
+        let at = |hour, minute| Local.with_ymd_and_hms(2024, 1, 1, hour, minute, 0).unwrap();
+        let tag = database
+            .tags()
+            .create_path("R&D | <Lab>")
+            .unwrap()
+            .remove(0);
+        database.add_block(at(9, 0), at(10, 30), Some(&tag));
+        database.add_block(at(11, 0), at(11, 30), None);
+        let day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        History::new(database)
+            .timesheet(day, &Settings::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn reports_in_markdown() {
+        let database = Database::in_memory();
+        let report = report(
+            &timesheet(&database),
+            Format::Markdown,
+            &Settings::default(),
+            "Notes",
+            None,
+        );
+        for line in [
+            "# Timesheet",
+            "24-01-01 to 24-01-07",
+            "## 24-01-01 - 2h 0m",
+            "| 09:00 | 10:30 | 1h 30m | R&D \\| <Lab> |",
+            "| R&D \\| <Lab> | 1h 30m |",
+            "| Untagged | 30m 0s |",
+            "| 2h 0m | 40h 0m | -38h 0m |",
+            "Notes",
+        ] {
+            assert!(report.lines().any(|l| l == line), "{line} in {report}");
+        }
+    }
+
+    #[test]
+    fn reports_in_html() {
+        let database = Database::in_memory();
+        let report = report(
+            &timesheet(&database),
+            Format::Html,
+            &Settings::default(),
+            "First <b>\n\nSecond",
+            Some("{{tags}}{{notes}}{{total}}"),
+        );
+        assert_eq!(
+            report,
+            "<table>\n<tr><th>Tag</th><th>Time</th></tr>\n\
+            <tr><td>R&amp;D | &lt;Lab&gt;</td><td>1h 30m</td></tr>\n\
+            <tr><td>Untagged</td><td>30m 0s</td></tr>\n</table>\n\
+            <p>First &lt;b&gt;</p>\n<p>Second</p>\n2h 0m"
+        );
+    }
+
+    #[test]
+    fn exports_blocks_in_markdown() {
+        let database = Database::in_memory();
+        let timesheet = timesheet(&database);
+        let (blocks, tags) = (&timesheet.days[0].blocks, &timesheet.tags);
+        let markdown = self::blocks(blocks, tags, Format::Markdown, &Settings::default());
+        assert_eq!(
+            markdown,
+            "# Blocks\n\n\
+            | Day | Start | End | Time | Tag |\n\
+            | --- | --- | --- | ---: | --- |\n\
+            | 24-01-01 | 09:00 | 10:30 | 1h 30m | R&D \\| <Lab> |\n\
+            | 24-01-01 | 11:00 | 11:30 | 30m 0s |  |\n\
+            \n**Total: 2h 0m**\n"
+        );
+    }
+
+    #[test]
+    fn escapes_invoice_dates() {
+        let day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let line = InvoiceLine {
+            tag: "Client".to_string(),
+            rate: crate::database::Rate {
+                amount: 6000,
+                currency: "EUR".to_string(),
+            },
+            raw: Duration::hours(1),
+            time: Duration::hours(1),
+            amount: 6000,
+        };
+        let invoice = Invoice {
+            first: day,
+            last: day,
+            by_tag: Vec::new(),
+            by_day: vec![crate::history::InvoiceDay {
+                day,
+                lines: vec![line],
+            }],
+            totals: Vec::new(),
+        };
+        let settings = Settings {
+            date_format: "<%d>".to_string(),
+            ..Default::default()
+        };
+        let html = self::invoice(&invoice, Format::Html, &settings);
+        assert!(html.contains("<h3>&lt;01&gt;</h3>"), "{html}");
+    }
+
+    #[test]
+    fn fills_known_placeholders_once() {
+        let values = [
+            ("title", "{{notes}}".to_string()),
+            ("notes", "Notes".to_string()),
+        ];
+        assert_eq!(
+            fill("{{ title }}: {{notes}} {{unknown}} {{", &values),
+            "{{notes}}: Notes {{unknown}} {{"
+        );
+    }
+}
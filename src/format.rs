@@ -0,0 +1,40 @@
+//! Formats durations and amounts the same way in the window, on the command line and in
+//! exports.
+
+use chrono::Duration;
+
+pub fn fmt_duration(mut duration: Duration) -> String {
+    //Assume negative durations are rounding errors, so move to zero
+    duration = duration.max(Duration::zero());
+
+    let hours = duration.num_hours();
+    let minutes = duration.num_minutes() % 60;
+    let seconds = duration.num_seconds() % 60;
+
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, seconds)
+    }
+}
+
+/// Formats a duration next to its rounded value, or on its own if rounding didn't change it
+pub fn fmt_rounded(raw: Duration, rounded: Duration) -> String {
+    if raw == rounded {
+        fmt_duration(raw)
+    } else {
+        format!("{} (rounded {})", fmt_duration(raw), fmt_duration(rounded))
+    }
+}
+
+/// Formats an amount in the smallest unit of a currency, e.g. `USD 12.50`
+pub fn fmt_money(amount: i64, currency: &str) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    let amount = amount.unsigned_abs();
+    format!("{currency} {sign}{}.{:02}", amount / 100, amount % 100)
+}
+
+/// Formats a duration as decimal hours, as used on invoices
+pub fn fmt_hours(duration: Duration) -> String {
+    format!("{:.2}", duration.num_seconds() as f64 / 3600.0)
+}
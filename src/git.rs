@@ -0,0 +1,215 @@
+//! Links git repositories to tags. Blocks can be started with the tag of the repository
+//! in the current directory, and the commits made while a block ran can be listed with it.
+//! History is read with the `git` command, nothing is sent anywhere.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Local, TimeZone};
+use tracing::warn;
+
+use crate::database::{Block, Tag};
+use crate::settings::Repository;
+
+/// Separates the fields of each commit in `git log` output
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+#[derive(Clone, Debug)]
+pub struct Commit {
+    /// Where the repository is checked out
+    pub repository: PathBuf,
+    pub hash: String,
+    pub time: DateTime<Local>,
+    /// First line of the message
+    pub summary: String,
+}
+
+impl Commit {
+    /// Abbreviated hash, like git shows
+    pub fn short_hash(&self) -> &str {
+        &self.hash[..self.hash.len().min(8)]
+    }
+
+    /// The name of the folder the repository is checked out in
+    pub fn repository_name(&self) -> String {
+        (self.repository.file_name())
+            .unwrap_or(self.repository.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// Runs git in `dir`, returning what it printed
+fn git(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git, is it installed?")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Compares remote urls loosely, so `https://host/repo.git` and `https://host/repo/` match
+fn normalize_remote(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    url.strip_suffix(".git").unwrap_or(url).to_lowercase()
+}
+
+/// The configured repository that `dir` is in. Matches the checkout itself, or any other
+/// clone with the same remote.
+pub fn repository_for<'a>(repositories: &'a [Repository], dir: &Path) -> Option<&'a Repository> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let checked_out = repositories.iter().find(|repo| {
+        let path = repo
+            .path
+            .canonicalize()
+            .unwrap_or_else(|_| repo.path.clone());
+        !repo.path.as_os_str().is_empty() && dir.starts_with(path)
+    });
+    if checked_out.is_some() {
+        return checked_out;
+    }
+
+    // `git remote -v` prints lines like `origin  https://host/repo.git (fetch)`
+    let remotes = git(&dir, &["remote", "-v"]).ok()?;
+    let remotes: Vec<String> = (remotes.lines())
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(normalize_remote)
+        .collect();
+    repositories.iter().find(|repo| {
+        !repo.remote.trim().is_empty() && remotes.contains(&normalize_remote(&repo.remote))
+    })
+}
+
+/// The tag of the repository `dir` is in, for `start --here`
+pub fn tag_for(repositories: &[Repository], tags: &[Tag], dir: &Path) -> anyhow::Result<Tag> {
+    let repository = repository_for(repositories, dir)
+        .ok_or_else(|| anyhow!("{} isn't in a repository from the settings", dir.display()))?;
+    let tag = (repository.tag.as_ref())
+        .ok_or_else(|| anyhow!("{} doesn't have a tag", repository.path.display()))?;
+    // the settings keep a copy of the tag, which may have been renamed since
+    tags.iter()
+        .find(|t| *t == tag)
+        .cloned()
+        .ok_or_else(|| anyhow!("The tag for {} was deleted", repository.path.display()))
+}
+
+/// Commits made in the repository between `start` and `end`, by the user git is set up for
+pub fn commits_in(
+    repository: &Path,
+    start: DateTime<Local>,
+    end: DateTime<Local>,
+) -> anyhow::Result<Vec<Commit>> {
+    let since = format!("--since=@{}", start.timestamp());
+    let until = format!("--until=@{}", end.timestamp());
+    let format = format!("--format=%H{FIELD_SEPARATOR}%ct{FIELD_SEPARATOR}%s");
+    let mut args = vec!["log", "--all", "--no-merges", &since, &until, &format];
+    let email = git(repository, &["config", "user.email"]).unwrap_or_default();
+    let author = format!("--author={}", email.trim());
+    if !email.trim().is_empty() {
+        args.push(&author);
+    }
+
+    let log = git(repository, &args)?;
+    log.lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, FIELD_SEPARATOR);
+            let (Some(hash), Some(time), Some(summary)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                bail!("Unexpected git log output: {line}");
+            };
+            let time = (time.parse().ok())
+                .and_then(|t| Local.timestamp_opt(t, 0).single())
+                .ok_or_else(|| anyhow!("Unexpected commit time: {time}"))?;
+            Ok(Commit {
+                repository: repository.to_path_buf(),
+                hash: hash.to_string(),
+                time,
+                summary: summary.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Commits made in any of the repositories while the block ran, oldest first.
+/// Repositories that can't be read are logged and left out.
+pub fn commits_during(repositories: &[Repository], block: &Block) -> Vec<Commit> {
+    let mut commits: Vec<Commit> = repositories
+        .iter()
+        .filter(|repo| !repo.path.as_os_str().is_empty())
+        .flat_map(|repo| {
+            commits_in(&repo.path, block.start, block.end).unwrap_or_else(|e| {
+                warn!("Failed to read commits in {}: {e:#}", repo.path.display());
+                Vec::new()
+            })
+        })
+        .collect();
+    commits.sort_by_key(|c| c.time);
+    commits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_repositories_and_their_commits() {
+        let dir = std::env::temp_dir().join(format!("timekeeper-git-{}", std::process::id()));
+        let nested = dir.join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+        let run = |args: &[&str], date: &str| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .env("GIT_AUTHOR_DATE", date)
+                .env("GIT_COMMITTER_DATE", date)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        run(&["init", "-q"], "");
+        run(&["config", "user.email", "me@example.com"], "");
+        run(&["config", "user.name", "Me"], "");
+        run(
+            &["remote", "add", "origin", "https://example.com/Repo.git"],
+            "",
+        );
+        run(
+            &["commit", "-q", "--allow-empty", "-m", "Early"],
+            "2024-01-01T08:00:00Z",
+        );
+        run(
+            &["commit", "-q", "--allow-empty", "-m", "During"],
+            "2024-01-01T10:00:00Z",
+        );
+
+        let repository = |path: &Path, remote: &str| Repository {
+            path: path.to_path_buf(),
+            remote: remote.to_string(),
+            tag: None,
+        };
+        let by_path = [repository(&dir, "")];
+        assert!(repository_for(&by_path, &nested).is_some());
+        let by_remote = [repository(
+            Path::new("/elsewhere"),
+            "https://example.com/repo/",
+        )];
+        assert!(repository_for(&by_remote, &nested).is_some());
+        assert!(repository_for(&by_remote, &std::env::temp_dir()).is_none());
+
+        let at = |hour: i64| Local.timestamp_opt(1704067200 + hour * 3600, 0).unwrap();
+        let commits = commits_in(&dir, at(9), at(11)).unwrap();
+        let summaries: Vec<&str> = commits.iter().map(|c| &*c.summary).collect();
+        assert_eq!(summaries, ["During"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,12 +1,25 @@
-use chrono::{DateTime, Duration, Local, NaiveDateTime, NaiveTime, TimeZone};
+use std::hash::Hash;
+use std::path::PathBuf;
+
+use chrono::Timelike;
+use chrono::{DateTime, Days, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
 use eframe::egui::{self, DragValue, RichText};
 use eframe::epaint::Color32;
 use egui_extras::DatePickerButton;
 use tracing::info;
 
-use crate::database::{Database, Tag};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::database::{BackupInfo, BlockChange, BlockFilter, Database, Rule, RuleAction};
+use crate::database::{BlockEdit, Rate, Storage, Tag, TAG_PATH_SEPARATOR};
 // use crate::error::ReportAndContinue;
-use crate::history::{DayBlock, GoalState, History};
+use crate::export::Format;
+use crate::format::{fmt_duration, fmt_hours, fmt_money, fmt_rounded};
+use crate::history::{DayBlock, GoalState, History, Invoice, PlannedTotal, TagTotal};
+use crate::pomodoro::Pomodoro;
+use crate::profiles::{self, Profile};
+use crate::settings::{Rounding, RoundingDirection, RoundingLevel};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{api, export::REPORT_PLACEHOLDERS, git::Commit, hooks};
 use crate::{database::Block, settings::Settings};
 
 #[must_use]
@@ -14,12 +27,45 @@ pub enum GuiMessage {
     None,
     ChangedBlockTag(Block),
     DeletedBlock(Block),
+    RestoredBlock(Block),
+    PurgedBlock(Block),
     SetState(GuiState),
     StartStopwatch(Option<Tag>),
+    StartTimer(Option<Tag>, Duration),
+    StartPomodoro(Option<Tag>),
+    /// Stops the pomodoro session's block, keeping the time left in the phase
+    PausePomodoro,
+    ResumePomodoro,
     StopStopwatch,
     CreateTag(String),
     DeleteTag(Tag),
-    RenameTag(Tag),
+    UpdateTag(Tag),
+    /// Moves everything from the first tag into the second, and deletes the first
+    MergeTag(Tag, Tag),
+    Undo,
+    Redo,
+    SwitchProfile(String),
+    CreateBackup,
+    RestoreBackup(PathBuf),
+    ShowBlockHistory(Block),
+    /// Save an invoice summary for the days from the first to the last date
+    ExportInvoice(NaiveDate, NaiveDate, Format),
+    /// Save a timesheet for the week with this day in it
+    SaveReport(NaiveDate, Format),
+    /// Make the same change to every block, as one edit
+    EditBlocks(Vec<Block>, BlockEdit),
+    /// Divide the block at the time, tagging the parts before and after it
+    SplitBlock(Block, DateTime<Local>, [Option<Tag>; 2]),
+    /// Combine the blocks, counting the time between them
+    MergeBlocks(Block, Block),
+    /// Save a list of the blocks
+    ExportBlocks(Vec<Block>, Format),
+    #[cfg(not(target_arch = "wasm32"))]
+    CreateRule(Rule),
+    #[cfg(not(target_arch = "wasm32"))]
+    UpdateRule(Rule),
+    #[cfg(not(target_arch = "wasm32"))]
+    DeleteRule(Rule),
 }
 impl std::ops::BitOrAssign for GuiMessage {
     fn bitor_assign(&mut self, rhs: Self) {
@@ -36,7 +82,10 @@ pub enum GuiState {
     ThisWeek,
     History(DateTime<Local>),
     Tags(TagsGuiData),
-    Settings,
+    Trash,
+    Rules,
+    Search(SearchGuiData),
+    Settings(SettingsGuiData),
 }
 impl PartialEq for GuiState {
     fn eq(&self, other: &Self) -> bool {
@@ -49,24 +98,45 @@ impl GuiState {
         ui.horizontal(|ui| {
             ui.selectable_value(self, GuiState::Today, "Today");
             ui.selectable_value(self, GuiState::ThisWeek, "This Week");
-            if ui.selectable_label(matches!(self, GuiState::History(_)), "History").clicked() {
+            if ui
+                .selectable_label(matches!(self, GuiState::History(_)), "History")
+                .clicked()
+            {
                 *self = GuiState::History(Local::now());
             }
-            if ui.selectable_label(matches!(self, GuiState::Tags{ .. }), "Tags").clicked() {
+            if ui
+                .selectable_label(matches!(self, GuiState::Tags { .. }), "Tags")
+                .clicked()
+            {
                 *self = GuiState::Tags(TagsGuiData::default());
             }
-            ui.selectable_value(self, GuiState::Settings, "Settings");
+            ui.selectable_value(self, GuiState::Trash, "Trash");
+            ui.selectable_value(self, GuiState::Rules, "Rules");
+            if ui
+                .selectable_label(matches!(self, GuiState::Search(_)), "Search")
+                .clicked()
+            {
+                *self = GuiState::Search(SearchGuiData::default());
+            }
+            if ui
+                .selectable_label(matches!(self, GuiState::Settings(_)), "Settings")
+                .clicked()
+            {
+                *self = GuiState::Settings(SettingsGuiData::default());
+            }
         });
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
     pub(crate) fn draw_screen(
         &mut self,
         database: &Database,
         settings: &mut Settings,
+        profile: &Profile,
         ui: &mut egui::Ui,
     ) -> anyhow::Result<GuiMessage> {
         let mut history = History::new(database);
-        let mut tags = database.tags().all()?;
+        let tags = database.tags().all()?;
 
         let message = match self {
             GuiState::Today => draw_today(database, settings, ui)?,
@@ -74,8 +144,11 @@ impl GuiState {
             GuiState::History(datetime) => {
                 draw_history(*datetime, &tags, &mut history, settings, ui)
             }
-            GuiState::Tags(data) => data.draw(&mut tags, ui),
-            GuiState::Settings => draw_settings(settings, ui),
+            GuiState::Tags(data) => data.draw(&tags, ui),
+            GuiState::Trash => draw_trash(database, settings, ui)?,
+            GuiState::Rules => draw_rules(database, settings, &tags, ui)?,
+            GuiState::Search(data) => data.draw(database, settings, &tags, ui)?,
+            GuiState::Settings(data) => data.draw(database, settings, &tags, profile, ui),
         };
 
         Ok(message)
@@ -140,10 +213,27 @@ pub(crate) fn draw_goal(
     }
 }
 
+/// Options for the next block started from the stopwatch panel
+pub struct StopwatchGuiData {
+    tag: Option<Tag>,
+    planned_minutes: i64,
+}
+impl Default for StopwatchGuiData {
+    fn default() -> Self {
+        Self {
+            tag: None,
+            planned_minutes: 30,
+        }
+    }
+}
+
 pub(crate) fn draw_stopwatch(
     current: Option<Block>,
+    pomodoro: Option<&Pomodoro>,
+    data: &mut StopwatchGuiData,
     mut history: History<'_>,
-    settings: &Settings,
+    tags: &[Tag],
+    settings: &mut Settings,
     ui: &mut egui::Ui,
 ) -> GuiMessage {
     ui.with_layout(
@@ -152,60 +242,177 @@ pub(crate) fn draw_stopwatch(
             draw_goals(current.is_some(), &mut history, settings, ui);
 
             if let Some(current) = current {
-                let text = format!("{}\tStop", fmt_duration(current.duration()));
-                let button =
-                    egui::Button::new(RichText::new(text).heading()).fill(Color32::DARK_GREEN);
-                if ui.add(button).clicked() {
+                let mut fill = tag_color(current.tag.as_ref());
+                let text = if let Some(pomodoro) = pomodoro {
+                    format!(
+                        "{} - {} left\tStop",
+                        pomodoro.phase().label(),
+                        fmt_duration(pomodoro.remaining(settings, Local::now()))
+                    )
+                } else if let Some(planned) = current.planned {
+                    let remaining = planned - current.duration();
+                    if remaining < Duration::zero() {
+                        fill = Color32::DARK_RED;
+                        format!("{} over\tStop", fmt_duration(-remaining))
+                    } else {
+                        format!("{} left\tStop", fmt_duration(remaining))
+                    }
+                } else {
+                    format!("{}\tStop", fmt_duration(current.duration()))
+                };
+                let button = egui::Button::new(RichText::new(text).heading()).fill(fill);
+                let stop = ui.add(button).clicked();
+                if pomodoro.is_some() && ui.button("Pause").clicked() {
+                    GuiMessage::PausePomodoro
+                } else if stop {
+                    GuiMessage::StopStopwatch
+                } else {
+                    GuiMessage::None
+                }
+            } else if let Some(pomodoro) = pomodoro.filter(|p| p.is_paused()) {
+                let text = format!(
+                    "{} - {} left, paused\tResume",
+                    pomodoro.phase().label(),
+                    fmt_duration(pomodoro.remaining(settings, Local::now()))
+                );
+                if ui.button(RichText::new(text).heading()).clicked() {
+                    GuiMessage::ResumePomodoro
+                } else if ui.button("End session").clicked() {
                     GuiMessage::StopStopwatch
                 } else {
                     GuiMessage::None
                 }
-            } else if ui.button(RichText::new("Start").heading()).clicked() {
-                GuiMessage::StartStopwatch(None)
             } else {
-                GuiMessage::None
+                ui.horizontal(|ui| {
+                    let selected = data
+                        .tag
+                        .as_ref()
+                        .map(|t| tag_text(t, tags))
+                        .unwrap_or_default();
+                    egui::ComboBox::from_id_salt("stopwatch-next-tag")
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut data.tag, None, "No tag");
+                            for tag in pickable(tags) {
+                                ui.selectable_value(
+                                    &mut data.tag,
+                                    Some(tag.clone()),
+                                    tag_text(tag, tags),
+                                );
+                            }
+                        });
+                    ui.add(
+                        DragValue::new(&mut data.planned_minutes)
+                            .range(1..=720)
+                            .speed(0.2)
+                            .suffix(" minutes"),
+                    );
+                    ui.checkbox(&mut settings.stop_at_planned, "Stop when done");
+                });
+
+                if ui.button(RichText::new("Start").heading()).clicked() {
+                    GuiMessage::StartStopwatch(data.tag.clone())
+                } else if ui.button(RichText::new("Start Timer").heading()).clicked() {
+                    let planned = Duration::minutes(data.planned_minutes);
+                    GuiMessage::StartTimer(data.tag.clone(), planned)
+                } else if ui
+                    .button(RichText::new("Start Pomodoro").heading())
+                    .clicked()
+                {
+                    GuiMessage::StartPomodoro(data.tag.clone())
+                } else {
+                    GuiMessage::None
+                }
             }
         },
     )
     .inner
 }
 
-pub fn fmt_duration(mut duration: Duration) -> String {
-    //Assume negative durations are rounding errors, so move to zero
-    duration = duration.max(Duration::zero());
+fn billable_label(billable: Option<bool>) -> &'static str {
+    match billable {
+        None => "Billable as parent",
+        Some(true) => "Billable",
+        Some(false) => "Not billable",
+    }
+}
 
-    let hours = duration.num_hours();
-    let minutes = duration.num_minutes() % 60;
-    let seconds = duration.num_seconds() % 60;
+/// Indent per level of nesting when drawing the tag tree
+const TAG_INDENT: f32 = 16.0;
 
-    if hours > 0 {
-        format!("{}h {}m", hours, minutes)
-    } else {
-        format!("{}m {}s", minutes, seconds)
+/// The tag's colour, or the default stopwatch colour if it has none
+fn tag_color(tag: Option<&Tag>) -> Color32 {
+    tag.and_then(|t| t.color)
+        .map_or(Color32::DARK_GREEN, |[r, g, b]| Color32::from_rgb(r, g, b))
+}
+
+/// The tag's icon and name, in the tag's colour if it has one
+fn tag_label(tag: &Tag) -> RichText {
+    tag_colored(RichText::new(tag.label()), tag)
+}
+
+/// Tags that can be chosen for blocks, leaving out archived ones
+fn pickable(tags: &[Tag]) -> impl Iterator<Item = &Tag> {
+    tags.iter().filter(|t| !t.is_archived(tags))
+}
+
+/// A drop down of the tags that can be picked, or none
+fn draw_tag_picker(id_salt: impl Hash, tag: &mut Option<Tag>, tags: &[Tag], ui: &mut egui::Ui) {
+    let selected = tag.as_ref().map(|t| tag_text(t, tags));
+    egui::ComboBox::from_id_salt(id_salt)
+        .selected_text(selected.unwrap_or_default())
+        .show_ui(ui, |ui| {
+            for picked in pickable(tags) {
+                ui.selectable_value(tag, Some(picked.clone()), tag_text(picked, tags));
+            }
+            ui.separator();
+            ui.selectable_value(tag, None, "Untagged");
+        });
+}
+
+/// The tag's icon and full path, e.g. `ACME / Website / QA`, in the tag's colour
+fn tag_text(tag: &Tag, tags: &[Tag]) -> RichText {
+    let path = tag.path(tags);
+    let text = match &tag.icon {
+        Some(icon) => format!("{icon} {path}"),
+        None => path,
+    };
+    tag_colored(RichText::new(text), tag)
+}
+
+fn tag_colored(text: RichText, tag: &Tag) -> RichText {
+    match tag.color {
+        Some([r, g, b]) => text.color(Color32::from_rgb(r, g, b)),
+        None => text,
     }
 }
 
-fn draw_today(
-    database: &Database,
+fn fmt_pomodoros(count: usize) -> String {
+    format!("🍅 x{}", count)
+}
+
+pub(crate) fn draw_today(
+    storage: &dyn Storage,
     settings: &Settings,
     ui: &mut egui::Ui,
 ) -> anyhow::Result<GuiMessage> {
     let now = Local::now();
-    let history = History::new(database);
+    let history = History::new(storage);
 
     let (total, blocks) = history.blocks_in_day(now);
 
+    let pomodoros = History::count_pomodoros(&blocks);
+    let rounded = settings.rounding.day_total(&blocks);
+
     ui.horizontal(|ui| {
         ui.label(RichText::new(now.format(&settings.date_format).to_string()).heading());
-        ui.label(RichText::new(fmt_duration(total)).heading());
+        ui.label(RichText::new(fmt_rounded(total, rounded)).heading());
+        if pomodoros > 0 {
+            ui.label(RichText::new(fmt_pomodoros(pomodoros)).heading());
+        }
     });
 
-    Ok(draw_block_table(
-        blocks,
-        &database.tags().all()?,
-        settings,
-        ui,
-    ))
+    Ok(draw_block_table(blocks, &storage.tags()?, settings, ui))
 }
 
 fn draw_block_table(
@@ -219,12 +426,26 @@ fn draw_block_table(
     }
 
     let mut message = GuiMessage::None;
+    let selection_id = egui::Id::new(("block-selection", blocks[0].id()));
+    let mut selection: BlockSelection = ui
+        .data_mut(|d| d.get_temp(selection_id))
+        .unwrap_or_default();
+    let ids: Vec<usize> = blocks.iter().map(|b| b.id()).collect();
+    selection.check_applied(ui);
+    selection.ids.retain(|id| ids.contains(id));
+    let split_id = egui::Id::new(("block-split", blocks[0].id()));
+    let mut split: Option<SplitForm> = ui.data_mut(|d| d.get_temp(split_id)).flatten();
 
     egui::Grid::new(blocks[0].id())
-        .num_columns(3)
+        .num_columns(4)
         .striped(true)
         .show(ui, |ui| {
-            for mut block in blocks {
+            for (row, mut block) in blocks.clone().into_iter().enumerate() {
+                let mut checked = selection.ids.contains(&block.id());
+                if ui.checkbox(&mut checked, "").changed() {
+                    selection.select(&ids, row, checked, ui.input(|i| i.modifiers.shift));
+                }
+                let next = blocks.get(row + 1);
                 ui.horizontal(|ui| {
                     ui.label(block.start.format(&settings.time_format).to_string());
                     ui.label("->");
@@ -233,23 +454,37 @@ fn draw_block_table(
                         ui.label(block.end.format(&settings.date_format).to_string());
                     }
                     ui.label(block.end.format(&settings.time_format).to_string());
+                })
+                .response
+                .interact(egui::Sense::click())
+                .context_menu(|ui| {
+                    if ui.button("Split…").clicked() {
+                        split = Some(SplitForm::new(&block));
+                        ui.close_menu();
+                    }
+                    if let Some(next) = next {
+                        message |= draw_merge_menu(&block, next, ui);
+                    }
                 });
-                ui.label(fmt_duration(block.duration()));
+                let duration = block.duration();
+                ui.label(fmt_rounded(duration, settings.rounding.block(duration)));
 
                 let old_tag = block.tag.clone();
                 let mut to_delete = false;
+                let mut show_history = false;
 
                 ui.horizontal(|ui| {
-                    let tag_text = if let Some(tag) = &block.tag {
-                        &tag.name
-                    } else {
-                        ""
-                    };
+                    let selected = block.tag.as_ref().map(|t| tag_text(t, tags));
+                    let selected = selected.unwrap_or_default();
                     egui::ComboBox::from_id_salt(block.id())
-                        .selected_text(tag_text)
+                        .selected_text(selected)
                         .show_ui(ui, |ui| {
-                            for tag in tags {
-                                ui.selectable_value(&mut block.tag, Some(tag.clone()), &tag.name);
+                            for tag in pickable(tags) {
+                                ui.selectable_value(
+                                    &mut block.tag,
+                                    Some(tag.clone()),
+                                    tag_text(tag, tags),
+                                );
                             }
                             if old_tag.is_some() {
                                 ui.separator();
@@ -258,10 +493,13 @@ fn draw_block_table(
                         });
 
                     to_delete = ui.button("X").clicked();
+                    show_history = ui.button("🕓").on_hover_text("History").clicked();
                 });
 
                 if to_delete {
                     message = GuiMessage::DeletedBlock(block);
+                } else if show_history {
+                    message = GuiMessage::ShowBlockHistory(block);
                 } else if old_tag != block.tag {
                     message = GuiMessage::ChangedBlockTag(block);
                 }
@@ -270,10 +508,335 @@ fn draw_block_table(
             }
         });
 
+    if !selection.ids.is_empty() {
+        message |= selection.draw(&blocks, tags, ui);
+    }
+    if let Some(form) = &mut split {
+        let (done, split_message) = form.draw(tags, settings, ui);
+        message |= split_message;
+        if done {
+            split = None;
+        }
+    }
+    ui.data_mut(|d| {
+        d.insert_temp(selection_id, selection);
+        d.insert_temp(split_id, split);
+    });
+
     message
 }
 
-fn draw_this_week(
+/// Merges the block with the one after it, saying so if a gap between them will be counted
+fn draw_merge_menu(block: &Block, next: &Block, ui: &mut egui::Ui) -> GuiMessage {
+    let gap = next.start - block.end;
+    let label = if gap > Duration::zero() {
+        format!(
+            "Merge with next, counting the {} between",
+            fmt_duration(gap)
+        )
+    } else {
+        "Merge with next".to_string()
+    };
+    if ui.button(label).clicked() {
+        ui.close_menu();
+        GuiMessage::MergeBlocks(block.clone(), next.clone())
+    } else {
+        GuiMessage::None
+    }
+}
+
+/// A block being split, with the time to split it at and the tags of each part
+#[derive(Clone)]
+struct SplitForm {
+    block: Block,
+    at: NaiveTime,
+    tags: [Option<Tag>; 2],
+}
+
+impl SplitForm {
+    /// Splits the block in the middle by default
+    fn new(block: &Block) -> Self {
+        let middle = block.start + block.duration() / 2;
+        Self {
+            block: block.clone(),
+            at: middle.time().with_second(0).unwrap_or(middle.time()),
+            tags: [block.tag.clone(), block.tag.clone()],
+        }
+    }
+
+    /// The time to split at, on the day the block started
+    fn at(&self) -> Option<DateTime<Local>> {
+        let at = self.block.start.date_naive().and_time(self.at);
+        Local.from_local_datetime(&at).earliest()
+    }
+
+    /// Returns whether the form is done with, and the split to make
+    fn draw(&mut self, tags: &[Tag], settings: &Settings, ui: &mut egui::Ui) -> (bool, GuiMessage) {
+        let (mut done, mut message) = (false, GuiMessage::None);
+        let id = self.block.id();
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Split {} -> {} at",
+                self.block.start.format(&settings.time_format),
+                self.block.end.format(&settings.time_format)
+            ));
+            let mut hour = self.at.hour();
+            let mut minute = self.at.minute();
+            ui.add(DragValue::new(&mut hour).range(0..=23).speed(0.1));
+            ui.label(":");
+            ui.add(DragValue::new(&mut minute).range(0..=59).speed(0.2));
+            if let Some(time) = NaiveTime::from_hms_opt(hour, minute, 0) {
+                self.at = time;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Before");
+            draw_tag_picker(("split-before", id), &mut self.tags[0], tags, ui);
+            ui.label("After");
+            draw_tag_picker(("split-after", id), &mut self.tags[1], tags, ui);
+        });
+
+        let end = if self.block.running {
+            Local::now()
+        } else {
+            self.block.end
+        };
+        let at = self.at().filter(|&at| at > self.block.start && at < end);
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(at.is_some(), egui::Button::new("Split"))
+                .clicked()
+            {
+                if let Some(at) = at {
+                    message = GuiMessage::SplitBlock(self.block.clone(), at, self.tags.clone());
+                    done = true;
+                }
+            }
+            if ui.button("Cancel").clicked() {
+                done = true;
+            }
+            if at.is_none() {
+                ui.colored_label(Color32::RED, "Pick a time during the block");
+            }
+        });
+        (done, message)
+    }
+}
+
+/// Where the window leaves how a bulk edit went for the block table: why it failed, or
+/// `None` if it was made
+pub(crate) fn bulk_edit_outcome_id() -> egui::Id {
+    egui::Id::new("bulk-edit-outcome")
+}
+
+/// Rows picked in a block table to be edited together, kept in egui's memory between frames
+#[derive(Clone, Default)]
+struct BlockSelection {
+    ids: Vec<usize>,
+    /// The row last clicked, which shift-clicks select from
+    anchor: Option<usize>,
+    action: BulkAction,
+    tag: Option<Tag>,
+    shift_minutes: i64,
+    /// Set once an edit is sent, until the window says how it went
+    applying: bool,
+    /// Why the last edit failed
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum BulkAction {
+    #[default]
+    SetTag,
+    Merge,
+    Shift,
+    Delete,
+}
+
+impl BulkAction {
+    fn label(self) -> &'static str {
+        match self {
+            BulkAction::SetTag => "Set tag",
+            BulkAction::Merge => "Merge",
+            BulkAction::Shift => "Shift",
+            BulkAction::Delete => "Delete",
+        }
+    }
+}
+
+impl BlockSelection {
+    /// Checks or unchecks the row, or every row from the last one clicked when shift is held
+    fn select(&mut self, ids: &[usize], row: usize, checked: bool, shift: bool) {
+        let anchor = self.anchor.and_then(|a| ids.iter().position(|&id| id == a));
+        let rows = match anchor {
+            Some(anchor) if shift => anchor.min(row)..=anchor.max(row),
+            _ => row..=row,
+        };
+        for &id in &ids[rows] {
+            self.ids.retain(|&selected| selected != id);
+            if checked {
+                self.ids.push(id);
+            }
+        }
+        self.anchor = Some(ids[row]);
+        self.error = None;
+    }
+
+    /// Clears the selection once its edit is made, or keeps it with why the edit failed
+    fn check_applied(&mut self, ui: &egui::Ui) {
+        if !self.applying {
+            return;
+        }
+        let Some(outcome) = ui.data_mut(|d| d.remove_temp(bulk_edit_outcome_id())) else {
+            return;
+        };
+        self.applying = false;
+        match outcome {
+            None => self.ids.clear(),
+            Some(error) => self.error = Some(error),
+        }
+    }
+
+    fn edit(&self) -> BlockEdit {
+        match self.action {
+            BulkAction::SetTag => BlockEdit::SetTag(self.tag.clone()),
+            BulkAction::Merge => BlockEdit::Merge,
+            BulkAction::Shift => BlockEdit::Shift(Duration::minutes(self.shift_minutes)),
+            BulkAction::Delete => BlockEdit::Delete,
+        }
+    }
+
+    /// Picks what to do with the selected blocks, previewing the totals it leads to
+    fn draw(&mut self, blocks: &[Block], tags: &[Tag], ui: &mut egui::Ui) -> GuiMessage {
+        let mut message = GuiMessage::None;
+        let rows: Vec<usize> = (0..blocks.len())
+            .filter(|&row| self.ids.contains(&blocks[row].id()))
+            .collect();
+        let selected: Vec<Block> = rows.iter().map(|&row| blocks[row].clone()).collect();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("{} selected", selected.len()));
+            egui::ComboBox::from_id_salt(("bulk-action", blocks[0].id()))
+                .selected_text(self.action.label())
+                .show_ui(ui, |ui| {
+                    for action in [
+                        BulkAction::SetTag,
+                        BulkAction::Merge,
+                        BulkAction::Shift,
+                        BulkAction::Delete,
+                    ] {
+                        ui.selectable_value(&mut self.action, action, action.label());
+                    }
+                });
+            match self.action {
+                BulkAction::SetTag => {
+                    draw_tag_picker(("bulk-tag", blocks[0].id()), &mut self.tag, tags, ui);
+                }
+                BulkAction::Shift => {
+                    ui.add(
+                        DragValue::new(&mut self.shift_minutes)
+                            .range(-24 * 60..=24 * 60)
+                            .suffix("m"),
+                    );
+                }
+                BulkAction::Merge | BulkAction::Delete => (),
+            }
+        });
+
+        let edit = self.edit();
+        let adjacent = rows.windows(2).all(|pair| pair[1] == pair[0] + 1);
+        let edited = if edit == BlockEdit::Merge && !adjacent {
+            Err(anyhow::anyhow!(
+                "Only rows next to each other can be merged"
+            ))
+        } else {
+            edit.apply(&selected)
+        };
+        ui.horizontal(|ui| {
+            match edited {
+                Ok(edited) => {
+                    let total = |blocks: &[Block]| blocks.iter().map(Block::duration).sum();
+                    let (before, after): (Duration, Duration) = (total(&selected), total(&edited));
+                    let table: Duration = total(blocks);
+                    ui.label(format!(
+                        "{} -> {}, total {} -> {}",
+                        fmt_duration(before),
+                        fmt_duration(after),
+                        fmt_duration(table),
+                        fmt_duration(table - before + after)
+                    ));
+                    if edit == BlockEdit::Merge && after > before {
+                        ui.label(format!(
+                            "counting the {} between",
+                            fmt_duration(after - before)
+                        ));
+                    }
+                    if ui.button("Apply").clicked() {
+                        message = GuiMessage::EditBlocks(selected, edit);
+                        self.applying = true;
+                        self.error = None;
+                    }
+                }
+                Err(e) => {
+                    ui.colored_label(Color32::RED, e.to_string());
+                }
+            }
+            if ui.button("Clear selection").clicked() {
+                self.ids.clear();
+                self.error = None;
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        message
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Lists every recorded change to a block, oldest first
+pub fn draw_block_history(changes: &[BlockChange], settings: &Settings, ui: &mut egui::Ui) {
+    if changes.is_empty() {
+        ui.label("No changes recorded");
+        return;
+    }
+    let format = format!("{} {}", settings.date_format, settings.time_format);
+    egui::Grid::new("block-history")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            for change in changes {
+                ui.label(change.changed.format(&format).to_string());
+                ui.label(&change.action);
+                ui.label(change.describe(&settings.time_format));
+                ui.end_row();
+            }
+        });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Lists commits made in the linked repositories while a block ran
+pub fn draw_commits(commits: &[Commit], settings: &Settings, ui: &mut egui::Ui) {
+    ui.heading("Commits");
+    if commits.is_empty() {
+        ui.label("No commits while this block ran");
+        return;
+    }
+    egui::Grid::new("block-commits")
+        .num_columns(4)
+        .striped(true)
+        .show(ui, |ui| {
+            for commit in commits {
+                ui.label(commit.time.format(&settings.time_format).to_string());
+                ui.label(commit.repository_name());
+                ui.monospace(commit.short_hash());
+                ui.label(&commit.summary);
+                ui.end_row();
+            }
+        });
+}
+
+pub(crate) fn draw_this_week(
     settings: &Settings,
     tags: &[Tag],
     history: &mut History<'_>,
@@ -293,16 +856,30 @@ fn draw_week(
     let mut message = GuiMessage::None;
 
     let (total, blocks) = history.blocks_in_week(day, settings);
+    let planned = History::planned_vs_actual(blocks.iter().flat_map(|d| &d.blocks));
+    let tag_totals = History::tag_totals(blocks.iter().flat_map(|d| &d.blocks), tags);
 
-    for DayBlock { day, blocks, total } in blocks {
+    let rounded = blocks.iter().fold(Duration::zero(), |a, d| a + d.rounded);
+
+    for DayBlock {
+        day,
+        blocks,
+        total,
+        rounded,
+        pomodoros,
+    } in blocks
+    {
         if total.is_zero() {
             continue;
         }
-        let header = format!(
+        let mut header = format!(
             "{} - {}",
             day.format(&settings.date_format),
-            fmt_duration(total)
+            fmt_rounded(total, rounded)
         );
+        if pomodoros > 0 {
+            header = format!("{} - {}", header, fmt_pomodoros(pomodoros));
+        }
         egui::CollapsingHeader::new(RichText::new(header).heading())
             .id_salt(day.naive_local().date())
             .show(ui, |ui| {
@@ -312,12 +889,131 @@ fn draw_week(
     ui.separator();
     ui.horizontal(|ui| {
         ui.label(RichText::new("Total:").heading());
-        ui.label(RichText::new(fmt_duration(total)).heading());
+        ui.label(RichText::new(fmt_rounded(total, rounded)).heading());
+    });
+
+    if !planned.is_empty() {
+        ui.separator();
+        draw_planned_vs_actual(&planned, tags, ui);
+    }
+
+    if !tag_totals.is_empty() {
+        ui.separator();
+        draw_tag_totals(&tag_totals, ui);
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Save report as");
+        for format in [Format::Markdown, Format::Html] {
+            if ui.button(format!("{format:?}")).clicked() {
+                message |= GuiMessage::SaveReport(day.date_naive(), format);
+            }
+        }
     });
 
+    let first = History::start_of_week(day, settings).date_naive();
+    let last = first + Days::new(6);
+    match history.invoice(first, last, settings) {
+        Ok(invoice) if !invoice.by_tag.is_empty() => {
+            ui.separator();
+            message |= draw_billable(&invoice, ui);
+        }
+        Ok(_) => (),
+        Err(e) => tracing::warn!("Failed to total billable time: {e:#}"),
+    }
+
+    message
+}
+
+fn draw_planned_vs_actual(totals: &[PlannedTotal], tags: &[Tag], ui: &mut egui::Ui) {
+    ui.label(RichText::new("Planned vs actual").heading());
+    egui::Grid::new("planned-vs-actual")
+        .num_columns(4)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Tag");
+            ui.label("Planned");
+            ui.label("Actual");
+            ui.label("Difference");
+            ui.end_row();
+
+            for total in totals {
+                match &total.tag {
+                    Some(tag) => ui.label(tag_text(tag, tags)),
+                    None => ui.label("Untagged"),
+                };
+                ui.label(fmt_duration(total.planned));
+                ui.label(fmt_duration(total.actual));
+                let difference = total.actual - total.planned;
+                if difference < Duration::zero() {
+                    ui.label(format!("-{}", fmt_duration(-difference)));
+                } else {
+                    ui.label(format!("+{}", fmt_duration(difference)));
+                }
+                ui.end_row();
+            }
+        });
+}
+
+/// Billable time and amounts per tag, with buttons to save them as an invoice summary
+fn draw_billable(invoice: &Invoice, ui: &mut egui::Ui) -> GuiMessage {
+    ui.label(RichText::new("Billable").heading());
+    egui::Grid::new("billable")
+        .num_columns(4)
+        .striped(true)
+        .show(ui, |ui| {
+            ui.label("Tag");
+            ui.label("Recorded");
+            ui.label("Billed");
+            ui.label("Amount");
+            ui.end_row();
+
+            for line in &invoice.by_tag {
+                ui.label(&line.tag);
+                ui.label(format!("{} h", fmt_hours(line.raw)));
+                ui.label(format!("{} h", fmt_hours(line.time)));
+                ui.label(fmt_money(line.amount, &line.rate.currency));
+                ui.end_row();
+            }
+            for (currency, total) in &invoice.totals {
+                ui.label(RichText::new("Total").strong());
+                ui.label("");
+                ui.label("");
+                ui.label(RichText::new(fmt_money(*total, currency)).strong());
+                ui.end_row();
+            }
+        });
+
+    let mut message = GuiMessage::None;
+    ui.horizontal(|ui| {
+        ui.label("Save invoice summary as");
+        for format in [Format::Markdown, Format::Html] {
+            if ui.button(format!("{format:?}")).clicked() {
+                message = GuiMessage::ExportInvoice(invoice.first, invoice.last, format);
+            }
+        }
+    });
     message
 }
 
+/// Time per tag as a tree, each tag including the time of the tags nested under it
+fn draw_tag_totals(totals: &[TagTotal], ui: &mut egui::Ui) {
+    ui.label(RichText::new("Time by tag").heading());
+    egui::Grid::new("tag-totals")
+        .num_columns(2)
+        .striped(true)
+        .show(ui, |ui| {
+            for total in totals {
+                ui.horizontal(|ui| {
+                    ui.add_space(total.depth as f32 * TAG_INDENT);
+                    ui.label(tag_label(&total.tag));
+                });
+                ui.label(fmt_duration(total.total));
+                ui.end_row();
+            }
+        });
+}
+
 fn draw_history(
     date: DateTime<Local>,
     tags: &[Tag],
@@ -366,58 +1062,421 @@ fn draw_history(
     draw_week(start_of_week, tags, settings, history, ui)
 }
 
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq)]
-pub struct TagsGuiData {
-    new_name: String,
-    edit_tag: Option<Tag>,
-    focus_edit: bool,
-}
-impl Default for TagsGuiData {
-    fn default() -> Self {
-        TagsGuiData { new_name: String::new(), edit_tag: None, focus_edit: false }
-    }
-}
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_trash(
+    database: &Database,
+    settings: &mut Settings,
+    ui: &mut egui::Ui,
+) -> anyhow::Result<GuiMessage> {
+    let mut message = GuiMessage::None;
 
-impl TagsGuiData {
-    fn draw(&mut self, tags: &[Tag], ui: &mut egui::Ui) -> GuiMessage {
-        let mut message = GuiMessage::None;
-        for tag in tags {
-            message |= self.draw_row(tag, ui)
-        }
+    ui.horizontal(|ui| {
+        ui.label("Deleted blocks are removed for good after");
+        ui.add(
+            DragValue::new(&mut settings.trash_days)
+                .range(1..=365)
+                .speed(0.2)
+                .suffix(" days"),
+        );
+    });
+    ui.separator();
 
-        ui.separator();
+    let blocks = database.blocks().trash()?;
+    let tags = database.tags().all()?;
+    if blocks.is_empty() {
+        ui.label("The trash is empty");
+        return Ok(message);
+    }
 
-        ui.horizontal(|ui| {
-            ui.text_edit_singleline(&mut self.new_name);
-            if ui.button("Create").clicked() {
-                message |= GuiMessage::CreateTag(std::mem::take(&mut self.new_name));
+    egui::Grid::new("trash-grid")
+        .num_columns(5)
+        .striped(true)
+        .show(ui, |ui| {
+            for block in blocks {
+                ui.label(format!(
+                    "{} {} -> {}",
+                    block.start.format(&settings.date_format),
+                    block.start.format(&settings.time_format),
+                    block.end.format(&settings.time_format)
+                ));
+                ui.label(fmt_duration(block.duration()));
+                ui.label(
+                    block
+                        .tag
+                        .as_ref()
+                        .map(|t| tag_text(t, &tags))
+                        .unwrap_or_default(),
+                );
+                if let Some(deleted) = block.deleted {
+                    ui.label(format!("deleted {}", deleted.format(&settings.date_format)));
+                } else {
+                    ui.label("");
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        message = GuiMessage::RestoredBlock(block.clone());
+                    }
+                    if ui.button("Delete forever").clicked() {
+                        message = GuiMessage::PurgedBlock(block.clone());
+                    }
+                });
+                ui.end_row();
             }
         });
 
-        message
-    }
+    Ok(message)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+/// Lists scheduled rules for editing, and when they last fired
+fn draw_rules(
+    database: &Database,
+    settings: &Settings,
+    tags: &[Tag],
+    ui: &mut egui::Ui,
+) -> anyhow::Result<GuiMessage> {
+    let mut message = GuiMessage::None;
+    ui.label("Start or stop blocks at a time of day. Rules fire while the app is open.");
+
+    egui::Grid::new("rules-grid")
+        .num_columns(6)
+        .striped(true)
+        .show(ui, |ui| {
+            for rule in database.rules().all()? {
+                let mut edited = rule.clone();
+                ui.checkbox(&mut edited.enabled, "");
+                egui::ComboBox::from_id_salt(("rule-action", rule.id()))
+                    .selected_text(match edited.action {
+                        RuleAction::Start => "Start",
+                        RuleAction::Stop => "Stop",
+                    })
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut edited.action, RuleAction::Start, "Start");
+                        ui.selectable_value(&mut edited.action, RuleAction::Stop, "Stop");
+                    });
+
+                ui.horizontal(|ui| {
+                    ui.label("at");
+                    let mut hour = edited.time.hour();
+                    let mut minute = edited.time.minute();
+                    ui.add(DragValue::new(&mut hour).range(0..=23).speed(0.1));
+                    ui.label(":");
+                    ui.add(DragValue::new(&mut minute).range(0..=59).speed(0.2));
+                    if let Some(time) = NaiveTime::from_hms_opt(hour, minute, 0) {
+                        edited.time = time;
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    let days = ["M", "T", "W", "T", "F", "S", "S"];
+                    for (day, name) in edited.days.iter_mut().zip(days) {
+                        ui.toggle_value(day, name);
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if edited.action == RuleAction::Start {
+                        let selected = edited.tag.as_ref().map(|t| tag_text(t, tags));
+                        egui::ComboBox::from_id_salt(("rule-tag", rule.id()))
+                            .selected_text(selected.unwrap_or_default())
+                            .show_ui(ui, |ui| {
+                                for tag in pickable(tags) {
+                                    let text = tag_text(tag, tags);
+                                    ui.selectable_value(&mut edited.tag, Some(tag.clone()), text);
+                                }
+                                ui.separator();
+                                ui.selectable_value(&mut edited.tag, None, "Untagged");
+                            });
+
+                        let mut stops = edited.planned.is_some();
+                        ui.checkbox(&mut stops, "for");
+                        match (stops, edited.planned) {
+                            (true, None) => edited.planned = Some(Duration::minutes(15)),
+                            (false, Some(_)) => edited.planned = None,
+                            _ => (),
+                        }
+                        if let Some(planned) = &mut edited.planned {
+                            draw_minutes(planned, ui);
+                        }
+                    }
+                });
+
+                if ui.button("X").clicked() {
+                    message = GuiMessage::DeleteRule(rule);
+                } else if edited != rule {
+                    message = GuiMessage::UpdateRule(edited);
+                }
+                ui.end_row();
+            }
+            anyhow::Ok(())
+        })
+        .inner?;
+    if ui.button("Add rule").clicked() {
+        message = GuiMessage::CreateRule(Rule::new());
+    }
+
+    ui.separator();
+    ui.heading("Log");
+    let firings = database.rules().firings(50)?;
+    if firings.is_empty() {
+        ui.label("No rules have fired yet");
+    }
+    let format = format!("{} {}", settings.date_format, settings.time_format);
+    egui::Grid::new("rule-firings")
+        .num_columns(3)
+        .striped(true)
+        .show(ui, |ui| {
+            for firing in firings {
+                ui.label(firing.fired.format(&format).to_string());
+                ui.label(firing.description);
+                ui.label(firing.outcome);
+                ui.end_row();
+            }
+        });
+
+    Ok(message)
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Default)]
+pub struct SearchGuiData {
+    tags: Vec<Tag>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    min_minutes: i64,
+    text: String,
+    retag: Option<Tag>,
+    confirm_delete: bool,
+    /// Blocks found and the filter they were found with, so the database is only searched
+    /// again when the filter changes or the blocks are edited
+    #[serde(skip)]
+    #[cfg(not(target_arch = "wasm32"))]
+    results: Option<(BlockFilter, Vec<Block>)>,
+}
+
+impl SearchGuiData {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn filter(&self) -> BlockFilter {
+        BlockFilter {
+            tags: self.tags.clone(),
+            from: self.from,
+            to: self.to,
+            min_duration: (self.min_minutes > 0).then(|| Duration::minutes(self.min_minutes)),
+            text: self.text.clone(),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw(
+        &mut self,
+        database: &Database,
+        settings: &Settings,
+        tags: &[Tag],
+        ui: &mut egui::Ui,
+    ) -> anyhow::Result<GuiMessage> {
+        let mut message = GuiMessage::None;
+        self.draw_filter(tags, ui);
+        ui.separator();
+
+        let filter = self.filter();
+        if self.results.as_ref().map(|(searched, _)| searched) != Some(&filter) {
+            let blocks = database.blocks().query(&filter)?;
+            self.results = Some((filter, blocks));
+        }
+        let Some((_, blocks)) = &self.results else {
+            return Ok(message);
+        };
+        if blocks.is_empty() {
+            ui.label("No blocks match");
+            return Ok(message);
+        }
+        let mut edited = false;
+        let total: Duration = blocks.iter().map(Block::duration).sum();
+        ui.label(format!(
+            "{} blocks, {} in total. The latest was on {}.",
+            blocks.len(),
+            fmt_duration(total),
+            blocks[0].start.format(&settings.date_format)
+        ));
 
-    fn draw_row(&mut self, tag: &Tag, ui: &mut egui::Ui) -> GuiMessage {
-        let mut message = GuiMessage::None;
         ui.horizontal(|ui| {
-            if Some(&*tag) == self.edit_tag.as_ref() {
-                let response = {
-                    let tag = self.edit_tag.as_mut().expect("Already checked if this option is Some");
-                    ui.text_edit_singleline(&mut tag.name)
-                };
-                if self.focus_edit {
-                    response.request_focus();
-                    self.focus_edit = false;
+            ui.label("Tag them all");
+            draw_tag_picker("search-retag", &mut self.retag, tags, ui);
+            if ui.button("Retag").clicked() {
+                let edit = BlockEdit::SetTag(self.retag.clone());
+                message |= GuiMessage::EditBlocks(blocks.clone(), edit);
+                edited = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            if self.confirm_delete {
+                ui.label(format!("Move {} blocks to the trash?", blocks.len()));
+                if ui.button("Delete").clicked() {
+                    message |= GuiMessage::EditBlocks(blocks.clone(), BlockEdit::Delete);
+                    self.confirm_delete = false;
+                    edited = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    self.confirm_delete = false;
+                }
+            } else if ui.button("Delete them all").clicked() {
+                self.confirm_delete = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Export as");
+            for format in [Format::Markdown, Format::Html] {
+                if ui.button(format!("{format:?}")).clicked() {
+                    message |= GuiMessage::ExportBlocks(blocks.clone(), format);
+                }
+            }
+        });
+        ui.separator();
+
+        draw_tag_totals(&History::tag_totals(blocks, tags), ui);
+        ui.separator();
+        egui::Grid::new("search-results")
+            .num_columns(3)
+            .striped(true)
+            .show(ui, |ui| {
+                for block in blocks {
+                    ui.label(format!(
+                        "{} {} -> {}",
+                        block.start.format(&settings.date_format),
+                        block.start.format(&settings.time_format),
+                        block.end.format(&settings.time_format)
+                    ));
+                    ui.label(fmt_duration(block.duration()));
+                    ui.label(
+                        block
+                            .tag
+                            .as_ref()
+                            .map(|t| tag_text(t, tags))
+                            .unwrap_or_default(),
+                    );
+                    ui.end_row();
                 }
-                if response.lost_focus() {
-                    message |= GuiMessage::RenameTag(self.edit_tag.take().expect("Already checked if this option is Some"));
+            });
+        if edited {
+            self.results = None;
+        }
+        Ok(message)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw_filter(&mut self, tags: &[Tag], ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            ui.label("Tags");
+            self.tags.retain(|tag| {
+                !ui.button(tag_text(tag, tags))
+                    .on_hover_text("Remove")
+                    .clicked()
+            });
+            let mut added = None;
+            egui::ComboBox::from_id_salt("search-tags")
+                .selected_text("Add")
+                .show_ui(ui, |ui| {
+                    for tag in tags.iter().filter(|t| !self.tags.contains(t)) {
+                        ui.selectable_value(&mut added, Some(tag.clone()), tag_text(tag, tags));
+                    }
+                });
+            self.tags.extend(added);
+        });
+
+        ui.horizontal(|ui| {
+            let today = Local::now().date_naive();
+            for (label, day) in [("From", &mut self.from), ("to", &mut self.to)] {
+                let mut limited = day.is_some();
+                ui.checkbox(&mut limited, label);
+                match (limited, &day) {
+                    (true, None) => *day = Some(today),
+                    (false, Some(_)) => *day = None,
+                    _ => (),
+                }
+                if let Some(day) = day {
+                    ui.add(DatePickerButton::new(day).id_salt(label));
                 }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("At least");
+            ui.add(
+                DragValue::new(&mut self.min_minutes)
+                    .range(0..=24 * 60)
+                    .suffix("m"),
+            );
+            ui.label("Text");
+            ui.text_edit_singleline(&mut self.text)
+                .on_hover_text("Looked for in the path and description of the tag");
+        });
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Default)]
+pub struct TagsGuiData {
+    new_name: String,
+    edit_tag: Option<Tag>,
+    focus_edit: bool,
+}
+
+impl TagsGuiData {
+    pub(crate) fn draw(&mut self, tags: &[Tag], ui: &mut egui::Ui) -> GuiMessage {
+        let mut message = GuiMessage::None;
+        // tags come in tree order, so indenting each by its depth draws the tree
+        for tag in tags {
+            message |= self.draw_row(tag, tags, ui)
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_name).hint_text(format!(
+                    "Name, or a path like Client{TAG_PATH_SEPARATOR}Project"
+                )),
+            );
+            if ui.button("Create").clicked() {
+                message |= GuiMessage::CreateTag(std::mem::take(&mut self.new_name));
+            }
+        });
+
+        message
+    }
+
+    fn draw_row(&mut self, tag: &Tag, tags: &[Tag], ui: &mut egui::Ui) -> GuiMessage {
+        let mut message = GuiMessage::None;
+        ui.horizontal(|ui| {
+            ui.add_space(tag.ancestors(tags).len() as f32 * TAG_INDENT);
+            if Some(&*tag) == self.edit_tag.as_ref() {
+                message |= self.draw_editor(tags, ui);
             } else {
-                ui.label(&tag.name);
+                let mut label = tag_label(tag);
+                if tag.is_archived(tags) {
+                    label = label.weak().italics();
+                }
+                let label = ui.label(label);
+                if let Some(description) = &tag.description {
+                    label.on_hover_text(description);
+                }
                 if ui.button("✏").clicked() {
                     self.edit_tag = Some(tag.clone());
                     self.focus_edit = true;
-                } else if ui.button("X").clicked() {
+                }
+                let archive = if tag.archived { "Unarchive" } else { "Archive" };
+                if ui.button(archive).clicked() {
+                    let mut tag = tag.clone();
+                    tag.archived = !tag.archived;
+                    message |= GuiMessage::UpdateTag(tag);
+                }
+                ui.menu_button("Merge into…", |ui| {
+                    for other in tags.iter().filter(|t| !t.is_within(tag, tags)) {
+                        if ui.button(tag_text(other, tags)).clicked() {
+                            message |= GuiMessage::MergeTag(tag.clone(), other.clone());
+                            ui.close_menu();
+                        }
+                    }
+                });
+                if ui.button("X").clicked() {
                     message |= GuiMessage::DeleteTag(tag.clone());
                 }
             }
@@ -426,10 +1485,394 @@ impl TagsGuiData {
 
         message
     }
+
+    /// Fields for the tag being edited, saved when enter is pressed or save is clicked
+    fn draw_editor(&mut self, tags: &[Tag], ui: &mut egui::Ui) -> GuiMessage {
+        let tag = self
+            .edit_tag
+            .as_mut()
+            .expect("Only called while editing a tag");
+
+        let mut icon = tag.icon.clone().unwrap_or_default();
+        let icon_response = ui.add(
+            egui::TextEdit::singleline(&mut icon)
+                .hint_text("Icon")
+                .desired_width(40.0),
+        );
+        tag.icon = Some(icon).filter(|i| !i.trim().is_empty());
+
+        let name_response = ui.text_edit_singleline(&mut tag.name);
+        if self.focus_edit {
+            name_response.request_focus();
+            self.focus_edit = false;
+        }
+
+        let mut color = tag.color.unwrap_or([0, 100, 0]);
+        if ui.color_edit_button_srgb(&mut color).changed() {
+            tag.color = Some(color);
+        }
+        if tag.color.is_some() && ui.button("No colour").clicked() {
+            tag.color = None;
+        }
+
+        let mut description = tag.description.clone().unwrap_or_default();
+        let description_response =
+            ui.add(egui::TextEdit::singleline(&mut description).hint_text("Description"));
+        tag.description = Some(description).filter(|d| !d.trim().is_empty());
+
+        let mut has_rate = tag.rate.is_some();
+        ui.checkbox(&mut has_rate, "Rate");
+        match (has_rate, &mut tag.rate) {
+            (true, Some(rate)) => {
+                let mut amount = rate.amount as f64 / 100.0;
+                ui.add(
+                    DragValue::new(&mut amount)
+                        .range(0.0..=100_000.0)
+                        .fixed_decimals(2)
+                        .suffix("/h"),
+                );
+                rate.amount = (amount * 100.0).round() as i64;
+                ui.add(
+                    egui::TextEdit::singleline(&mut rate.currency)
+                        .hint_text("Currency")
+                        .desired_width(40.0),
+                );
+            }
+            (true, None) => {
+                // start from the rate that would otherwise be inherited
+                let parent = tag.parent.and_then(|id| tags.iter().find(|t| t.id() == id));
+                tag.rate = Some(parent.and_then(|p| p.billing_rate(tags)).unwrap_or(Rate {
+                    amount: 0,
+                    currency: "USD".to_string(),
+                }));
+            }
+            (false, _) => tag.rate = None,
+        }
+
+        egui::ComboBox::from_id_salt("tag-billable")
+            .selected_text(billable_label(tag.billable))
+            .show_ui(ui, |ui| {
+                for billable in [None, Some(true), Some(false)] {
+                    ui.selectable_value(&mut tag.billable, billable, billable_label(billable));
+                }
+            });
+
+        // a tag can't be moved under itself or one of its own children
+        let parent = tag.parent.and_then(|id| tags.iter().find(|t| t.id() == id));
+        let selected = parent
+            .map(|t| tag_text(t, tags))
+            .unwrap_or("No parent".into());
+        let editing = tag.clone();
+        egui::ComboBox::from_id_salt("tag-parent")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut tag.parent, None, "No parent");
+                for other in tags.iter().filter(|t| !t.is_within(&editing, tags)) {
+                    ui.selectable_value(&mut tag.parent, Some(other.id()), tag_text(other, tags));
+                }
+            });
+
+        let entered = [icon_response, name_response, description_response]
+            .iter()
+            .any(|r| r.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)));
+        if ui.button("Save").clicked() || entered {
+            let tag = self
+                .edit_tag
+                .take()
+                .expect("Only called while editing a tag");
+            GuiMessage::UpdateTag(tag)
+        } else {
+            if ui.button("Cancel").clicked() {
+                self.edit_tag = None;
+            }
+            GuiMessage::None
+        }
+    }
 }
 
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Default)]
+pub struct SettingsGuiData {
+    new_profile: String,
+    /// Reading backups means opening each one, so only do it when the list may have changed
+    #[serde(skip)]
+    #[cfg(not(target_arch = "wasm32"))]
+    backups: Option<Vec<BackupInfo>>,
+}
 
-fn draw_settings(settings: &mut Settings, ui: &mut egui::Ui) -> GuiMessage {
+impl SettingsGuiData {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw(
+        &mut self,
+        database: &Database,
+        settings: &mut Settings,
+        tags: &[Tag],
+        profile: &Profile,
+        ui: &mut egui::Ui,
+    ) -> GuiMessage {
+        let mut message = self.draw_profiles(profile, ui);
+        ui.separator();
+        draw_settings(settings, tags, ui);
+        ui.separator();
+        draw_api(settings, ui);
+        ui.separator();
+        draw_hooks(database, settings, ui);
+        ui.separator();
+        draw_repositories(settings, tags, ui);
+        ui.separator();
+        draw_report_settings(settings, ui);
+        ui.separator();
+        message |= self.draw_backups(database, settings, ui);
+        message
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn draw_backups(
+        &mut self,
+        database: &Database,
+        settings: &mut Settings,
+        ui: &mut egui::Ui,
+    ) -> GuiMessage {
+        let mut message = GuiMessage::None;
+
+        ui.heading("Backups");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut settings.backups.daily, "Back up daily");
+            ui.add(
+                DragValue::new(&mut settings.backups.keep)
+                    .range(1..=365)
+                    .speed(0.2)
+                    .prefix("Keep ")
+                    .suffix(" backups"),
+            );
+            if ui.button("Back up now").clicked() {
+                message = GuiMessage::CreateBackup;
+                self.backups = None;
+            }
+        });
+
+        let backups = self.backups.get_or_insert_with(|| {
+            database.backups().list().unwrap_or_else(|e| {
+                tracing::warn!("Failed to list backups: {e:#}");
+                Vec::new()
+            })
+        });
+        if backups.is_empty() {
+            ui.label("No backups yet");
+            return message;
+        }
+
+        let mut restore = None;
+        egui::Grid::new("settings-grid-backups")
+            .num_columns(5)
+            .striped(true)
+            .show(ui, |ui| {
+                for backup in backups.iter() {
+                    ui.label(
+                        backup
+                            .created
+                            .format(&format!(
+                                "{} {}",
+                                settings.date_format, settings.time_format
+                            ))
+                            .to_string(),
+                    );
+                    ui.label(&backup.reason);
+                    ui.label(format!("{} blocks", backup.blocks));
+                    if let (Some(first), Some(last)) = (backup.first, backup.last) {
+                        ui.label(format!(
+                            "{} -> {}",
+                            first.format(&settings.date_format),
+                            last.format(&settings.date_format)
+                        ));
+                    } else {
+                        ui.label("");
+                    }
+                    if ui
+                        .button("Restore")
+                        .on_hover_text("The current database is backed up before restoring")
+                        .clicked()
+                    {
+                        restore = Some(backup.path.clone());
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if let Some(path) = restore {
+            message |= GuiMessage::RestoreBackup(path);
+            self.backups = None;
+        }
+        message
+    }
+
+    fn draw_profiles(&mut self, current: &Profile, ui: &mut egui::Ui) -> GuiMessage {
+        let mut message = GuiMessage::None;
+
+        ui.heading("Profiles");
+        if let Profile::Custom(path) = current {
+            ui.label(format!("Using database at {}", path.display()));
+        }
+        for name in profiles::list() {
+            let selected = matches!(current, Profile::Named(n) if *n == name);
+            if ui.selectable_label(selected, &name).clicked() && !selected {
+                message |= GuiMessage::SwitchProfile(name);
+            }
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_profile);
+            let valid = profiles::validate_name(&self.new_profile).is_ok();
+            if ui.add_enabled(valid, egui::Button::new("Create")).clicked() {
+                message |= GuiMessage::SwitchProfile(std::mem::take(&mut self.new_profile));
+            }
+        });
+
+        message
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_api(settings: &mut Settings, ui: &mut egui::Ui) {
+    let api = &mut settings.api;
+    ui.heading("API");
+    ui.label("An HTTP API on localhost for editor plugins and scripts. Changes apply on restart.");
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut api.enabled, "Serve the API");
+        ui.add(
+            DragValue::new(&mut api.port)
+                .range(1024..=65535)
+                .prefix("Port "),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Token:");
+        ui.add(egui::TextEdit::singleline(&mut api.token).password(true));
+        if ui.button("New token").clicked() {
+            api.token = api::new_token();
+        }
+        if ui.button("Copy").clicked() {
+            ui.ctx().copy_text(api.token.clone());
+        }
+    });
+    if api.enabled && api.token.is_empty() {
+        ui.colored_label(Color32::YELLOW, "The API won't start without a token");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_hooks(database: &Database, settings: &mut Settings, ui: &mut egui::Ui) {
+    let hooks = &mut settings.hooks;
+    ui.heading("Hooks");
+    ui.label(
+        "Shell commands run when a block starts or stops. They can read TIMEKEEPER_TAG, \
+        TIMEKEEPER_START, TIMEKEEPER_END, TIMEKEEPER_DURATION and TIMEKEEPER_BLOCK.",
+    );
+    let mut changed = false;
+    egui::Grid::new("settings-grid-hooks")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("When a block starts:");
+            changed |= ui.text_edit_singleline(&mut hooks.on_start).changed();
+            ui.end_row();
+            ui.label("When a block stops:");
+            changed |= ui.text_edit_singleline(&mut hooks.on_stop).changed();
+            ui.end_row();
+            ui.label("Timeout:");
+            changed |= ui
+                .add(
+                    DragValue::new(&mut hooks.timeout_seconds)
+                        .range(1..=300)
+                        .suffix(" seconds"),
+                )
+                .changed();
+            ui.end_row();
+        });
+    // hooks are read from the database when blocks change, so don't wait for the next save
+    if changed {
+        if let Err(e) = database.settings().set_hooks(hooks) {
+            tracing::warn!("Failed to save hooks: {e:#}");
+        }
+    }
+    if let Some(failure) = hooks::last_failure() {
+        ui.colored_label(Color32::RED, failure);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_report_settings(settings: &mut Settings, ui: &mut egui::Ui) {
+    let report = &mut settings.report;
+    ui.heading("Reports");
+    let placeholders: Vec<String> = (REPORT_PLACEHOLDERS.iter())
+        .map(|p| format!("{{{{{p}}}}}"))
+        .collect();
+    ui.label(format!(
+        "Your own templates can use {}. Leave a template empty to use the built in one.",
+        placeholders.join(", ")
+    ));
+    egui::Grid::new("settings-grid-report")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Markdown template:");
+            draw_path(&mut report.markdown_template, ui);
+            ui.end_row();
+            ui.label("HTML template:");
+            draw_path(&mut report.html_template, ui);
+            ui.end_row();
+            ui.label("Notes:");
+            ui.text_edit_multiline(&mut report.notes);
+            ui.end_row();
+        });
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_path(path: &mut PathBuf, ui: &mut egui::Ui) {
+    let mut text = path.to_string_lossy().into_owned();
+    if ui.text_edit_singleline(&mut text).changed() {
+        *path = PathBuf::from(text);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn draw_repositories(settings: &mut Settings, tags: &[Tag], ui: &mut egui::Ui) {
+    ui.heading("Git Repositories");
+    ui.label(
+        "`timekeeper start --here` uses the tag of the repository it is run in, and block \
+        history lists the commits made in these repositories while the block ran.",
+    );
+    let mut remove = None;
+    egui::Grid::new("settings-grid-repositories")
+        .num_columns(4)
+        .show(ui, |ui| {
+            ui.label("Checked out at");
+            ui.label("Remote url (optional)");
+            ui.label("Tag");
+            ui.end_row();
+            for (i, repository) in settings.repositories.iter_mut().enumerate() {
+                draw_path(&mut repository.path, ui);
+                ui.text_edit_singleline(&mut repository.remote);
+                let selected = repository.tag.as_ref().map(|t| tag_text(t, tags));
+                egui::ComboBox::from_id_salt(("repository-tag", i))
+                    .selected_text(selected.unwrap_or_default())
+                    .show_ui(ui, |ui| {
+                        for tag in pickable(tags) {
+                            let text = tag_text(tag, tags);
+                            ui.selectable_value(&mut repository.tag, Some(tag.clone()), text);
+                        }
+                    });
+                if ui.button("X").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+    if let Some(i) = remove {
+        settings.repositories.remove(i);
+    }
+    if ui.button("Add repository").clicked() {
+        settings.repositories.push(Default::default());
+    }
+}
+
+pub(crate) fn draw_settings(settings: &mut Settings, tags: &[Tag], ui: &mut egui::Ui) {
     let now = Local::now();
     ui.heading("Date And Time");
     egui::Grid::new("settings-grid-formats")
@@ -518,5 +1961,121 @@ fn draw_settings(settings: &mut Settings, ui: &mut egui::Ui) -> GuiMessage {
             })
         });
 
-    GuiMessage::None
+    ui.separator();
+
+    ui.heading("Pomodoro");
+    egui::Grid::new("settings-grid-pomodoro")
+        .num_columns(2)
+        .show(ui, |ui| {
+            let pomodoro = &mut settings.pomodoro;
+
+            ui.label("Work:");
+            draw_minutes(&mut pomodoro.work, ui);
+            ui.end_row();
+
+            ui.label("Short break:");
+            draw_minutes(&mut pomodoro.short_break, ui);
+            ui.end_row();
+
+            ui.label("Long break:");
+            draw_minutes(&mut pomodoro.long_break, ui);
+            ui.end_row();
+
+            ui.label("Long break every:");
+            ui.add(
+                DragValue::new(&mut pomodoro.cycles_before_long_break)
+                    .range(1..=12)
+                    .speed(0.1)
+                    .suffix(" pomodoros"),
+            );
+            ui.end_row();
+
+            ui.label("Break tag:");
+            let selected = pomodoro.break_tag.as_ref().map(|t| tag_text(t, tags));
+            let selected = selected.unwrap_or_default();
+            egui::ComboBox::from_id_salt("settings-pomodoro-break-tag")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut pomodoro.break_tag, None, "No tag");
+                    for tag in pickable(tags) {
+                        ui.selectable_value(
+                            &mut pomodoro.break_tag,
+                            Some(tag.clone()),
+                            tag_text(tag, tags),
+                        );
+                    }
+                });
+            ui.end_row();
+        });
+
+    ui.separator();
+
+    ui.heading("Rounding");
+    ui.label("Used for reports, exports and invoices. Recorded times are never changed.");
+    ui.horizontal(|ui| {
+        let rounding = &mut settings.rounding;
+        ui.label("Round");
+        egui::ComboBox::from_id_salt("settings-rounding-level")
+            .selected_text(rounding_level_label(rounding.level))
+            .show_ui(ui, |ui| {
+                for level in [RoundingLevel::Block, RoundingLevel::Day] {
+                    ui.selectable_value(&mut rounding.level, level, rounding_level_label(level));
+                }
+            });
+        draw_rounding(&mut rounding.rule, "settings-rounding-rule", ui);
+    });
+}
+
+fn rounding_level_label(level: RoundingLevel) -> &'static str {
+    match level {
+        RoundingLevel::Block => "each block",
+        RoundingLevel::Day => "each day's total",
+    }
+}
+
+/// Direction and step of a rounding rule, a step of 0 minutes turns rounding off
+fn draw_rounding(rounding: &mut Rounding, id: &str, ui: &mut egui::Ui) {
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(rounding_label(rounding.direction))
+        .show_ui(ui, |ui| {
+            for direction in [
+                RoundingDirection::Nearest,
+                RoundingDirection::Up,
+                RoundingDirection::Down,
+            ] {
+                ui.selectable_value(
+                    &mut rounding.direction,
+                    direction,
+                    rounding_label(direction),
+                );
+            }
+        });
+    ui.add(
+        DragValue::new(&mut rounding.minutes)
+            .range(0..=240)
+            .speed(0.2)
+            .suffix(" minutes"),
+    );
+    if rounding.minutes == 0 {
+        ui.weak("(off)");
+    }
+}
+
+fn rounding_label(direction: RoundingDirection) -> &'static str {
+    match direction {
+        RoundingDirection::Nearest => "to the nearest",
+        RoundingDirection::Up => "up",
+        RoundingDirection::Down => "down",
+    }
+}
+
+fn draw_minutes(duration: &mut Duration, ui: &mut egui::Ui) {
+    let mut minutes = duration.num_minutes();
+    ui.add(
+        DragValue::new(&mut minutes)
+            .range(1..=240)
+            .speed(0.2)
+            .suffix(" minutes"),
+    );
+    *duration = Duration::minutes(minutes);
 }
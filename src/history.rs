@@ -1,7 +1,7 @@
-use chrono::{DateTime, Datelike, Days, Duration, Local, Timelike};
+use chrono::{DateTime, Datelike, Days, Duration, Local, NaiveDate, TimeZone, Timelike};
 
 use crate::{
-    database::{Block, Database},
+    database::{Block, Rate, Storage, Tag},
     settings::Settings,
 };
 
@@ -16,6 +16,10 @@ pub struct DayBlock {
     pub day: DateTime<Local>,
     pub blocks: Vec<Block>,
     pub total: Duration,
+    /// Total after applying the rounding settings
+    pub rounded: Duration,
+    /// Number of completed pomodoros
+    pub pomodoros: usize,
 }
 impl Default for DayBlock {
     fn default() -> Self {
@@ -23,30 +27,94 @@ impl Default for DayBlock {
             day: Local::now(),
             blocks: Default::default(),
             total: Duration::zero(),
+            rounded: Duration::zero(),
+            pomodoros: 0,
         }
     }
 }
 
-pub struct History<'a> {
-    database: &'a Database,
+/// Planned and actual time spent on a tag, for blocks that had a planned duration
+pub struct PlannedTotal {
+    pub tag: Option<Tag>,
+    pub planned: Duration,
+    pub actual: Duration,
 }
 
-impl<'a> History<'a> {
-    pub fn new(database: &'a Database) -> Self {
-        Self { database }
+/// Time spent on a tag, including the tags nested under it
+pub struct TagTotal {
+    pub tag: Tag,
+    /// How many tags this one is nested under
+    pub depth: usize,
+    pub total: Duration,
+}
+
+/// Billable time on one tag
+pub struct InvoiceLine {
+    /// Path of the tag
+    pub tag: String,
+    pub rate: Rate,
+    /// Time as recorded
+    pub raw: Duration,
+    /// Time after rounding, which is what is billed
+    pub time: Duration,
+    /// In the smallest unit of the rate's currency
+    pub amount: i64,
+}
+
+pub struct InvoiceDay {
+    pub day: NaiveDate,
+    pub lines: Vec<InvoiceLine>,
+}
+
+/// Billable time and amounts over a range of days
+pub struct Invoice {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+    pub by_tag: Vec<InvoiceLine>,
+    pub by_day: Vec<InvoiceDay>,
+    /// Amount due in each currency
+    pub totals: Vec<(String, i64)>,
+}
+
+/// A week of blocks, as it goes on a timesheet
+pub struct Timesheet {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+    /// Only the days that have blocks. When days are rounded, each tag's time on a day is
+    /// rounded on its own.
+    pub days: Vec<DayBlock>,
+    /// Rounded time per tag path. Untagged time is last, under `None`.
+    pub by_tag: Vec<(Option<String>, Duration)>,
+    pub total: Duration,
+    /// Total after applying the rounding settings
+    pub rounded: Duration,
+    /// The weekly goal
+    pub goal: Duration,
+    /// Every tag, for the paths of the blocks' tags
+    pub tags: Vec<Tag>,
+}
+
+impl Timesheet {
+    /// How far the rounded total is over the goal, negative if it falls short
+    pub fn balance(&self) -> Duration {
+        self.rounded - self.goal
     }
+}
 
-    pub fn delete_block(&mut self, block: Block) {
-        if let Err(e) = self.database.blocks().delete(block) {
-            tracing::warn!("{:#}", e);
-        }
+pub struct History<'a> {
+    storage: &'a dyn Storage,
+}
+
+impl<'a> History<'a> {
+    pub fn new(storage: &'a dyn Storage) -> Self {
+        Self { storage }
     }
 
     pub fn blocks_in_day(&self, day: DateTime<Local>) -> (Duration, Vec<Block>) {
         let before = day - Duration::seconds(day.num_seconds_from_midnight() as i64);
         let after = before + Duration::days(1);
 
-        match self.database.blocks().in_range(before, after) {
+        match self.storage.blocks_in_range(before, after) {
             Err(e) => {
                 tracing::warn!("{:#}", e);
                 (Duration::zero(), Vec::new())
@@ -60,11 +128,17 @@ impl<'a> History<'a> {
         }
     }
 
+    /// Blocks starting on any day from `first` to `last`
+    pub fn blocks_in_days(&self, first: NaiveDate, last: NaiveDate) -> anyhow::Result<Vec<Block>> {
+        self.storage
+            .blocks_in_range(start_of_day(first), start_of_day(last + Days::new(1)))
+    }
+
     pub fn total_time(&self, day: DateTime<Local>) -> Duration {
         let before = day - Duration::seconds(day.num_seconds_from_midnight() as i64);
         let after = before + Duration::days(1);
 
-        match self.database.blocks().in_range(before, after) {
+        match self.storage.blocks_in_range(before, after) {
             Err(e) => {
                 tracing::warn!("{:#}", e);
                 Duration::zero()
@@ -75,6 +149,157 @@ impl<'a> History<'a> {
         }
     }
 
+    pub fn count_pomodoros(blocks: &[Block]) -> usize {
+        blocks.iter().filter(|b| b.pomodoro).count()
+    }
+
+    /// Compares planned against actual time per tag. Blocks without a plan are ignored.
+    pub fn planned_vs_actual<'b>(blocks: impl IntoIterator<Item = &'b Block>) -> Vec<PlannedTotal> {
+        let mut totals: Vec<PlannedTotal> = Vec::new();
+        for block in blocks {
+            let Some(planned) = block.planned else {
+                continue;
+            };
+            match totals.iter_mut().find(|t| t.tag == block.tag) {
+                Some(total) => {
+                    total.planned += planned;
+                    total.actual += block.duration();
+                }
+                None => totals.push(PlannedTotal {
+                    tag: block.tag.clone(),
+                    planned,
+                    actual: block.duration(),
+                }),
+            }
+        }
+        totals
+    }
+
+    /// Totals per tag, with time on nested tags rolled up into their parents.
+    /// `tags` should hold every tag, the totals keep its order and skip unused tags.
+    pub fn tag_totals<'b>(
+        blocks: impl IntoIterator<Item = &'b Block>,
+        tags: &[Tag],
+    ) -> Vec<TagTotal> {
+        let mut totals: Vec<TagTotal> = tags
+            .iter()
+            .map(|tag| TagTotal {
+                tag: tag.clone(),
+                depth: tag.ancestors(tags).len(),
+                total: Duration::zero(),
+            })
+            .collect();
+
+        for block in blocks {
+            let Some(tag) = &block.tag else {
+                continue;
+            };
+            for total in &mut totals {
+                if tag.is_within(&total.tag, tags) {
+                    total.total += block.duration();
+                }
+            }
+        }
+
+        totals.retain(|t| !t.total.is_zero());
+        totals
+    }
+
+    /// Billable time and amounts per tag and per day, from the start of `first` to the end of
+    /// `last`. Time is rounded by the rounding settings, per block or per day and tag.
+    pub fn invoice(
+        &self,
+        first: NaiveDate,
+        last: NaiveDate,
+        settings: &Settings,
+    ) -> anyhow::Result<Invoice> {
+        let tags = self.storage.tags()?;
+        let blocks = self.blocks_in_days(first, last)?;
+
+        let mut by_day: Vec<InvoiceDay> = Vec::new();
+        for block in &blocks {
+            let Some(tag) = &block.tag else {
+                continue;
+            };
+            let Some(rate) = tag.billing_rate(&tags) else {
+                continue;
+            };
+
+            let day = block.start.date_naive();
+            let day = match by_day.iter_mut().position(|d| d.day == day) {
+                Some(i) => &mut by_day[i],
+                None => {
+                    by_day.push(InvoiceDay {
+                        day,
+                        lines: Vec::new(),
+                    });
+                    by_day.last_mut().expect("Just pushed a day")
+                }
+            };
+            let path = tag.path(&tags);
+            let line = match day.lines.iter_mut().position(|line| line.tag == path) {
+                Some(i) => &mut day.lines[i],
+                None => {
+                    day.lines.push(InvoiceLine {
+                        tag: path,
+                        rate,
+                        raw: Duration::zero(),
+                        time: Duration::zero(),
+                        amount: 0,
+                    });
+                    day.lines.last_mut().expect("Just pushed a line")
+                }
+            };
+            line.raw += block.duration();
+            line.time += settings.rounding.block(block.duration());
+        }
+        by_day.sort_by_key(|d| d.day);
+
+        // each tag's total is made of its rounded daily totals
+        let mut by_tag: Vec<InvoiceLine> = Vec::new();
+        for line in by_day.iter_mut().flat_map(|d| &mut d.lines) {
+            line.time = settings.rounding.day(line.time);
+            match by_tag.iter_mut().find(|total| total.tag == line.tag) {
+                Some(total) => {
+                    total.raw += line.raw;
+                    total.time += line.time;
+                }
+                None => by_tag.push(InvoiceLine {
+                    tag: line.tag.clone(),
+                    rate: line.rate.clone(),
+                    raw: line.raw,
+                    time: line.time,
+                    amount: 0,
+                }),
+            }
+        }
+        by_tag.sort_by_cached_key(|line| tag_order(&line.tag, &tags));
+
+        // amounts are worked out on the total time, so they don't collect rounding errors
+        let lines = by_tag
+            .iter_mut()
+            .chain(by_day.iter_mut().flat_map(|d| &mut d.lines));
+        for line in lines {
+            line.amount = line.rate.cost(line.time);
+        }
+
+        let mut totals: Vec<(String, i64)> = Vec::new();
+        for line in &by_tag {
+            match totals.iter_mut().find(|(c, _)| *c == line.rate.currency) {
+                Some((_, total)) => *total += line.amount,
+                None => totals.push((line.rate.currency.clone(), line.amount)),
+            }
+        }
+
+        Ok(Invoice {
+            first,
+            last,
+            by_tag,
+            by_day,
+            totals,
+        })
+    }
+
     pub(crate) fn start_of_week(date: DateTime<Local>, settings: &Settings) -> DateTime<Local> {
         let offset = match settings.start_of_week {
             chrono::Weekday::Mon => date.weekday().num_days_from_monday(),
@@ -99,6 +324,8 @@ impl<'a> History<'a> {
         for dayblock in &mut days {
             let (total, blocks) = self.blocks_in_day(day);
 
+            dayblock.pomodoros = History::count_pomodoros(&blocks);
+            dayblock.rounded = settings.rounding.day_total(&blocks);
             dayblock.blocks = blocks;
             grand_total += total;
             dayblock.total = total;
@@ -109,6 +336,54 @@ impl<'a> History<'a> {
         (grand_total, days)
     }
 
+    /// The week containing `day`, with subtotals per tag and the balance against the goal
+    pub fn timesheet(&self, day: NaiveDate, settings: &Settings) -> anyhow::Result<Timesheet> {
+        let tags = self.storage.tags()?;
+        let (total, days) = self.blocks_in_week(start_of_day(day), settings);
+        let first = days[0].day.date_naive();
+        let last = days[6].day.date_naive();
+        let mut days: Vec<DayBlock> = days.into_iter().filter(|d| !d.blocks.is_empty()).collect();
+
+        // each tag's time on a day is rounded on its own, so the lines add up to the totals
+        let mut by_tag: Vec<(Option<String>, Duration)> = Vec::new();
+        for day in &mut days {
+            let mut day_by_tag: Vec<(Option<String>, Vec<&Block>)> = Vec::new();
+            for block in &day.blocks {
+                let path = block.tag.as_ref().map(|t| t.path(&tags));
+                match day_by_tag.iter_mut().find(|(p, _)| *p == path) {
+                    Some((_, blocks)) => blocks.push(block),
+                    None => day_by_tag.push((path, vec![block])),
+                }
+            }
+
+            let mut rounded = Duration::zero();
+            for (path, blocks) in day_by_tag {
+                let time = settings.rounding.day_total(blocks);
+                rounded += time;
+                match by_tag.iter_mut().find(|(p, _)| *p == path) {
+                    Some((_, total)) => *total += time,
+                    None => by_tag.push((path, time)),
+                }
+            }
+            day.rounded = rounded;
+        }
+        by_tag.sort_by_cached_key(|(path, _)| match path {
+            Some(path) => tag_order(path, &tags),
+            None => usize::MAX,
+        });
+
+        Ok(Timesheet {
+            first,
+            last,
+            rounded: days.iter().fold(Duration::zero(), |a, d| a + d.rounded),
+            days,
+            by_tag,
+            total,
+            goal: settings.weekly_goal,
+            tags,
+        })
+    }
+
     pub(crate) fn remaining_daily_goal(&self, settings: &Settings) -> GoalState {
         let goal = settings.daily_goal;
         if goal <= Duration::zero() {
@@ -143,3 +418,113 @@ impl<'a> History<'a> {
         }
     }
 }
+
+/// Position of the tag with this path in the tag tree, so lines follow the tree
+fn tag_order(path: &str, tags: &[Tag]) -> usize {
+    tags.iter()
+        .position(|t| t.path(tags) == path)
+        .unwrap_or(tags.len())
+}
+
+/// Local midnight at the start of `date`
+pub(crate) fn start_of_day(date: NaiveDate) -> DateTime<Local> {
+    let midnight = date.and_time(Default::default());
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .unwrap_or_else(|| Local.from_utc_datetime(&midnight))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+    use crate::settings::{Rounding, RoundingDirection, RoundingLevel};
+
+    fn at(month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        let year = if month == 12 { 2023 } else { 2024 };
+        Local
+            .with_ymd_and_hms(year, month, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    /// The first week of 2024, which started on a Monday, with blocks either side of it
+    fn timesheet(level: RoundingLevel) -> Timesheet {
+        let database = Database::in_memory();
+        let website = database
+            .tags()
+            .create_path("ACME / Website")
+            .unwrap()
+            .remove(1);
+        let internal = database.tags().create_path("Internal").unwrap().remove(0);
+        database.add_block(at(12, 31, 9, 0), at(12, 31, 10, 0), Some(&website));
+        database.add_block(at(1, 1, 9, 0), at(1, 1, 9, 5), Some(&website));
+        database.add_block(at(1, 1, 9, 30), at(1, 1, 9, 35), Some(&website));
+        database.add_block(at(1, 1, 10, 0), at(1, 1, 10, 10), Some(&internal));
+        database.add_block(at(1, 7, 9, 0), at(1, 7, 9, 20), None);
+        database.add_block(at(1, 8, 9, 0), at(1, 8, 10, 0), Some(&website));
+
+        let mut settings = Settings::default();
+        settings.rounding.rule = Rounding {
+            minutes: 15,
+            direction: RoundingDirection::Up,
+        };
+        settings.rounding.level = level;
+        let day = NaiveDate::from_ymd_opt(2024, 1, 3).unwrap();
+        History::new(&database).timesheet(day, &settings).unwrap()
+    }
+
+    fn by_tag(timesheet: &Timesheet) -> Vec<(Option<&str>, i64)> {
+        (timesheet.by_tag.iter())
+            .map(|(path, time)| (path.as_deref(), time.num_minutes()))
+            .collect()
+    }
+
+    fn rounded_days(timesheet: &Timesheet) -> Vec<i64> {
+        timesheet
+            .days
+            .iter()
+            .map(|d| d.rounded.num_minutes())
+            .collect()
+    }
+
+    #[test]
+    fn timesheets_cover_one_week() {
+        let timesheet = timesheet(RoundingLevel::Block);
+        assert_eq!(
+            timesheet.first,
+            NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()
+        );
+        assert_eq!(timesheet.last, NaiveDate::from_ymd_opt(2024, 1, 7).unwrap());
+        let days: Vec<u32> = timesheet.days.iter().map(|d| d.day.day()).collect();
+        assert_eq!(days, [1, 7]);
+        assert_eq!(timesheet.total, Duration::minutes(40));
+    }
+
+    #[test]
+    fn tag_subtotals_are_rounded_like_the_total() {
+        let timesheet = timesheet(RoundingLevel::Block);
+        assert_eq!(
+            by_tag(&timesheet),
+            [
+                (Some("ACME / Website"), 30),
+                (Some("Internal"), 15),
+                (None, 30)
+            ]
+        );
+        assert_eq!(rounded_days(&timesheet), [45, 30]);
+        assert_eq!(timesheet.rounded, Duration::minutes(75));
+
+        let timesheet = self::timesheet(RoundingLevel::Day);
+        assert_eq!(
+            by_tag(&timesheet),
+            [
+                (Some("ACME / Website"), 15),
+                (Some("Internal"), 15),
+                (None, 30)
+            ]
+        );
+        assert_eq!(rounded_days(&timesheet), [30, 30]);
+        assert_eq!(timesheet.rounded, Duration::minutes(60));
+    }
+}
@@ -0,0 +1,160 @@
+//! Runs the user's commands when a block starts or stops, e.g. to set a chat status.
+//! Details of the block are passed in `TIMEKEEPER_*` environment variables.
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use tracing::{info, warn};
+
+use crate::database::{Block, Tag};
+use crate::settings::HookSettings;
+
+/// Hooks that haven't finished, so a command line invocation can wait for them before exiting
+static PENDING: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+/// The last hook that failed and why, shown in the settings
+static LAST_FAILURE: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    Start,
+    Stop,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Event::Start => "start",
+            Event::Stop => "stop",
+        }
+    }
+}
+
+/// Runs the hook for `event` in the background, if one is set
+pub fn fire(settings: &HookSettings, event: Event, block: &Block, tags: &[Tag]) {
+    let command = match event {
+        Event::Start => &settings.on_start,
+        Event::Stop => &settings.on_stop,
+    };
+    if command.trim().is_empty() {
+        return;
+    }
+
+    let env = [
+        ("TIMEKEEPER_EVENT", event.name().to_string()),
+        ("TIMEKEEPER_BLOCK", block.id().to_string()),
+        (
+            "TIMEKEEPER_TAG",
+            (block.tag.as_ref()).map_or_else(String::new, |t| t.path(tags)),
+        ),
+        ("TIMEKEEPER_START", block.start.to_rfc3339()),
+        ("TIMEKEEPER_END", block.end.to_rfc3339()),
+        (
+            "TIMEKEEPER_DURATION",
+            block.duration().num_seconds().to_string(),
+        ),
+    ];
+    let command = command.clone();
+    let timeout = Duration::from_secs(settings.timeout_seconds);
+    let handle = std::thread::spawn(move || {
+        info!("Running {} hook", event.name());
+        if let Err(e) = run(&command, &env, timeout) {
+            let failure = format!("The {} hook failed: {e:#}", event.name());
+            warn!("{failure}");
+            *LAST_FAILURE.lock().unwrap_or_else(|e| e.into_inner()) = Some(failure);
+        }
+    });
+
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|h| !h.is_finished());
+    pending.push(handle);
+}
+
+/// Waits for hooks that are still running, each is stopped after its timeout
+pub fn wait() {
+    let pending = std::mem::take(&mut *PENDING.lock().unwrap_or_else(|e| e.into_inner()));
+    for handle in pending {
+        let _ = handle.join();
+    }
+}
+
+/// The most recent hook failure, if any
+pub fn last_failure() -> Option<String> {
+    LAST_FAILURE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+fn run(command: &str, env: &[(&str, String)], timeout: Duration) -> anyhow::Result<()> {
+    let mut child = shell(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run `{command}`"))?;
+
+    // the pipe is read while waiting, so a chatty command can't fill it and block
+    let mut stderr = child.stderr.take().context("The command has no stderr")?;
+    let reader = std::thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stderr.read_to_end(&mut output);
+        String::from_utf8_lossy(&output).into_owned()
+    });
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("`{command}` took longer than {} seconds", timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+
+    if !status.success() {
+        let stderr = reader.join().unwrap_or_default();
+        bail!("`{command}` exited with {status}\n{}", stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(not(unix))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_the_block_and_stops_slow_commands() {
+        let env = [("TIMEKEEPER_TAG", "ACME / Web".to_string())];
+        let timeout = Duration::from_secs(1);
+        assert!(run(r#"test "$TIMEKEEPER_TAG" = "ACME / Web""#, &env, timeout).is_ok());
+
+        let failed = run("echo oops >&2; exit 3", &env, timeout).unwrap_err();
+        assert!(failed.to_string().contains("oops"));
+        let chatty = "head -c 200000 /dev/zero >&2";
+        assert!(run(chatty, &env, timeout).is_ok());
+        let slow = run("sleep 5", &env, timeout).unwrap_err();
+        assert!(slow.to_string().contains("longer than 1 seconds"));
+    }
+}
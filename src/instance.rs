@@ -0,0 +1,245 @@
+//! Keeps to one window per database. The window listens on a Unix socket next to the
+//! database, and later invocations forward their command to it instead of opening another.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, Sender};
+
+use eframe::egui;
+
+use crate::profiles::Profile;
+use crate::Commands;
+
+/// How long a forwarded command waits for the window to handle it
+const REPLY_TIMEOUT_SECONDS: u64 = 5;
+
+/// A command sent to the running window
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub enum Request {
+    /// Bring the window to the front
+    Show,
+    Start {
+        tag: Option<String>,
+        /// Directory to take the tag from, for `start --here`
+        here: Option<PathBuf>,
+    },
+    Stop,
+    Switch {
+        tag: String,
+    },
+    Status,
+}
+
+/// What the window did, as text to print, or an error message
+type Reply = Result<String, String>;
+
+impl Request {
+    /// The request to forward for a command, `None` if it shouldn't be forwarded
+    fn from_command(command: Option<&Commands>) -> Option<Self> {
+        match command {
+            None => Some(Request::Show),
+            Some(Commands::Start { tag, here }) => Some(Request::Start {
+                tag: tag.clone(),
+                // the window runs elsewhere, so it needs to be told where this is
+                here: here.then(|| std::env::current_dir().unwrap_or_default()),
+            }),
+            Some(Commands::Stop) => Some(Request::Stop),
+            Some(Commands::Switch { tag }) => Some(Request::Switch { tag: tag.clone() }),
+            Some(Commands::Status) => Some(Request::Status),
+            Some(_) => None,
+        }
+    }
+}
+
+/// A request received by the window, which must be answered with [`Self::reply`]
+pub struct Forwarded {
+    pub request: Request,
+    reply: Sender<Reply>,
+}
+
+impl Forwarded {
+    pub fn reply(self, result: anyhow::Result<String>) {
+        // the sender may have given up waiting
+        let _ = self.reply.send(result.map_err(|e| format!("{e:#}")));
+    }
+}
+
+/// Socket for the profile's database, kept next to it
+fn socket_path(profile: &Profile) -> anyhow::Result<PathBuf> {
+    Ok(profile.database_path()?.with_extension("sock"))
+}
+
+/// Sends the command to a window that already has the profile open, and prints its reply.
+/// Returns `None` if there is no such window, or the command isn't one it handles.
+pub fn forward(profile: &Profile, command: Option<&Commands>) -> Option<anyhow::Result<()>> {
+    let request = Request::from_command(command)?;
+    let path = socket_path(profile).ok()?;
+    platform::send(&path, &request)
+}
+
+/// Receives commands from later invocations while the window is open
+pub struct Listener {
+    path: PathBuf,
+    requests: Receiver<Forwarded>,
+}
+
+impl Listener {
+    /// Starts listening for the profile, waking `ctx` when a command arrives
+    pub fn bind(profile: &Profile, ctx: egui::Context) -> anyhow::Result<Self> {
+        let path = socket_path(profile)?;
+        let requests = platform::listen(&path, ctx)?;
+        Ok(Self { path, requests })
+    }
+
+    /// The next command waiting to be handled, if any
+    pub fn try_recv(&self) -> Option<Forwarded> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::time::Duration;
+
+    use anyhow::{anyhow, bail, Context};
+    use eframe::egui;
+    use tracing::{info, warn};
+
+    use super::{Forwarded, Reply, Request, REPLY_TIMEOUT_SECONDS};
+
+    pub fn send(path: &Path, request: &Request) -> Option<anyhow::Result<()>> {
+        // nothing is listening if the window isn't open
+        let stream = UnixStream::connect(path).ok()?;
+        let reply = exchange(stream, request).and_then(|reply| reply.map_err(|e| anyhow!(e)));
+        Some(reply.map(|output| {
+            if !output.is_empty() {
+                println!("{output}");
+            }
+        }))
+    }
+
+    fn exchange(mut stream: UnixStream, request: &Request) -> anyhow::Result<Reply> {
+        let timeout = Some(Duration::from_secs(REPLY_TIMEOUT_SECONDS + 1));
+        stream.set_read_timeout(timeout)?;
+        let mut line = serde_json::to_string(request)?;
+        line.push('\n');
+        stream
+            .write_all(line.as_bytes())
+            .context("Sending command to the open window")?;
+
+        let mut reply = String::new();
+        BufReader::new(stream)
+            .read_line(&mut reply)
+            .context("Waiting for the open window")?;
+        serde_json::from_str(&reply).context("Reading reply from the open window")
+    }
+
+    pub fn listen(path: &Path, ctx: egui::Context) -> anyhow::Result<Receiver<Forwarded>> {
+        if UnixStream::connect(path).is_ok() {
+            bail!("Another window is already using {}", path.display());
+        }
+        // left behind by a window that didn't close cleanly
+        let _ = std::fs::remove_file(path);
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("Listening on {}", path.display()))?;
+        info!("Listening for commands on {}", path.display());
+
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .context("Accepting connection")
+                    .and_then(|stream| handle(stream, &sender, &ctx));
+                if let Err(e) = result {
+                    warn!("Failed to handle forwarded command: {e:#}");
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn handle(
+        stream: UnixStream,
+        requests: &Sender<Forwarded>,
+        ctx: &egui::Context,
+    ) -> anyhow::Result<()> {
+        let mut line = String::new();
+        BufReader::new(&stream).read_line(&mut line)?;
+        let request: Request = serde_json::from_str(&line).context("Reading command")?;
+        info!("Received {:?}", request);
+
+        let (reply, replies) = mpsc::channel();
+        requests.send(Forwarded { request, reply })?;
+        // the window only checks for commands when it redraws
+        ctx.request_repaint();
+        let reply: Reply = replies
+            .recv_timeout(Duration::from_secs(REPLY_TIMEOUT_SECONDS))
+            .unwrap_or_else(|_| Err("The window didn't respond".to_string()));
+
+        let mut reply = serde_json::to_string(&reply)?;
+        reply.push('\n');
+        (&stream).write_all(reply.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Other platforms don't have Unix sockets, so every invocation runs on its own
+#[cfg(not(unix))]
+mod platform {
+    use std::path::Path;
+    use std::sync::mpsc::Receiver;
+
+    use eframe::egui;
+
+    use super::{Forwarded, Request};
+
+    pub fn send(_path: &Path, _request: &Request) -> Option<anyhow::Result<()>> {
+        None
+    }
+
+    pub fn listen(_path: &Path, _ctx: egui::Context) -> anyhow::Result<Receiver<Forwarded>> {
+        anyhow::bail!("Forwarding commands to the open window needs Unix sockets")
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_commands_to_the_listener() {
+        let dir = std::env::temp_dir().join(format!("timekeeper-instance-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let profile = Profile::Custom(dir.join("database.sqlite"));
+        let listener = Listener::bind(&profile, egui::Context::default()).unwrap();
+        assert!(Listener::bind(&profile, egui::Context::default()).is_err());
+
+        let sender = {
+            let profile = profile.clone();
+            std::thread::spawn(move || forward(&profile, Some(&Commands::Stop)))
+        };
+        let forwarded = loop {
+            if let Some(forwarded) = listener.try_recv() {
+                break forwarded;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert!(matches!(forwarded.request, Request::Stop));
+        forwarded.reply(Err(anyhow::anyhow!("Nothing is running")));
+        let reply = sender.join().unwrap().expect("the listener is running");
+        assert_eq!(reply.unwrap_err().to_string(), "Nothing is running");
+
+        drop(listener);
+        assert!(forward(&profile, Some(&Commands::Stop)).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,15 +1,45 @@
 #![forbid(unsafe_code)]
 #![cfg_attr(not(debug_assertions), deny(warnings))] // Forbid warnings in release builds
 #![warn(clippy::all, rust_2018_idioms)]
+// the web app only uses part of the gui
+#![cfg_attr(target_arch = "wasm32", allow(dead_code))]
 
+#[cfg(not(target_arch = "wasm32"))]
+mod api;
+#[cfg(not(target_arch = "wasm32"))]
 mod app;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
 mod database;
+mod export;
+mod format;
+#[cfg(not(target_arch = "wasm32"))]
+mod git;
 mod gui;
 mod history;
+#[cfg(not(target_arch = "wasm32"))]
+mod hooks;
+#[cfg(not(target_arch = "wasm32"))]
+mod instance;
+mod pomodoro;
+mod profiles;
+#[cfg(not(target_arch = "wasm32"))]
+mod scheduler;
 mod settings;
+#[cfg(not(target_arch = "wasm32"))]
+mod undo;
+#[cfg(target_arch = "wasm32")]
+mod web;
+#[cfg(not(target_arch = "wasm32"))]
 pub use app::TimeKeeperApp;
+pub use database::{Storage, WebStorage};
 
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
 use clap::{Parser, Subcommand};
+use export::Format;
+use profiles::Profile;
 
 pub const APP_NAME: &str = "TimeKeeper";
 
@@ -19,10 +49,126 @@ pub const APP_NAME: &str = "TimeKeeper";
 pub struct Args {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Path of a database to use instead of a profile's database. Takes precedence over --profile
+    #[arg(long, env = "TIMEKEEPER_DB", global = true)]
+    database: Option<PathBuf>,
+
+    /// Name of the profile to use. Defaults to the profile last selected in the gui
+    #[arg(long, global = true)]
+    profile: Option<String>,
 }
 
 #[derive(Subcommand)]
 pub enum Commands {
-    /// Start the stopwatch immediately
-    Start,
+    /// Start the stopwatch immediately, opening the window unless it is already open
+    Start {
+        /// Tag for the block, by name or by path like `Client / Project`
+        #[arg(long)]
+        tag: Option<String>,
+        /// Use the tag of the git repository in the current directory, as set in the settings
+        #[arg(long, conflicts_with = "tag")]
+        here: bool,
+    },
+    /// Stop the running block
+    Stop,
+    /// Stop the running block and start another with a different tag
+    Switch {
+        /// Tag for the new block, by name or by path like `Client / Project`
+        tag: String,
+    },
+    /// Print the running block and today's total, without opening the window
+    Status,
+    /// List backups of the database, or restore one
+    Restore {
+        /// File name of the backup to restore, as listed when run without one, or a path
+        backup: Option<String>,
+    },
+    /// Print the history of changes to blocks
+    Log {
+        /// Only show changes to the block with this id
+        #[arg(long)]
+        block: Option<usize>,
+        /// Most changes to show when no block is given
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+    },
+    /// Summarise billable time per tag and per day, for an invoice
+    Invoice {
+        /// First day to include, as YYYY-MM-DD
+        from: NaiveDate,
+        /// Last day to include, as YYYY-MM-DD
+        to: NaiveDate,
+        #[arg(long, value_enum, default_value_t = Format::Markdown)]
+        format: Format,
+        /// Write the summary to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Render a weekly timesheet, from the built in template or your own
+    Report {
+        /// Any day in the week, as YYYY-MM-DD. Defaults to this week
+        day: Option<NaiveDate>,
+        #[arg(long, value_enum, default_value_t = Format::Markdown)]
+        format: Format,
+        /// Write the report to this file instead of printing it
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Template to use instead of the one in the settings. `{{days}}`, `{{tags}}` and the
+        /// other placeholders are replaced with parts of the report
+        #[arg(long)]
+        template: Option<PathBuf>,
+        /// Notes to add instead of the ones in the settings
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    /// List blocks along with the commits made while they ran, in the repositories from
+    /// the settings
+    Commits {
+        /// First day to include, as YYYY-MM-DD. Defaults to today
+        from: Option<NaiveDate>,
+        /// Last day to include, as YYYY-MM-DD. Defaults to the first day
+        to: Option<NaiveDate>,
+    },
+    /// Serve the HTTP API on localhost without opening the window
+    Serve {
+        /// Port to listen on, instead of the one in the settings. 0 picks a free port.
+        #[arg(long)]
+        port: Option<u16>,
+        /// Token requests must send, instead of the one in the settings
+        #[arg(long, env = "TIMEKEEPER_TOKEN")]
+        token: Option<String>,
+    },
+}
+
+impl Args {
+    /// Runs commands that don't need a window, or sends them to the window if it is open.
+    /// Returns `None` if the gui should be started instead.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn run_headless(&self) -> Option<anyhow::Result<()>> {
+        let profile = self.profile();
+        if let Some(forwarded) = instance::forward(&profile, self.command.as_ref()) {
+            return Some(forwarded);
+        }
+
+        let command = match &self.command {
+            Some(Commands::Start { .. }) | None => return None,
+            Some(command) => command,
+        };
+        let result = database::Database::new(&profile)
+            .and_then(|mut database| cli::run(command, &mut database));
+        hooks::wait();
+        Some(result)
+    }
+
+    /// The profile chosen on the command line, or the active profile
+    pub(crate) fn profile(&self) -> Profile {
+        if let Some(path) = &self.database {
+            Profile::Custom(path.clone())
+        } else if let Some(name) = &self.profile {
+            Profile::Named(name.clone())
+        } else {
+            Profile::active()
+        }
+    }
 }
@@ -18,6 +18,14 @@ fn main() {
     tracing::info!("Reading Args");
     let args = Args::parse();
 
+    if let Some(result) = args.run_headless() {
+        if let Err(e) = result {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     tracing::info!("Starting up");
     let native_options = eframe::NativeOptions::default();
     let finish = eframe::run_native(
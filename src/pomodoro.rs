@@ -0,0 +1,234 @@
+use chrono::{DateTime, Duration, Local};
+use tracing::info;
+
+use crate::database::{Storage, Tag};
+use crate::settings::{PomodoroSettings, Settings};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Phase {
+    Work,
+    ShortBreak,
+    LongBreak,
+}
+
+impl Phase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Phase::Work => "Work",
+            Phase::ShortBreak => "Short break",
+            Phase::LongBreak => "Long break",
+        }
+    }
+
+    fn length(&self, settings: &PomodoroSettings) -> Duration {
+        match self {
+            Phase::Work => settings.work,
+            Phase::ShortBreak => settings.short_break,
+            Phase::LongBreak => settings.long_break,
+        }
+    }
+}
+
+/// A running pomodoro session, which alternates between work and break blocks
+pub struct Pomodoro {
+    phase: Phase,
+    phase_start: DateTime<Local>,
+    /// When the session was paused, if it is
+    paused: Option<DateTime<Local>>,
+    work_tag: Option<Tag>,
+    completed: u32,
+}
+
+impl Pomodoro {
+    /// Starts a session with a work block tagged `tag`
+    pub fn start(
+        storage: &dyn Storage,
+        tag: Option<Tag>,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<Self> {
+        storage.start(tag.clone(), None)?;
+        info!("Started pomodoro session");
+        Ok(Self {
+            phase: Phase::Work,
+            phase_start: now,
+            paused: None,
+            work_tag: tag,
+            completed: 0,
+        })
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Time left in the current phase, which doesn't run down while paused
+    pub fn remaining(&self, settings: &Settings, now: DateTime<Local>) -> Duration {
+        let now = self.paused.unwrap_or(now);
+        self.phase_start + self.phase.length(&settings.pomodoro) - now
+    }
+
+    /// Stops the running block, keeping the rest of the phase for when the session resumes
+    pub fn pause(&mut self, storage: &dyn Storage, now: DateTime<Local>) -> anyhow::Result<()> {
+        if self.paused.is_some() {
+            return Ok(());
+        }
+        if self.phase == Phase::Work {
+            self.remember_work_tag(storage)?;
+        }
+        storage.stop()?;
+        self.paused = Some(now);
+        info!("Paused pomodoro session");
+        Ok(())
+    }
+
+    /// Starts a block for the rest of the phase
+    pub fn resume(
+        &mut self,
+        storage: &dyn Storage,
+        settings: &Settings,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<()> {
+        let Some(paused) = self.paused else {
+            return Ok(());
+        };
+        let tag = match self.phase {
+            Phase::Work => self.work_tag.clone(),
+            Phase::ShortBreak | Phase::LongBreak => settings.pomodoro.break_tag.clone(),
+        };
+        storage.start(tag, None)?;
+        self.phase_start += now - paused;
+        self.paused = None;
+        info!("Resumed pomodoro session");
+        Ok(())
+    }
+
+    /// The block may have been retagged while it was running
+    fn remember_work_tag(&mut self, storage: &dyn Storage) -> anyhow::Result<()> {
+        if let Some(current) = storage.current()? {
+            self.work_tag = current.tag;
+        }
+        Ok(())
+    }
+
+    /// Moves on to the next phase once the current one has run out.
+    /// Returns the new phase if a transition happened.
+    pub fn tick(
+        &mut self,
+        storage: &dyn Storage,
+        settings: &Settings,
+        now: DateTime<Local>,
+    ) -> anyhow::Result<Option<Phase>> {
+        if self.paused.is_some() || self.remaining(settings, now) > Duration::zero() {
+            return Ok(None);
+        }
+
+        let next = match self.phase {
+            Phase::Work => {
+                self.remember_work_tag(storage)?;
+                storage.finish_pomodoro()?;
+                self.completed += 1;
+
+                let cycles = settings.pomodoro.cycles_before_long_break.max(1);
+                let next = if self.completed % cycles == 0 {
+                    Phase::LongBreak
+                } else {
+                    Phase::ShortBreak
+                };
+                storage.start(settings.pomodoro.break_tag.clone(), None)?;
+                next
+            }
+            Phase::ShortBreak | Phase::LongBreak => {
+                storage.stop()?;
+                storage.start(self.work_tag.clone(), None)?;
+                Phase::Work
+            }
+        };
+
+        info!("Pomodoro moved to {}", next.label());
+        self.phase = next;
+        self.phase_start = now;
+        Ok(Some(next))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::database::{BlockFilter, Database};
+
+    fn settings(database: &Database) -> Settings {
+        Settings {
+            pomodoro: PomodoroSettings {
+                work: Duration::minutes(25),
+                short_break: Duration::minutes(5),
+                long_break: Duration::minutes(15),
+                cycles_before_long_break: 2,
+                break_tag: database.tags().create_path("Break").unwrap().pop(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn current_tag(database: &Database) -> Option<String> {
+        let current = database.blocks().current().unwrap();
+        current.expect("a block is running").tag.map(|t| t.name)
+    }
+
+    #[test]
+    fn long_breaks_come_after_some_cycles() {
+        let database = Database::in_memory();
+        let settings = settings(&database);
+        let tag = database.tags().create_path("ACME").unwrap().pop();
+        let start = Local::now();
+        let at = |minutes| start + Duration::minutes(minutes);
+        let mut pomodoro = Pomodoro::start(&database, tag, start).unwrap();
+        let mut tick = |minutes| pomodoro.tick(&database, &settings, at(minutes)).unwrap();
+
+        assert_eq!(tick(24), None);
+        assert_eq!(tick(25), Some(Phase::ShortBreak));
+        assert_eq!(current_tag(&database).as_deref(), Some("Break"));
+        assert_eq!(tick(29), None);
+        assert_eq!(tick(30), Some(Phase::Work));
+        assert_eq!(current_tag(&database).as_deref(), Some("ACME"));
+        assert_eq!(tick(55), Some(Phase::LongBreak));
+        assert_eq!(tick(69), None);
+        assert_eq!(tick(70), Some(Phase::Work));
+        assert_eq!(tick(95), Some(Phase::ShortBreak));
+
+        let blocks = database.blocks().query(&BlockFilter::default()).unwrap();
+        assert_eq!(blocks.iter().filter(|b| b.pomodoro).count(), 3);
+    }
+
+    #[test]
+    fn pausing_keeps_the_time_left() {
+        let database = Database::in_memory();
+        let settings = settings(&database);
+        let tag = database.tags().create_path("ACME").unwrap().pop();
+        let start = Local::now();
+        let at = |minutes| start + Duration::minutes(minutes);
+        let mut pomodoro = Pomodoro::start(&database, tag, start).unwrap();
+
+        pomodoro.pause(&database, at(10)).unwrap();
+        assert!(pomodoro.is_paused());
+        assert!(database.blocks().current().unwrap().is_none());
+        assert_eq!(pomodoro.remaining(&settings, at(40)), Duration::minutes(15));
+        assert_eq!(pomodoro.tick(&database, &settings, at(40)).unwrap(), None);
+
+        pomodoro.resume(&database, &settings, at(40)).unwrap();
+        assert_eq!(current_tag(&database).as_deref(), Some("ACME"));
+        assert_eq!(pomodoro.remaining(&settings, at(50)), Duration::minutes(5));
+        assert_eq!(pomodoro.tick(&database, &settings, at(54)).unwrap(), None);
+        let next = pomodoro.tick(&database, &settings, at(55)).unwrap();
+        assert_eq!(next, Some(Phase::ShortBreak));
+
+        // breaks carry on with the break tag
+        pomodoro.pause(&database, at(56)).unwrap();
+        pomodoro.resume(&database, &settings, at(60)).unwrap();
+        assert_eq!(current_tag(&database).as_deref(), Some("Break"));
+        assert_eq!(pomodoro.remaining(&settings, at(60)), Duration::minutes(4));
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use tracing::warn;
+
+use crate::APP_NAME;
+
+pub const DEFAULT_PROFILE: &str = "default";
+/// File in the data directory holding the name of the profile last selected in the gui
+const ACTIVE_PROFILE_FILE: &str = "active_profile";
+const PROFILES_DIR: &str = "profiles";
+
+/// A database, and the settings stored in it
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Profile {
+    /// A profile kept in the app's data directory
+    Named(String),
+    /// A database file chosen with `--database` or `TIMEKEEPER_DB`
+    Custom(PathBuf),
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile::Named(DEFAULT_PROFILE.to_string())
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Profile::Named(name) => write!(f, "{}", name),
+            Profile::Custom(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Profile {
+    pub fn database_path(&self) -> anyhow::Result<PathBuf> {
+        match self {
+            Profile::Named(_) => self.database_path_in(&data_dir()?),
+            Profile::Custom(path) => Ok(path.clone()),
+        }
+    }
+
+    /// Where the database is, if `data_dir` is the app's data directory
+    fn database_path_in(&self, data_dir: &Path) -> anyhow::Result<PathBuf> {
+        match self {
+            Profile::Named(name) if name == DEFAULT_PROFILE => Ok(data_dir.join("database.sqlite")),
+            Profile::Named(name) => {
+                validate_name(name)?;
+                Ok(data_dir.join(PROFILES_DIR).join(format!("{name}.sqlite")))
+            }
+            Profile::Custom(path) => Ok(path.clone()),
+        }
+    }
+
+    /// The profile last selected in the gui, or the default profile
+    pub fn active() -> Self {
+        let Ok(dir) = data_dir() else {
+            return Self::default();
+        };
+        match std::fs::read_to_string(dir.join(ACTIVE_PROFILE_FILE)) {
+            Ok(name) if validate_name(name.trim()).is_ok() => Profile::Named(name.trim().into()),
+            _ => Self::default(),
+        }
+    }
+
+    /// Remember this profile so it is used next time no profile is given
+    pub fn set_active(&self) {
+        let Profile::Named(name) = self else {
+            return;
+        };
+        let result = data_dir().and_then(|dir| {
+            std::fs::write(dir.join(ACTIVE_PROFILE_FILE), name).context("Writing active profile")
+        });
+        if let Err(e) = result {
+            warn!("Failed to remember active profile: {e:#}");
+        }
+    }
+}
+
+/// Names of all profiles in the data directory
+pub fn list() -> Vec<String> {
+    let mut profiles = vec![DEFAULT_PROFILE.to_string()];
+
+    let entries = data_dir().and_then(|dir| {
+        std::fs::read_dir(dir.join(PROFILES_DIR)).context("Reading profiles directory")
+    });
+    if let Ok(entries) = entries {
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "sqlite"))
+            .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+            .filter(|name| name != DEFAULT_PROFILE)
+            .collect();
+        names.sort();
+        profiles.extend(names);
+    }
+
+    profiles
+}
+
+/// Profile names become file names, so only allow a safe set of characters
+pub fn validate_name(name: &str) -> anyhow::Result<()> {
+    if name.is_empty() {
+        bail!("Profile name can't be empty");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("Profile names may only contain letters, numbers, `-` and `_`");
+    }
+    Ok(())
+}
+
+/// The app's data directory, created if it doesn't exist yet
+pub fn data_dir() -> anyhow::Result<PathBuf> {
+    let proj_dirs = directories_next::ProjectDirs::from("", "", APP_NAME)
+        .ok_or(anyhow!("Failed to find path to data_dir"))?;
+    let data_dir = proj_dirs.data_dir().to_path_buf();
+
+    std::fs::create_dir_all(data_dir.join(PROFILES_DIR))
+        .with_context(|| format!("Failed to create app path at {}", data_dir.display()))?;
+
+    Ok(data_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_names_are_safe_file_names() {
+        for name in ["work", "client-2", "side_project", "Arbeitä"] {
+            assert!(validate_name(name).is_ok(), "{name}");
+        }
+        for name in ["", " ", "..", ".", "a/b", "a\\b", "../work", "work.sqlite"] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn finds_the_database_of_a_profile() {
+        let dir = Path::new("/data/timekeeper");
+        let path = |profile: Profile| profile.database_path_in(dir).ok();
+        assert_eq!(path(Profile::default()), Some(dir.join("database.sqlite")));
+        assert_eq!(
+            path(Profile::Named("work".into())),
+            Some(dir.join("profiles").join("work.sqlite"))
+        );
+        assert_eq!(path(Profile::Named("../work".into())), None);
+
+        let custom = Profile::Custom("/tmp/other.sqlite".into());
+        assert_eq!(
+            path(custom.clone()),
+            Some(PathBuf::from("/tmp/other.sqlite"))
+        );
+        assert_eq!(custom.database_path().ok(), path(custom));
+    }
+}
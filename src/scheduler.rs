@@ -0,0 +1,241 @@
+//! Wakes the window every second, and fires scheduled rules at their time.
+//! Runs on its own thread with its own connection, so rules fire even while the window
+//! isn't being drawn. What rules change is sent back to the window, so it can be undone.
+
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+
+use chrono::{DateTime, Local};
+use eframe::egui;
+use tracing::{info, warn};
+
+use crate::database::{Database, Rule, RuleAction};
+use crate::profiles::Profile;
+use crate::settings::Settings;
+use crate::undo::Edit;
+
+/// Lets the window tell the scheduler about profile changes, and hear about the changes
+/// rules made. The thread stops once this is dropped.
+pub struct Scheduler {
+    profiles: Sender<Profile>,
+    edits: Receiver<(Profile, Edit)>,
+}
+
+impl Scheduler {
+    pub fn spawn(profile: Profile, ctx: egui::Context) -> Self {
+        let (profiles, receiver) = mpsc::channel();
+        let (sender, edits) = mpsc::channel();
+        thread::spawn(move || run(profile, ctx, receiver, sender));
+        Self { profiles, edits }
+    }
+
+    /// Fires the rules of `profile` from now on
+    pub fn switch_profile(&self, profile: Profile) {
+        let _ = self.profiles.send(profile);
+    }
+
+    /// Changes made by rules since this was last called, with the profile they were made in
+    pub fn edits(&self) -> impl Iterator<Item = (Profile, Edit)> + '_ {
+        self.edits.try_iter()
+    }
+}
+
+/// A block started by a rule, which is stopped once it has run as long as planned
+struct Started {
+    rule: Rule,
+    block: usize,
+}
+
+fn run(
+    mut profile: Profile,
+    ctx: egui::Context,
+    profiles: Receiver<Profile>,
+    edits: Sender<(Profile, Edit)>,
+) {
+    let one_second = std::time::Duration::from_secs(1);
+    let open = |profile: &Profile| {
+        Database::new(profile)
+            .map_err(|e| warn!("Scheduled rules won't fire: {e:#}"))
+            .ok()
+    };
+    let mut database = open(&profile);
+    let mut started = None;
+    // rules that were due while the app was closed don't fire
+    let mut last_checked = Local::now();
+
+    loop {
+        thread::sleep(one_second);
+        match profiles.try_recv() {
+            Ok(switched) => {
+                profile = switched;
+                database = open(&profile);
+                started = None;
+            }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => return,
+        }
+
+        let now = Local::now();
+        if let Some(database) = &database {
+            let mut made = Vec::new();
+            if let Err(e) = fire_due(database, last_checked, now, &mut started, &mut made) {
+                warn!("Failed to check scheduled rules: {e:#}");
+            }
+            for edit in made {
+                if edits.send((profile.clone(), edit)).is_err() {
+                    return;
+                }
+            }
+        }
+        last_checked = now;
+        ctx.request_repaint();
+    }
+}
+
+/// Fires rules that were due after `after`, up to `now`, logging each firing and adding
+/// the changes made to `edits`
+fn fire_due(
+    database: &Database,
+    after: DateTime<Local>,
+    now: DateTime<Local>,
+    started: &mut Option<Started>,
+    edits: &mut Vec<Edit>,
+) -> anyhow::Result<()> {
+    stop_when_planned(database, started, edits)?;
+
+    let tags = database.tags().all()?;
+    for rule in database.rules().all()? {
+        let Some(fired) = rule.fires_between(after, now) else {
+            continue;
+        };
+        let time_format = Settings::load(database).time_format;
+        let description = rule.describe(&tags, &time_format);
+        info!("Firing rule: {description}");
+        let outcome = match fire(database, &rule, started, edits) {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                warn!("Rule failed: {e:#}");
+                format!("Failed: {e:#}")
+            }
+        };
+        database
+            .rules()
+            .record_firing(&rule, fired, &description, &outcome)?;
+    }
+    Ok(())
+}
+
+/// Does what the rule says, returning what happened
+fn fire(
+    database: &Database,
+    rule: &Rule,
+    started: &mut Option<Started>,
+    edits: &mut Vec<Edit>,
+) -> anyhow::Result<String> {
+    let running = database.blocks().current()?;
+    match rule.action {
+        RuleAction::Stop => match stop(database)? {
+            Some(stopped) => {
+                edits.push(stopped);
+                Ok("Stopped".to_string())
+            }
+            None => Ok("Nothing was running".to_string()),
+        },
+        RuleAction::Start => {
+            let mut made: Vec<Edit> = stop(database)?.into_iter().collect();
+            database
+                .stopwatch()
+                .start_planned(rule.tag.clone(), rule.planned)?;
+            let block = database.blocks().current()?;
+            if let Some(block) = &block {
+                *started = Some(Started {
+                    rule: rule.clone(),
+                    block: block.id(),
+                });
+            }
+            made.push(Edit::Block {
+                before: None,
+                after: block,
+            });
+            edits.push(Edit::group(made));
+            Ok(match running {
+                Some(_) => "Stopped the running block and started another".to_string(),
+                None => "Started".to_string(),
+            })
+        }
+    }
+}
+
+/// Stops the running block, returning the change if there was one
+fn stop(database: &Database) -> anyhow::Result<Option<Edit>> {
+    let Some(running) = database.blocks().current()? else {
+        return Ok(None);
+    };
+    database.stopwatch().stop()?;
+    let after = database.blocks().get(running.id())?;
+    Ok(Some(Edit::Block {
+        before: Some(running),
+        after,
+    }))
+}
+
+/// Stops the block a rule started once it has run as long as the rule planned
+fn stop_when_planned(
+    database: &Database,
+    started: &mut Option<Started>,
+    edits: &mut Vec<Edit>,
+) -> anyhow::Result<()> {
+    let Some(Started { rule, block }) = started else {
+        return Ok(());
+    };
+    let current = database.blocks().current()?;
+    // the block was stopped some other way
+    let Some(current) = current.filter(|b| b.id() == *block) else {
+        *started = None;
+        return Ok(());
+    };
+    let Some(planned) = current.planned else {
+        *started = None;
+        return Ok(());
+    };
+
+    // the window may not have moved the block's end on lately
+    if Local::now() - current.start >= planned {
+        edits.extend(stop(database)?);
+        let tags = database.tags().all()?;
+        let description = rule.describe(&tags, &Settings::load(database).time_format);
+        let outcome = format!("Stopped after {}m", planned.num_minutes());
+        database
+            .rules()
+            .record_firing(rule, Local::now(), &description, &outcome)?;
+        *started = None;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::undo::UndoStack;
+
+    #[test]
+    fn changes_made_by_rules_can_be_undone() {
+        let database = Database::in_memory();
+        database.stopwatch().start(None).unwrap();
+        let running = database.blocks().current().unwrap().unwrap();
+        let mut rule = Rule::new();
+        rule.action = RuleAction::Start;
+
+        let (mut started, mut edits) = (None, Vec::new());
+        let outcome = fire(&database, &rule, &mut started, &mut edits).unwrap();
+        assert_eq!(outcome, "Stopped the running block and started another");
+        let current = database.blocks().current().unwrap().unwrap();
+        assert_ne!(current.id(), running.id());
+
+        let mut undo = UndoStack::default();
+        edits.into_iter().for_each(|edit| undo.push(edit));
+        undo.undo(&database).unwrap();
+        let current = database.blocks().current().unwrap().unwrap();
+        assert_eq!(current.id(), running.id());
+    }
+}
@@ -1,6 +1,15 @@
+use std::path::{Path, PathBuf};
+
 use chrono::{Duration, Weekday};
 use serde::{Deserialize, Serialize};
-use tracing::{error, warn};
+#[cfg(not(target_arch = "wasm32"))]
+use tracing::error;
+use tracing::warn;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::database::Database;
+use crate::database::{Block, Tag};
+use crate::export::Format;
 
 #[derive(Serialize, Deserialize)]
 #[serde(remote = "Duration")]
@@ -27,6 +36,184 @@ pub(crate) struct Settings {
     pub daily_goal: Duration,
     #[serde(with = "DurationDef")]
     pub weekly_goal: Duration,
+
+    pub pomodoro: PomodoroSettings,
+
+    /// Stop blocks automatically once they reach their planned duration
+    pub stop_at_planned: bool,
+
+    pub backups: BackupSettings,
+
+    /// Days a deleted block is kept in the trash before it is removed for good
+    pub trash_days: u32,
+
+    /// Applied to reported and exported times, stored times are never rounded
+    pub rounding: RoundingSettings,
+
+    pub api: ApiSettings,
+
+    pub hooks: HookSettings,
+
+    /// Git repositories linked to tags, see `git.rs`
+    pub repositories: Vec<Repository>,
+
+    pub report: ReportSettings,
+}
+
+/// Weekly timesheet reports
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub(crate) struct ReportSettings {
+    /// Added to the end of every report
+    pub notes: String,
+    /// Templates to use instead of the built in ones, empty for the built in one
+    pub markdown_template: PathBuf,
+    pub html_template: PathBuf,
+}
+
+impl ReportSettings {
+    pub fn template(&self, format: Format) -> Option<&Path> {
+        let path = match format {
+            Format::Markdown => &self.markdown_template,
+            Format::Html => &self.html_template,
+        };
+        Some(path.as_path()).filter(|p| !p.as_os_str().is_empty())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Default)]
+#[serde(default)]
+pub(crate) struct Repository {
+    /// Where the repository is checked out, commits are read from here
+    pub path: PathBuf,
+    /// Remote url, so other clones of the repository match too. Empty to only match `path`
+    pub remote: String,
+    pub tag: Option<Tag>,
+}
+
+/// Commands run when blocks start and stop, see `hooks.rs`
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub(crate) struct HookSettings {
+    /// Run by the shell when a block starts, empty for none
+    pub on_start: String,
+    /// Run by the shell when a block stops, empty for none
+    pub on_stop: String,
+    /// Hooks still running after this long are killed
+    pub timeout_seconds: u64,
+}
+
+/// The HTTP API for integrations, see `api.rs`
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(default)]
+pub(crate) struct ApiSettings {
+    /// Serve the API while the window is open
+    pub enabled: bool,
+    pub port: u16,
+    /// Requests must send this as a bearer token
+    pub token: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub(crate) struct RoundingSettings {
+    pub rule: Rounding,
+    pub level: RoundingLevel,
+}
+
+/// What a rounding rule is applied to
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub(crate) enum RoundingLevel {
+    /// Each block is rounded, then the rounded blocks are added up
+    #[default]
+    Block,
+    /// Blocks are added up, then each day's total is rounded
+    Day,
+}
+
+/// Rounds durations to a multiple of some number of minutes
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub(crate) struct Rounding {
+    /// Round to multiples of this many minutes, 0 to leave durations as they are
+    pub minutes: u32,
+    pub direction: RoundingDirection,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub(crate) enum RoundingDirection {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+impl RoundingSettings {
+    /// A block's duration, rounded if blocks are rounded individually
+    pub fn block(&self, duration: Duration) -> Duration {
+        match self.level {
+            RoundingLevel::Block => self.rule.round(duration),
+            RoundingLevel::Day => duration,
+        }
+    }
+
+    /// Rounds one day's total, if totals are rounded. The total should be made of
+    /// durations that were passed through [`Self::block`].
+    pub fn day(&self, total: Duration) -> Duration {
+        match self.level {
+            RoundingLevel::Block => total,
+            RoundingLevel::Day => self.rule.round(total),
+        }
+    }
+
+    /// Rounded total of one day's blocks
+    pub fn day_total<'a>(&self, blocks: impl IntoIterator<Item = &'a Block>) -> Duration {
+        let total = blocks
+            .into_iter()
+            .fold(Duration::zero(), |a, b| a + self.block(b.duration()));
+        self.day(total)
+    }
+}
+
+impl Rounding {
+    pub fn round(&self, duration: Duration) -> Duration {
+        if self.minutes == 0 {
+            return duration;
+        }
+        let step = self.minutes as i64 * 60;
+        let seconds = duration.num_seconds();
+        let steps = match self.direction {
+            RoundingDirection::Nearest => (seconds + step / 2).div_euclid(step),
+            RoundingDirection::Up => (seconds + step - 1).div_euclid(step),
+            RoundingDirection::Down => seconds.div_euclid(step),
+        };
+        Duration::seconds(steps * step)
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub(crate) struct BackupSettings {
+    /// Back up the database once a day
+    pub daily: bool,
+    /// Number of daily and manual backups to keep, older ones are deleted
+    pub keep: usize,
+}
+
+/// Interval lengths used by pomodoro mode
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub(crate) struct PomodoroSettings {
+    #[serde(with = "DurationDef")]
+    pub work: Duration,
+    #[serde(with = "DurationDef")]
+    pub short_break: Duration,
+    #[serde(with = "DurationDef")]
+    pub long_break: Duration,
+    /// Number of work intervals before a long break is taken instead of a short one
+    pub cycles_before_long_break: u32,
+    /// Tag given to break blocks
+    pub break_tag: Option<Tag>,
 }
 
 impl Settings {
@@ -44,13 +231,43 @@ impl Settings {
         }
     }
 
-    pub(crate) fn serialize(&self) -> String {
-        match serde_json::to_string(&self) {
-            Ok(serialized) => serialized,
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Reads settings from the database, falling back to defaults for anything missing
+    pub(crate) fn load(database: &Database) -> Settings {
+        let stored = match database.settings().all() {
+            Ok(stored) => stored,
+            Err(e) => {
+                warn!("Failed to read settings: {:#}", e);
+                return Self::default();
+            }
+        };
+
+        match serde_json::from_value(serde_json::Value::Object(stored)) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Failed to read settings: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    /// Writes every setting to the database together
+    pub(crate) fn save(&self, database: &Database) {
+        let serialized = match serde_json::to_value(self) {
+            Ok(serde_json::Value::Object(serialized)) => serialized,
+            Ok(_) => {
+                error!("Settings did not serialize to an object");
+                return;
+            }
             Err(e) => {
                 error!("Failed to serialize settings. {}", e);
-                "{}".to_string()
+                return;
             }
+        };
+
+        if let Err(e) = database.settings().set_all(&serialized) {
+            error!("Failed to save settings. {:#}", e);
         }
     }
 }
@@ -63,6 +280,136 @@ impl Default for Settings {
             start_of_week: Weekday::Mon,
             daily_goal: Duration::hours(8),
             weekly_goal: Duration::hours(40),
+            pomodoro: PomodoroSettings::default(),
+            stop_at_planned: false,
+            backups: BackupSettings::default(),
+            trash_days: 30,
+            rounding: RoundingSettings::default(),
+            api: ApiSettings::default(),
+            hooks: HookSettings::default(),
+            repositories: Vec::new(),
+            report: ReportSettings::default(),
+        }
+    }
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 7431,
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            on_start: String::new(),
+            on_stop: String::new(),
+            timeout_seconds: 10,
+        }
+    }
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        Self {
+            daily: true,
+            keep: 14,
+        }
+    }
+}
+
+impl Default for RoundingSettings {
+    fn default() -> Self {
+        Self {
+            rule: Rounding {
+                minutes: 0,
+                direction: RoundingDirection::Up,
+            },
+            level: RoundingLevel::Block,
+        }
+    }
+}
+
+impl Default for PomodoroSettings {
+    fn default() -> Self {
+        Self {
+            work: Duration::minutes(25),
+            short_break: Duration::minutes(5),
+            long_break: Duration::minutes(15),
+            cycles_before_long_break: 4,
+            break_tag: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_steps_of_minutes() {
+        let rounding = |minutes, direction| Rounding { minutes, direction };
+        let time = Duration::minutes(22);
+        assert_eq!(rounding(0, RoundingDirection::Up).round(time), time);
+        assert_eq!(
+            rounding(15, RoundingDirection::Up).round(time),
+            Duration::minutes(30)
+        );
+        assert_eq!(
+            rounding(15, RoundingDirection::Down).round(time),
+            Duration::minutes(15)
+        );
+        assert_eq!(
+            rounding(15, RoundingDirection::Nearest).round(time),
+            Duration::minutes(15)
+        );
+        assert_eq!(
+            rounding(15, RoundingDirection::Nearest).round(Duration::minutes(23)),
+            Duration::minutes(30)
+        );
+    }
+
+    #[test]
+    fn rounds_blocks_or_daily_totals() {
+        let mut rounding = RoundingSettings {
+            rule: Rounding {
+                minutes: 6,
+                direction: RoundingDirection::Up,
+            },
+            level: RoundingLevel::Block,
+        };
+        let blocks = [Duration::minutes(1), Duration::minutes(2)];
+        let total = |rounding: &RoundingSettings| {
+            let sum = blocks
+                .iter()
+                .fold(Duration::zero(), |a, b| a + rounding.block(*b));
+            rounding.day(sum)
+        };
+        assert_eq!(total(&rounding), Duration::minutes(12));
+        rounding.level = RoundingLevel::Day;
+        assert_eq!(total(&rounding), Duration::minutes(6));
+    }
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn saves_and_loads_from_the_database() {
+        let database = Database::in_memory();
+        let mut settings = Settings {
+            weekly_goal: Duration::hours(30),
+            ..Default::default()
+        };
+        settings.hooks.on_start = "echo started".to_string();
+        settings.save(&database);
+
+        let loaded = Settings::load(&database);
+        assert_eq!(loaded.weekly_goal, Duration::hours(30));
+        assert_eq!(loaded.date_format, settings.date_format);
+        let hooks = database.settings().hooks().unwrap();
+        assert_eq!(hooks.on_start, "echo started");
+        assert_eq!(hooks.timeout_seconds, 10);
+    }
+}
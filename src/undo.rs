@@ -0,0 +1,241 @@
+use tracing::info;
+
+use crate::database::{Block, Database, Tag};
+
+/// Most edits kept in the undo history
+const MAX_HISTORY: usize = 100;
+
+/// A change to the database, stored as the state before and after so it can be reverted.
+/// `None` means the record didn't exist, or was deleted.
+pub enum Edit {
+    Block {
+        before: Option<Block>,
+        after: Option<Block>,
+    },
+    Tag {
+        before: Option<Tag>,
+        after: Option<Tag>,
+    },
+    /// Several edits made together, undone as one
+    Group(Vec<Edit>),
+}
+
+impl Edit {
+    /// Combines edits into one, unless there is only one
+    pub fn group(mut edits: Vec<Edit>) -> Edit {
+        if edits.len() == 1 {
+            edits.remove(0)
+        } else {
+            Edit::Group(edits)
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Edit::Block { before: None, .. } => "Started block",
+            Edit::Block { after: None, .. } => "Deleted block",
+            Edit::Block {
+                before: Some(before),
+                after: Some(after),
+            } if before.deleted.is_none() && after.deleted.is_some() => "Moved block to trash",
+            Edit::Block {
+                before: Some(before),
+                after: Some(after),
+            } if before.deleted.is_some() && after.deleted.is_none() => "Restored block",
+            Edit::Block {
+                before: Some(before),
+                after: Some(after),
+            } if before.running && !after.running => "Stopped block",
+            Edit::Block { .. } => "Changed block",
+            Edit::Tag { before: None, .. } => "Created tag",
+            Edit::Tag { after: None, .. } => "Deleted tag",
+            Edit::Tag { .. } => "Changed tag",
+            Edit::Group(edits) => edits.last().map_or("Nothing", Edit::description),
+        }
+    }
+
+    fn undo(&self, database: &Database) -> anyhow::Result<()> {
+        match self {
+            Edit::Block { before, after } => set_block(database, before, after),
+            Edit::Tag { before, after } => set_tag(database, before, after),
+            Edit::Group(edits) => edits.iter().rev().try_for_each(|edit| edit.undo(database)),
+        }
+    }
+
+    fn redo(&self, database: &Database) -> anyhow::Result<()> {
+        match self {
+            Edit::Block { before, after } => set_block(database, after, before),
+            Edit::Tag { before, after } => set_tag(database, after, before),
+            Edit::Group(edits) => edits.iter().try_for_each(|edit| edit.redo(database)),
+        }
+    }
+}
+
+/// Puts a block into `state`. `other` is the opposite state, used to find the block to delete.
+fn set_block(
+    database: &Database,
+    state: &Option<Block>,
+    other: &Option<Block>,
+) -> anyhow::Result<()> {
+    match (state, other) {
+        (Some(block), _) => database.blocks().put(block),
+        (None, Some(block)) => database.blocks().purge(block.clone()),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Puts a tag into `state`. `other` is the opposite state, used to find the tag to delete.
+fn set_tag(database: &Database, state: &Option<Tag>, other: &Option<Tag>) -> anyhow::Result<()> {
+    match (state, other) {
+        (Some(tag), _) => database.tags().put(tag),
+        (None, Some(tag)) => database.tags().delete(tag.clone()),
+        (None, None) => Ok(()),
+    }
+}
+
+#[derive(Default)]
+pub struct UndoStack {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl UndoStack {
+    /// Records an edit that has just been made
+    pub fn push(&mut self, edit: Edit) {
+        if matches!(&edit, Edit::Group(edits) if edits.is_empty()) {
+            return;
+        }
+        self.undo.push(edit);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    /// The most recent edit that can be undone
+    pub fn last(&self) -> Option<&Edit> {
+        self.undo.last()
+    }
+
+    pub fn undo(&mut self, database: &Database) -> anyhow::Result<()> {
+        let Some(edit) = self.undo.pop() else {
+            return Ok(());
+        };
+        if let Err(e) = edit.undo(database) {
+            self.undo.push(edit);
+            return Err(e);
+        }
+        info!("Undid: {}", edit.description());
+        self.redo.push(edit);
+        Ok(())
+    }
+
+    pub fn redo(&mut self, database: &Database) -> anyhow::Result<()> {
+        let Some(edit) = self.redo.pop() else {
+            return Ok(());
+        };
+        if let Err(e) = edit.redo(database) {
+            self.redo.push(edit);
+            return Err(e);
+        }
+        info!("Redid: {}", edit.description());
+        self.undo.push(edit);
+        Ok(())
+    }
+
+    /// Forget all edits, e.g. when the database is replaced
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use chrono::{DateTime, Local, TimeZone};
+
+    use super::*;
+
+    /// Whole seconds, as the database stores them
+    fn minutes_ago(minutes: i64) -> DateTime<Local> {
+        let now = Local::now().timestamp();
+        Local.timestamp_opt(now - minutes * 60, 0).unwrap()
+    }
+
+    /// Splits a running block the way the window does, recording it as one edit
+    fn split_running(database: &Database, undo: &mut UndoStack) -> (Block, Block, Block) {
+        let blocks = database.blocks();
+        let mut running = database.add_block(minutes_ago(120), minutes_ago(60), None);
+        running.running = true;
+        blocks.put(&running).unwrap();
+        let running = blocks.get(running.id()).unwrap().unwrap();
+
+        let (first, second) = blocks
+            .split(&running, minutes_ago(90), [None, None])
+            .unwrap();
+        undo.push(Edit::group(vec![
+            Edit::Block {
+                before: Some(running.clone()),
+                after: Some(first.clone()),
+            },
+            Edit::Block {
+                before: None,
+                after: Some(second.clone()),
+            },
+        ]));
+        (running, first, second)
+    }
+
+    #[test]
+    fn groups_are_undone_backwards_and_redone_forwards() {
+        let database = Database::in_memory();
+        let blocks = database.blocks();
+        let mut undo = UndoStack::default();
+        let (running, first, second) = split_running(&database, &mut undo);
+        assert_eq!(undo.last().map(Edit::description), Some("Started block"));
+
+        // only one block can run, so the new block has to go before the old one runs again
+        undo.undo(&database).unwrap();
+        assert_eq!(blocks.get(second.id()).unwrap(), None);
+        assert_eq!(
+            blocks.current().unwrap().map(|b| b.id()),
+            Some(running.id())
+        );
+        assert_eq!(
+            blocks.get(running.id()).unwrap().unwrap().start,
+            running.start
+        );
+
+        undo.redo(&database).unwrap();
+        assert_eq!(blocks.get(first.id()).unwrap(), Some(first));
+        assert_eq!(blocks.current().unwrap().map(|b| b.id()), Some(second.id()));
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let database = Database::in_memory();
+        let blocks = database.blocks();
+        let mut undo = UndoStack::default();
+        let (running, _, second) = split_running(&database, &mut undo);
+        undo.undo(&database).unwrap();
+
+        undo.push(Edit::Group(Vec::new()));
+        undo.redo(&database).unwrap();
+        assert_eq!(
+            blocks.get(second.id()).unwrap().map(|b| b.id()),
+            Some(second.id())
+        );
+        undo.undo(&database).unwrap();
+
+        let mut tagged = blocks.get(running.id()).unwrap().unwrap();
+        tagged.tag = database.tags().create_path("ACME").unwrap().pop();
+        blocks.put(&tagged).unwrap();
+        undo.push(Edit::Block {
+            before: Some(running),
+            after: Some(tagged.clone()),
+        });
+        undo.redo(&database).unwrap();
+        assert_eq!(blocks.get(second.id()).unwrap(), None);
+        assert_eq!(blocks.get(tagged.id()).unwrap(), Some(tagged));
+    }
+}
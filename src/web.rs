@@ -0,0 +1,207 @@
+use chrono::Local;
+use eframe::egui;
+use eframe::wasm_bindgen::{self, prelude::*, JsCast};
+use eframe::web_sys;
+use tracing::warn;
+
+use crate::database::{Storage, WebStorage};
+use crate::gui::{
+    draw_settings, draw_stopwatch, draw_this_week, draw_today, GuiMessage, StopwatchGuiData,
+    TagsGuiData,
+};
+use crate::history::History;
+use crate::pomodoro::Pomodoro;
+use crate::settings::Settings;
+
+const SETTINGS_KEY: &str = "Settings";
+
+/// Starts the app on the canvas with id `canvas_id`. Called from `docs/index.html`.
+#[wasm_bindgen]
+pub fn start(canvas_id: &str) -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+    tracing_wasm::set_as_global_default();
+
+    let canvas = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id(canvas_id))
+        .and_then(|element| element.dyn_into::<web_sys::HtmlCanvasElement>().ok())
+        .ok_or_else(|| JsValue::from_str(&format!("No canvas with id {canvas_id}")))?;
+
+    wasm_bindgen_futures::spawn_local(async move {
+        let started = eframe::WebRunner::new()
+            .start(
+                canvas,
+                eframe::WebOptions::default(),
+                Box::new(|cc| Ok(Box::new(WebApp::new(cc)))),
+            )
+            .await;
+        if let Err(e) = started {
+            tracing::error!("Failed to start: {:?}", e);
+        }
+    });
+    Ok(())
+}
+
+#[derive(PartialEq, Eq, Default)]
+enum Screen {
+    #[default]
+    Today,
+    ThisWeek,
+    Tags,
+    Settings,
+}
+
+/// The app in a browser. Blocks and tags are kept in local storage, and features that
+/// need the database, like the trash, undo and backups, are left out.
+pub struct WebApp {
+    storage: WebStorage,
+    settings: Settings,
+    screen: Screen,
+    stopwatch: StopwatchGuiData,
+    tags: TagsGuiData,
+    pomodoro: Option<Pomodoro>,
+}
+
+impl eframe::App for WebApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if let Err(e) = self.tick() {
+            warn!("{:#}", e);
+        }
+        // there is no background thread on the web, so ask to be woken for the stopwatch
+        ctx.request_repaint_after(std::time::Duration::from_secs(1));
+
+        let current = self.storage.current().unwrap_or_else(|e| {
+            warn!("{:#}", e);
+            None
+        });
+        let tags = self.storage.tags().unwrap_or_else(|e| {
+            warn!("{:#}", e);
+            Vec::new()
+        });
+
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.screen, Screen::Today, "Today");
+                ui.selectable_value(&mut self.screen, Screen::ThisWeek, "This Week");
+                ui.selectable_value(&mut self.screen, Screen::Tags, "Tags");
+                ui.selectable_value(&mut self.screen, Screen::Settings, "Settings");
+            });
+        });
+
+        let message = egui::TopBottomPanel::bottom("stopwatch")
+            .show(ctx, |ui| {
+                draw_stopwatch(
+                    current,
+                    self.pomodoro.as_ref(),
+                    &mut self.stopwatch,
+                    History::new(&self.storage),
+                    &tags,
+                    &mut self.settings,
+                    ui,
+                )
+            })
+            .inner;
+        self.handle_message(message);
+
+        let message = egui::CentralPanel::default()
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .show(ui, |ui| match self.screen {
+                        Screen::Today => draw_today(&self.storage, &self.settings, ui)
+                            .unwrap_or_else(|e| {
+                                warn!("{:#}", e);
+                                GuiMessage::None
+                            }),
+                        Screen::ThisWeek => {
+                            let mut history = History::new(&self.storage);
+                            draw_this_week(&self.settings, &tags, &mut history, ui)
+                        }
+                        Screen::Tags => self.tags.draw(&tags, ui),
+                        Screen::Settings => {
+                            draw_settings(&mut self.settings, &tags, ui);
+                            GuiMessage::None
+                        }
+                    })
+                    .inner
+            })
+            .inner;
+        self.handle_message(message);
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.storage.save(storage);
+        match serde_json::to_string(&self.settings) {
+            Ok(settings) => storage.set_string(SETTINGS_KEY, settings),
+            Err(e) => warn!("Failed to save settings: {}", e),
+        }
+    }
+}
+
+impl WebApp {
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let settings = cc.storage.and_then(|s| s.get_string(SETTINGS_KEY));
+        Self {
+            storage: WebStorage::load(cc.storage),
+            settings: Settings::deserailize(settings),
+            screen: Screen::default(),
+            stopwatch: StopwatchGuiData::default(),
+            tags: TagsGuiData::default(),
+            pomodoro: None,
+        }
+    }
+
+    fn handle_message(&mut self, message: GuiMessage) {
+        let result = match message {
+            GuiMessage::None => Ok(()),
+            GuiMessage::StartStopwatch(tag) => self.storage.start(tag, None),
+            GuiMessage::StartTimer(tag, planned) => self.storage.start(tag, Some(planned)),
+            GuiMessage::StartPomodoro(tag) => {
+                Pomodoro::start(&self.storage, tag, Local::now()).map(|p| self.pomodoro = Some(p))
+            }
+            GuiMessage::PausePomodoro => match &mut self.pomodoro {
+                Some(pomodoro) => pomodoro.pause(&self.storage, Local::now()),
+                None => Ok(()),
+            },
+            GuiMessage::ResumePomodoro => match &mut self.pomodoro {
+                Some(pomodoro) => pomodoro.resume(&self.storage, &self.settings, Local::now()),
+                None => Ok(()),
+            },
+            GuiMessage::StopStopwatch => {
+                self.pomodoro = None;
+                self.storage.stop()
+            }
+            GuiMessage::ChangedBlockTag(block) => self.storage.retag_block(block),
+            GuiMessage::DeletedBlock(block) => self.storage.delete_block(block),
+            GuiMessage::CreateTag(path) => self.storage.create_tags(&path).map(|_| ()),
+            GuiMessage::UpdateTag(tag) => self.storage.update_tag(tag),
+            GuiMessage::DeleteTag(tag) => self.storage.delete_tag(tag),
+            _ => {
+                warn!("Not available in the web app");
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            warn!("{:#}", e);
+        }
+    }
+
+    /// Keeps the running block up to date, moving pomodoros on and stopping finished timers
+    fn tick(&mut self) -> anyhow::Result<()> {
+        self.storage.update()?;
+        let Some(current) = self.storage.current()? else {
+            if !self.pomodoro.as_ref().is_some_and(Pomodoro::is_paused) {
+                self.pomodoro = None;
+            }
+            return Ok(());
+        };
+
+        if let Some(pomodoro) = &mut self.pomodoro {
+            pomodoro.tick(&self.storage, &self.settings, Local::now())?;
+        } else if let Some(planned) = current.planned {
+            if self.settings.stop_at_planned && current.duration() >= planned {
+                self.storage.stop()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! Runs `timekeeper_bin serve` against a fresh database and talks to it over HTTP
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+
+const TOKEN: &str = "test-token";
+
+/// The server process, killed when dropped
+struct Server {
+    child: Child,
+    addr: String,
+    dir: PathBuf,
+}
+
+impl Server {
+    fn start(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("timekeeper-api-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_timekeeper_bin"))
+            .args(["serve", "--port", "0", "--token", TOKEN])
+            .env("TIMEKEEPER_DB", dir.join("database.sqlite"))
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // logs go to stdout as well, so look for the line with the address
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let addr = stdout
+            .lines()
+            .map(|line| line.unwrap())
+            .find_map(|line| {
+                line.strip_prefix("Listening on http://")
+                    .map(str::to_string)
+            })
+            .expect("the server prints its address");
+        Self { child, addr, dir }
+    }
+
+    /// Sends a request, returning the status code and body
+    fn request(&self, method: &str, path: &str, token: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nAuthorization: Bearer {token}\r\n\
+            Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.addr,
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+        let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+        (status, body)
+    }
+
+    fn json(&self, method: &str, path: &str, body: &str) -> serde_json::Value {
+        let (status, body) = self.request(method, path, TOKEN, body);
+        assert_eq!(status, 200, "{method} {path}: {body}");
+        serde_json::from_str(&body).unwrap()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn requests_need_the_token() {
+    let server = Server::start("token");
+    assert_eq!(server.request("GET", "/status", "wrong", "").0, 401);
+    assert_eq!(server.request("GET", "/nothing", TOKEN, "").0, 404);
+}
+
+#[test]
+fn starts_and_stops_blocks() {
+    let server = Server::start("blocks");
+    let status = server.json("GET", "/status", "");
+    assert!(status["running"].is_null());
+    assert_eq!(server.json("GET", "/tags", ""), serde_json::json!([]));
+
+    let (code, _) = server.request("POST", "/start", TOKEN, r#"{"tag": "Nothing"}"#);
+    assert_eq!(code, 404);
+    let status = server.json("POST", "/start", "");
+    assert_eq!(status["running"]["running"], true);
+    let (code, _) = server.request("POST", "/start", TOKEN, "");
+    assert_eq!(code, 409);
+
+    let status = server.json("POST", "/stop", "");
+    assert!(status["running"].is_null());
+    let blocks = server.json("GET", "/blocks", "");
+    assert_eq!(blocks.as_array().unwrap().len(), 1);
+    let (code, _) = server.request("GET", "/blocks?from=yesterday", TOKEN, "");
+    assert_eq!(code, 400);
+}